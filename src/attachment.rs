@@ -1,10 +1,14 @@
 //! The `attachment` module provides types for working with CouchDB document
 //! attachments.
 
-use {Error, base64, serde, std};
+use {Error, base64, flate2, md5, serde, std};
 use mime::Mime;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 /// `Attachment` is a state-aware representation of a CouchDB document
 /// attachment.
@@ -29,6 +33,16 @@ use std::str::FromStr;
 /// * When serialized to JSON, a client-originating `Attachment` instance emits
 ///   a non-stub object that uses base64-encoding to encapsulate its content.
 ///
+/// * A client-originating `Attachment` instance may instead stream its content
+///   from a file or reader (e.g., via the `Attachment::from_file` method). Such
+///   an instance must be uploaded via multipart-encoding or as a standalone
+///   attachment, and it fails to serialize to JSON.
+///
+/// * A client-originating `Attachment` instance may instead hold content that
+///   is already gzip-encoded (via the `Attachment::new_gzipped` method), which
+///   the CouchDB server stores as is, without compressing it again. Such an
+///   instance, too, fails to serialize to JSON.
+///
 /// * `Attachment` supports conversion into a stub, which is useful when either:
 ///
 ///     * Updating a document but not making changes to its existing
@@ -55,10 +69,8 @@ use std::str::FromStr;
 ///   serializes attachments into yet another form (via `"follows": true` within
 ///   the attachment object).
 ///
-/// # TODO
-///
-/// * Add a means for applications to construct server-originating attachments
-///   from multipart data.
+/// An application may obtain server-originating attachments from
+/// multipart-encoded data via the [`multipart`](multipart/index.html) module.
 ///
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Attachment {
@@ -74,17 +86,79 @@ enum Inner {
         encoding: Option<Encoding>,
         revpos: u64,
     },
-    ClientOrigin { content: Vec<u8> },
-    Follows { content_length: u64 },
+    ClientOrigin { content: ClientContent },
+    Follows {
+        content_length: u64,
+        encoding: Option<Encoding>,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ClientContent {
+    Bytes(Vec<u8>),
+    Encoded {
+        bytes: Vec<u8>,
+        encoding: Encoding,
+        length: u64,
+    },
+    File { path: PathBuf, length: u64 },
+    Reader(SharedReader),
+}
+
+// SharedReader is a one-shot reader shared among all clones of an attachment.
+// The first clone to read the content consumes it.
+#[derive(Clone)]
+struct SharedReader {
+    length: u64,
+    reader: Arc<Mutex<Option<Box<dyn Read + Send>>>>,
+}
+
+/// `ContentReader` reads an attachment's content.
+///
+/// An application obtains a `ContentReader` via the `Attachment::content_reader`
+/// method.
+///
+/// A `ContentReader` yields exactly `content_length` bytes. If the underlying
+/// file or reader ends sooner then reading fails with an
+/// `std::io::ErrorKind::UnexpectedEof` error.
+///
+pub struct ContentReader<'a> {
+    length: u64,
+    remaining: u64,
+    inner: ReaderInner<'a>,
+}
+
+enum ReaderInner<'a> {
+    Borrowed(&'a [u8]),
+    File(std::io::Take<std::fs::File>),
+    Boxed(std::io::Take<Box<dyn Read + Send>>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum Content {
-    WithBytes(Vec<u8>),
-    WithLength(u64),
+    Bytes(Vec<u8>),
+    EncodedBytes { bytes: Vec<u8>, length: u64 },
+    Length(u64),
 }
 
 /// `Digest` is a hashed sum of an attachment's content.
+///
+/// # Remarks
+///
+/// For an attachment the CouchDB server stores compressed, the server computes
+/// the digest from the compressed content, not the original content.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+///
+/// let digest = couchdb::Digest::compute_md5(b"Lorem ipsum dolor sit amet");
+/// assert_eq!(digest.to_string(), "md5-/qgPLbAD1OvEU2AjgUqohQ==");
+/// assert!(digest.verify(b"Lorem ipsum dolor sit amet"));
+/// assert!(!digest.verify(b"Lorem ipsum dolor sit amet!"));
+/// ```
+///
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Digest {
     #[doc(hidden)]
@@ -99,6 +173,15 @@ enum EncodingCodec {
     Other(String),
 }
 
+/// `Md5Digester` computes an MD5 digest incrementally.
+///
+/// `Md5Digester` implements `Write`, so an application may, for example, copy
+/// attachment content into it from a reader while also writing the content
+/// elsewhere.
+///
+#[derive(Clone)]
+pub struct Md5Digester(md5::Context);
+
 /// `Encoding` contains information about the compression the CouchDB server
 /// uses to store an attachment's content.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -107,6 +190,44 @@ pub struct Encoding {
     codec: EncodingCodec,
 }
 
+/// `Compression` emulates how the CouchDB server decides whether to compress
+/// attachment content it stores.
+///
+/// # Summary
+///
+/// * The CouchDB server gzip-encodes the content of an uploaded attachment if
+///   and only if the attachment's content type matches one of the server's
+///   `[attachments] compressible_types` and the server's `[attachments]
+///   compression_level` is nonzero.
+///
+/// * The server stores content uploaded already gzip-encoded (e.g., via an
+///   attachment constructed with `Attachment::new_gzipped`) as is, regardless
+///   of content type.
+///
+/// * `Compression::default()` matches the default configuration of CouchDB
+///   2.x—i.e., compressible types `text/*`, `application/javascript`,
+///   `application/json`, and `application/xml` at compression level 8.
+///
+/// # Example
+///
+/// ```rust
+/// extern crate couchdb;
+/// extern crate mime;
+///
+/// let compression = couchdb::attachment::Compression::default();
+/// assert!(compression.is_compressible(&mime::TEXT_PLAIN));
+/// assert!(!compression.is_compressible(&mime::IMAGE_PNG));
+///
+/// let compression = couchdb::attachment::Compression::new("text/*", 0);
+/// assert!(!compression.is_compressible(&mime::TEXT_PLAIN));
+/// ```
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Compression {
+    compressible_types: Vec<String>,
+    level: u32,
+}
+
 impl Attachment {
     /// Constructs a new attachment.
     ///
@@ -125,7 +246,115 @@ impl Attachment {
     pub fn new(content_type: Mime, content: Vec<u8>) -> Self {
         Attachment {
             content_type: content_type,
-            inner: Inner::ClientOrigin { content: content },
+            inner: Inner::ClientOrigin { content: ClientContent::Bytes(content) },
+        }
+    }
+
+    /// Constructs a new attachment from gzip-encoded content.
+    ///
+    /// The CouchDB server stores the content as is, without compressing it
+    /// again, and reports the attachment's encoding as gzip. This lets the
+    /// application compress content at its own expense—e.g., for content types
+    /// the server does not compress.
+    ///
+    /// The application must upload the newly constructed attachment via
+    /// multipart-encoding or as a standalone attachment with the HTTP header
+    /// `Content-Encoding: gzip`. The attachment cannot be serialized as JSON.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the content is not valid gzip.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// extern crate couchdb;
+    /// extern crate mime;
+    ///
+    /// let compression = couchdb::attachment::Compression::default();
+    /// let gzipped = compression.compress(b"Lorem ipsum dolor sit amet");
+    ///
+    /// let att = couchdb::Attachment::new_gzipped(mime::TEXT_PLAIN, gzipped).unwrap();
+    /// assert_eq!(att.content_length(), 26);
+    /// assert!(att.encoding().unwrap().is_gzip());
+    /// assert_eq!(
+    ///     att.decoded_content().unwrap().unwrap().as_ref(),
+    ///     b"Lorem ipsum dolor sit amet"
+    /// );
+    /// ```
+    ///
+    pub fn new_gzipped(content_type: Mime, content: Vec<u8>) -> Result<Self, Error> {
+        let length = std::io::copy(
+            &mut flate2::read::GzDecoder::new(content.as_slice()),
+            &mut std::io::sink(),
+        ).map_err(|e| Error::from(("Failed to decode gzip-encoded attachment content", e)))?;
+        Ok(Attachment {
+            content_type: content_type,
+            inner: Inner::ClientOrigin {
+                content: ClientContent::Encoded {
+                    encoding: Encoding {
+                        codec: EncodingCodec::Gzip,
+                        length: content.len() as u64,
+                    },
+                    bytes: content,
+                    length: length,
+                },
+            },
+        })
+    }
+
+    /// Constructs a new attachment whose content is the given file.
+    ///
+    /// The attachment holds the file's path, not its content, and determines
+    /// the content length from the file's current size. The file is opened
+    /// only when the content is read—e.g., via the `content_reader` method—and
+    /// must not change size in the meantime.
+    ///
+    /// Like any attachment that streams its content, the newly constructed
+    /// attachment cannot be serialized as JSON. Instead, the application must
+    /// upload it via multipart-encoding or as a standalone attachment.
+    ///
+    pub fn from_file<P: Into<PathBuf>>(content_type: Mime, path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let metadata = std::fs::metadata(&path).map_err(|e| {
+            Error::from((format!("Failed to obtain metadata for file {:?}", path), e))
+        })?;
+        Ok(Attachment {
+            content_type: content_type,
+            inner: Inner::ClientOrigin {
+                content: ClientContent::File {
+                    path: path,
+                    length: metadata.len(),
+                },
+            },
+        })
+    }
+
+    /// Constructs a new attachment whose content is read from the given
+    /// reader.
+    ///
+    /// The reader must yield at least `content_length` bytes, and the
+    /// attachment's content is the first `content_length` of them.
+    ///
+    /// The content can be read only once. Clones of the attachment share the
+    /// reader, so reading the content via one clone consumes it for all clones.
+    ///
+    /// Like any attachment that streams its content, the newly constructed
+    /// attachment cannot be serialized as JSON. Instead, the application must
+    /// upload it via multipart-encoding or as a standalone attachment.
+    ///
+    pub fn from_reader<R>(content_type: Mime, reader: R, content_length: u64) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        Attachment {
+            content_type: content_type,
+            inner: Inner::ClientOrigin {
+                content: ClientContent::Reader(SharedReader {
+                    length: content_length,
+                    reader: Arc::new(Mutex::new(Some(Box::new(reader)))),
+                }),
+            },
         }
     }
 
@@ -156,25 +385,161 @@ impl Attachment {
     ///
     /// Content is available if and only if:
     ///
-    /// * The attachment originates from the client, or,
+    /// * The attachment originates from the client and its content is in
+    ///   memory (i.e., it does not stream its content from a file or reader),
+    ///   or,
     /// * The attachment originates from the server and is not a stub.
     ///
     pub fn content(&self) -> Option<&[u8]> {
         match self.inner {
-            Inner::ServerOrigin { content: Content::WithBytes(ref bytes), .. } => Some(bytes),
-            Inner::ServerOrigin { content: Content::WithLength(_), .. } => None,
-            Inner::ClientOrigin { ref content } => Some(content),
+            Inner::ServerOrigin { content: Content::Bytes(ref bytes), .. } => Some(bytes),
+            Inner::ServerOrigin { content: Content::EncodedBytes { .. }, .. } => None,
+            Inner::ServerOrigin { content: Content::Length(_), .. } => None,
+            Inner::ClientOrigin { content: ClientContent::Bytes(ref bytes) } => Some(bytes),
+            Inner::ClientOrigin { .. } => None,
             Inner::Follows { .. } => None,
         }
     }
 
+    /// Opens the attachment's content for reading, if available.
+    ///
+    /// Unlike the `content` method, this method works for attachments that
+    /// stream their content from a file or reader, too. It returns `None` if
+    /// and only if the attachment has no content in unencoded form.
+    ///
+    /// The application may use this method together with the `content_type`
+    /// and `content_length` methods to upload the attachment as a standalone
+    /// attachment—i.e., via `PUT /{db}/{doc}/{attachment}`.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the attachment's content is in a file
+    /// that fails to open or if the content is from a reader that has already
+    /// been consumed.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// extern crate couchdb;
+    /// extern crate mime;
+    ///
+    /// use std::io::Read;
+    ///
+    /// let content = std::io::Cursor::new(b"Lorem ipsum dolor sit amet".to_vec());
+    /// let att = couchdb::Attachment::from_reader(mime::TEXT_PLAIN, content, 11);
+    ///
+    /// let mut reader = att.content_reader().unwrap().unwrap();
+    /// assert_eq!(reader.content_length(), 11);
+    ///
+    /// let mut got = String::new();
+    /// reader.read_to_string(&mut got).unwrap();
+    /// assert_eq!(got, "Lorem ipsum");
+    ///
+    /// // The reader is consumed.
+    /// assert!(att.content_reader().is_err());
+    /// ```
+    ///
+    pub fn content_reader(&self) -> Result<Option<ContentReader<'_>>, Error> {
+        let (length, inner) = match self.inner {
+            Inner::ServerOrigin { content: Content::Bytes(ref bytes), .. } |
+            Inner::ClientOrigin { content: ClientContent::Bytes(ref bytes) } => {
+                (bytes.len() as u64, ReaderInner::Borrowed(bytes))
+            }
+            Inner::ClientOrigin { content: ClientContent::File { ref path, length } } => {
+                let f = std::fs::File::open(path).map_err(|e| {
+                    Error::from((format!("Failed to open attachment file {:?}", path), e))
+                })?;
+                (length, ReaderInner::File(f.take(length)))
+            }
+            Inner::ClientOrigin { content: ClientContent::Reader(ref x) } => {
+                let reader = x.reader
+                    .lock()
+                    .map_err(|_| Error::bad_attachment("Attachment content reader is poisoned"))?
+                    .take()
+                    .ok_or(Error::bad_attachment("Attachment content has already been read"))?;
+                (x.length, ReaderInner::Boxed(reader.take(x.length)))
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(ContentReader {
+            length: length,
+            remaining: length,
+            inner: inner,
+        }))
+    }
+
+    /// Borrows the attachment's content in encoded form, if available.
+    ///
+    /// Encoded content is available if and only if either:
+    ///
+    /// * The attachment originates from the server and the server sent the
+    ///   content compressed—e.g., as a gzip-encoded multipart part, or,
+    /// * The attachment was constructed from gzip-encoded content via the
+    ///   `Attachment::new_gzipped` method.
+    ///
+    /// In such a case, the `content` method returns `None`, and the `encoding`
+    /// method describes the compression.
+    ///
+    pub fn encoded_content(&self) -> Option<&[u8]> {
+        match self.inner {
+            Inner::ServerOrigin { content: Content::EncodedBytes { ref bytes, .. }, .. } => Some(bytes),
+            Inner::ClientOrigin { content: ClientContent::Encoded { ref bytes, .. } } => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Obtains the attachment's content in unencoded form, if available,
+    /// decompressing it as needed.
+    ///
+    /// Unlike the `content` method, this method works for attachments having
+    /// gzip-encoded content (see the `encoded_content` method), too. Such
+    /// content is decompressed into a new buffer.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the encoded content fails to decompress,
+    /// uses a codec other than gzip, or decompresses to a size other than the
+    /// attachment's content length.
+    ///
+    pub fn decoded_content(&self) -> Result<Option<Cow<'_, [u8]>>, Error> {
+
+        if let Some(content) = self.content() {
+            return Ok(Some(Cow::Borrowed(content)));
+        }
+
+        let (bytes, encoding) = match (self.encoded_content(), self.encoding()) {
+            (Some(bytes), Some(encoding)) => (bytes, encoding),
+            _ => return Ok(None),
+        };
+
+        if !encoding.is_gzip() {
+            return Err(Error::bad_attachment("Attachment content has an unsupported encoding"));
+        }
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut decoded)
+            .map_err(|e| {
+                Error::from(("Failed to decode gzip-encoded attachment content", e))
+            })?;
+
+        if decoded.len() as u64 != self.content_length() {
+            return Err(Error::bad_attachment(
+                "Decoded attachment content does not match the attachment's length",
+            ));
+        }
+
+        Ok(Some(Cow::Owned(decoded)))
+    }
+
     /// Returns the size of the attachment's content, in bytes.
     pub fn content_length(&self) -> u64 {
         match self.inner {
-            Inner::ServerOrigin { content: Content::WithBytes(ref bytes), .. } => bytes.len() as u64,
-            Inner::ServerOrigin { content: Content::WithLength(length), .. } => length,
-            Inner::ClientOrigin { ref content } => content.len() as u64,
-            Inner::Follows { content_length } => content_length,
+            Inner::ServerOrigin { content: Content::Bytes(ref bytes), .. } => bytes.len() as u64,
+            Inner::ServerOrigin { content: Content::EncodedBytes { length, .. }, .. } => length,
+            Inner::ServerOrigin { content: Content::Length(length), .. } => length,
+            Inner::ClientOrigin { ref content } => content.len(),
+            Inner::Follows { content_length, .. } => content_length,
         }
     }
 
@@ -217,8 +582,8 @@ impl Attachment {
     /// multipart-encoding.
     ///
     /// The returned attachment loses all information about the attachment
-    /// except for its content type and content length. The intention is for the
-    /// application to:
+    /// except for its content type, content length, and—if the attachment has
+    /// encoded content—encoding. The intention is for the application to:
     ///
     /// 1. Serialize the attachment stub within an enclosed document, as JSON,
     /// and,
@@ -269,7 +634,10 @@ impl Attachment {
     pub fn to_multipart_stub(&self) -> Attachment {
         Attachment {
             content_type: self.content_type.clone(),
-            inner: Inner::Follows { content_length: self.content_length() },
+            inner: Inner::Follows {
+                content_length: self.content_length(),
+                encoding: self.encoded_content().and(self.encoding().cloned()),
+            },
         }
     }
 
@@ -290,8 +658,9 @@ impl Attachment {
     pub fn encoding(&self) -> Option<&Encoding> {
         match self.inner {
             Inner::ServerOrigin { ref encoding, .. } => encoding.as_ref().clone(),
+            Inner::ClientOrigin { content: ClientContent::Encoded { ref encoding, .. } } => Some(encoding),
             Inner::ClientOrigin { .. } => None,
-            Inner::Follows { .. } => None,
+            Inner::Follows { ref encoding, .. } => encoding.as_ref(),
         }
     }
 
@@ -304,6 +673,109 @@ impl Attachment {
             Inner::Follows { .. } => None,
         }
     }
+
+    /// Computes the MD5 digest of the attachment's content, if available.
+    ///
+    /// Unlike the `digest` method, this method works for attachments
+    /// originating from the client, too. For example, an application may
+    /// compare the result with the digest of an attachment on the server to
+    /// skip re-uploading unchanged content.
+    ///
+    /// **Note:** The CouchDB server computes the digest of an attachment it
+    /// stores compressed from the compressed content. Such a digest never
+    /// matches the digest of the original content.
+    ///
+    pub fn content_digest(&self) -> Option<Digest> {
+        self.content().map(Digest::compute_md5)
+    }
+
+    /// Checks whether the attachment's content matches its digest.
+    ///
+    /// The check is possible if and only if the attachment originates from the
+    /// server, has an MD5 digest, and either:
+    ///
+    /// * Has gzip-encoded content (see the `encoded_content` method), or,
+    /// * Has content and was not stored compressed on the server.
+    ///
+    /// Otherwise, this method returns `None`.
+    ///
+    /// **Note:** The CouchDB server reports an attachment's encoding only when
+    /// asked to—e.g., via the `att_encoding_info=true` query parameter. Without
+    /// encoding information, an attachment stored compressed appears
+    /// uncompressed and fails this check.
+    ///
+    pub fn digest_matches(&self) -> Option<bool> {
+        match self.inner {
+            Inner::ServerOrigin { ref digest, .. } if !digest.is_md5() => None,
+            Inner::ServerOrigin {
+                content: Content::Bytes(ref bytes),
+                ref digest,
+                encoding: None,
+                ..
+            } => Some(digest.verify(bytes)),
+            Inner::ServerOrigin {
+                content: Content::EncodedBytes { ref bytes, .. },
+                ref digest,
+                ..
+            } => Some(digest.verify(bytes)),
+            _ => None,
+        }
+    }
+
+    // Fills a server-originating stub with content received as a multipart
+    // part, checking the content's length against the stub.
+    pub(crate) fn with_multipart_content(self, bytes: Vec<u8>, content_encoding: Option<&str>) -> Result<Self, Error> {
+
+        let (length, digest, encoding, revpos) = match self.inner {
+            Inner::ServerOrigin {
+                content: Content::Length(length),
+                digest,
+                encoding,
+                revpos,
+            } => (length, digest, encoding, revpos),
+            _ => return Err(Error::bad_multipart("Attachment is not a server-origin stub")),
+        };
+
+        let (content, encoding) = match content_encoding {
+            None | Some("identity") => {
+                if bytes.len() as u64 != length {
+                    return Err(Error::bad_multipart("Attachment part length does not match stub"));
+                }
+                (Content::Bytes(bytes), encoding)
+            }
+            Some(codec) => {
+                let codec = EncodingCodec::from(String::from(codec));
+                let encoded_length = bytes.len() as u64;
+                match encoding {
+                    Some(ref x) if x.codec != codec || x.length != encoded_length => {
+                        return Err(Error::bad_multipart("Attachment part encoding does not match stub"))
+                    }
+                    _ => {}
+                }
+                let encoding = Encoding {
+                    codec: codec,
+                    length: encoded_length,
+                };
+                (
+                    Content::EncodedBytes {
+                        bytes: bytes,
+                        length: length,
+                    },
+                    Some(encoding),
+                )
+            }
+        };
+
+        Ok(Attachment {
+            content_type: self.content_type,
+            inner: Inner::ServerOrigin {
+                content: content,
+                digest: digest,
+                encoding: encoding,
+                revpos: revpos,
+            },
+        })
+    }
 }
 
 impl<'a> Deserialize<'a> for Attachment {
@@ -341,14 +813,14 @@ impl<'a> Deserialize<'a> for Attachment {
 
         let inner = if let Some(SerializableBase64(bytes)) = x.data {
             Inner::ServerOrigin {
-                content: Content::WithBytes(bytes),
+                content: Content::Bytes(bytes),
                 digest: x.digest,
                 encoding: encoding,
                 revpos: x.revpos,
             }
         } else if let Some(content_length) = x.length {
             Inner::ServerOrigin {
-                content: Content::WithLength(content_length),
+                content: Content::Length(content_length),
                 digest: x.digest,
                 encoding: encoding,
                 revpos: x.revpos,
@@ -385,6 +857,10 @@ impl Serialize for Attachment {
             follows: Option<bool>,
             #[serde(skip_serializing_if = "Option::is_none")]
             length: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            encoding: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            encoded_length: Option<u64>,
         }
 
         let mut x = T::default();
@@ -394,12 +870,29 @@ impl Serialize for Attachment {
             Inner::ServerOrigin { .. } => {
                 x.stub = Some(true);
             }
-            Inner::ClientOrigin { ref content } => {
-                x.data = Some(base64::encode(content));
+            Inner::ClientOrigin { content: ClientContent::Bytes(ref bytes) } => {
+                x.data = Some(base64::encode(bytes));
+            }
+            Inner::ClientOrigin { content: ClientContent::Encoded { .. } } => {
+                return Err(serde::ser::Error::custom(
+                    "Attachment has encoded content and cannot be serialized with base64-encoded content",
+                ));
             }
-            Inner::Follows { content_length } => {
+            Inner::ClientOrigin { .. } => {
+                return Err(serde::ser::Error::custom(
+                    "Attachment streams its content and cannot be serialized with base64-encoded content",
+                ));
+            }
+            Inner::Follows {
+                content_length,
+                ref encoding,
+            } => {
                 x.follows = Some(true);
                 x.length = Some(content_length);
+                if let Some(ref encoding) = *encoding {
+                    x.encoding = Some(String::from(encoding.codec()));
+                    x.encoded_length = Some(encoding.length);
+                }
             }
         };
 
@@ -457,9 +950,77 @@ impl Content {
     /// Returns a length-only copy of the content.
     pub fn to_length_only(&self) -> Content {
         match *self {
-            Content::WithBytes(ref bytes) => Content::WithLength(bytes.len() as u64),
-            Content::WithLength(length) => Content::WithLength(length),
+            Content::Bytes(ref bytes) => Content::Length(bytes.len() as u64),
+            Content::EncodedBytes { length, .. } => Content::Length(length),
+            Content::Length(length) => Content::Length(length),
+        }
+    }
+}
+
+impl ClientContent {
+    pub fn len(&self) -> u64 {
+        match *self {
+            ClientContent::Bytes(ref bytes) => bytes.len() as u64,
+            ClientContent::Encoded { length, .. } => length,
+            ClientContent::File { length, .. } => length,
+            ClientContent::Reader(ref x) => x.length,
+        }
+    }
+}
+
+impl PartialEq for SharedReader {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.reader, &other.reader)
+    }
+}
+
+impl Eq for SharedReader {}
+
+impl std::fmt::Debug for SharedReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("SharedReader")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl<'a> ContentReader<'a> {
+    /// Returns the size of the content, in bytes.
+    pub fn content_length(&self) -> u64 {
+        self.length
+    }
+}
+
+impl<'a> Read for ContentReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
         }
+
+        let n = match self.inner {
+            ReaderInner::Borrowed(ref mut x) => x.read(buf)?,
+            ReaderInner::File(ref mut x) => x.read(buf)?,
+            ReaderInner::Boxed(ref mut x) => x.read(buf)?,
+        };
+
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Attachment content is shorter than its content length",
+            ));
+        }
+
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> std::fmt::Debug for ContentReader<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.debug_struct("ContentReader")
+            .field("length", &self.length)
+            .finish()
     }
 }
 
@@ -483,6 +1044,81 @@ impl Digest {
             _ => false,
         }
     }
+
+    /// Computes the MD5 digest of the given content.
+    pub fn compute_md5(content: &[u8]) -> Self {
+        Digest::Md5 { value: Vec::from(md5::compute(content).as_ref()) }
+    }
+
+    /// Computes the MD5 digest of all content read from the given reader.
+    pub fn compute_md5_from_reader<R: std::io::Read>(mut reader: R) -> Result<Self, Error> {
+        let mut digester = Md5Digester::new();
+        std::io::copy(&mut reader, &mut digester).map_err(|e| {
+            Error::from(("Failed to read content for digest", e))
+        })?;
+        Ok(digester.finish())
+    }
+
+    /// Returns whether the digest matches the given content.
+    ///
+    /// Only MD5 digests are supported. This method returns `false` for digests
+    /// using any other algorithm.
+    ///
+    pub fn verify(&self, content: &[u8]) -> bool {
+        match *self {
+            Digest::Md5 { ref value } => md5::compute(content).as_ref() == value.as_slice(),
+            _ => false,
+        }
+    }
+}
+
+impl Md5Digester {
+    /// Constructs a new digester with no content.
+    pub fn new() -> Self {
+        Md5Digester(md5::Context::new())
+    }
+
+    /// Adds content to the digest.
+    pub fn update(&mut self, content: &[u8]) {
+        self.0.consume(content);
+    }
+
+    /// Computes the digest of all content added so far.
+    pub fn finish(self) -> Digest {
+        Digest::Md5 { value: Vec::from(self.0.compute().as_ref()) }
+    }
+}
+
+impl Default for Md5Digester {
+    fn default() -> Self {
+        Md5Digester::new()
+    }
+}
+
+impl std::fmt::Debug for Md5Digester {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str("Md5Digester")
+    }
+}
+
+impl std::io::Write for Md5Digester {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Digest::Md5 { ref value } => write!(f, "md5-{}", base64::encode(value)),
+            Digest::Other { ref name, ref value } => write!(f, "{}-{}", name, base64::encode(value)),
+        }
+    }
 }
 
 impl FromStr for Digest {
@@ -514,6 +1150,132 @@ impl Encoding {
     pub fn is_gzip(&self) -> bool {
         self.codec == EncodingCodec::Gzip
     }
+
+    /// Borrows the name of the compression codec—e.g., `"gzip"`.
+    ///
+    /// The name is suitable as the value of an HTTP `Content-Encoding` header.
+    ///
+    pub fn codec(&self) -> &str {
+        match self.codec {
+            EncodingCodec::Gzip => "gzip",
+            EncodingCodec::Other(ref s) => s,
+        }
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new(
+            "text/*, application/javascript, application/json, application/xml",
+            8,
+        )
+    }
+}
+
+impl Compression {
+    /// Constructs a new `Compression` from CouchDB server configuration.
+    ///
+    /// The `compressible_types` argument is the value of the server's
+    /// `[attachments] compressible_types` option—i.e., a comma-separated list
+    /// of MIME types, each of which may use `*` as a wildcard. The
+    /// `compression_level` argument is the value of the server's
+    /// `[attachments] compression_level` option, with zero meaning no
+    /// compression.
+    ///
+    pub fn new(compressible_types: &str, compression_level: u32) -> Self {
+        Compression {
+            compressible_types: compressible_types
+                .split(',')
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            level: std::cmp::min(compression_level, 9),
+        }
+    }
+
+    /// Returns the gzip compression level, from 0 (no compression) to 9.
+    pub fn compression_level(&self) -> u32 {
+        self.level
+    }
+
+    /// Returns whether the CouchDB server compresses uploaded content having
+    /// the given content type.
+    pub fn is_compressible(&self, content_type: &Mime) -> bool {
+
+        if self.level == 0 {
+            return false;
+        }
+
+        let essence = match content_type.suffix() {
+            None => format!("{}/{}", content_type.type_(), content_type.subtype()),
+            Some(suffix) => format!(
+                "{}/{}+{}",
+                content_type.type_(),
+                content_type.subtype(),
+                suffix
+            ),
+        };
+        let essence = essence.to_lowercase();
+
+        self.compressible_types.iter().any(
+            |pattern| wildcard_matches(pattern, &essence),
+        )
+    }
+
+    /// Gzip-encodes the given content at the configured compression level.
+    ///
+    /// The result is suitable for constructing an attachment via the
+    /// `Attachment::new_gzipped` method.
+    ///
+    pub fn compress(&self, content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(self.level));
+        encoder.write_all(content).unwrap(); // writing to a Vec cannot fail
+        encoder.finish().unwrap()
+    }
+
+    /// Estimates the size of the content the CouchDB server stores for an
+    /// attachment having the given content type and content, in bytes.
+    ///
+    /// If the server compresses the content, the result is the size of the
+    /// content gzip-encoded at the configured compression level. The server's
+    /// zlib implementation may produce slightly different output, so the
+    /// estimate may be off by a few bytes. Otherwise, the result is the size of
+    /// the content.
+    ///
+    pub fn stored_length(&self, content_type: &Mime, content: &[u8]) -> u64 {
+        if self.is_compressible(content_type) {
+            self.compress(content).len() as u64
+        } else {
+            content.len() as u64
+        }
+    }
+}
+
+// Matches a string against a pattern in which each `*` matches any sequence of
+// characters, as CouchDB does for compressible types.
+fn wildcard_matches(pattern: &str, s: &str) -> bool {
+
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap();
+    if !s.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &s[first.len()..];
+    let mut pieces = pieces.collect::<Vec<_>>();
+    let last = match pieces.pop() {
+        None => return rest.is_empty(),
+        Some(x) => x,
+    };
+
+    for piece in pieces {
+        match rest.find(piece) {
+            None => return false,
+            Some(i) => rest = &rest[i + piece.len()..],
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
 }
 
 impl From<String> for EncodingCodec {
@@ -545,10 +1307,108 @@ impl<'a> Deserialize<'a> for SerializableMime {
     }
 }
 
+#[cfg(feature = "proptest")]
+mod arbitrary {
+    use super::*;
+    use mime;
+    use proptest::arbitrary::Arbitrary;
+    use proptest::prelude::*;
+    use proptest::strategy::BoxedStrategy;
+
+    fn content_type() -> BoxedStrategy<Mime> {
+        prop_oneof![
+            Just(mime::APPLICATION_JSON),
+            Just(mime::APPLICATION_OCTET_STREAM),
+            Just(mime::IMAGE_GIF),
+            Just(mime::IMAGE_PNG),
+            Just(mime::TEXT_HTML),
+            Just(mime::TEXT_PLAIN),
+        ].boxed()
+    }
+
+    fn encoding() -> BoxedStrategy<Encoding> {
+        (
+            prop_oneof![
+                Just(EncodingCodec::Gzip),
+                "[a-z]+".prop_map(EncodingCodec::from),
+            ],
+            any::<u64>(),
+        ).prop_map(|(codec, length)| {
+                Encoding {
+                    codec: codec,
+                    length: length,
+                }
+            })
+            .boxed()
+    }
+
+    impl Arbitrary for Digest {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            prop_oneof![
+                any::<[u8; 16]>().prop_map(|x| Digest::Md5 { value: Vec::from(x.as_ref()) }),
+                (
+                    "[a-z0-9]+".prop_filter("must not be md5", |x| x != "md5"),
+                    any::<Vec<u8>>(),
+                ).prop_map(|(name, value)| {
+                        Digest::Other {
+                            name: name,
+                            value: value,
+                        }
+                    }),
+            ].boxed()
+        }
+    }
+
+    impl Arbitrary for Attachment {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            let server_origin = (
+                prop_oneof![
+                    any::<Vec<u8>>().prop_map(Content::Bytes),
+                    any::<u64>().prop_map(Content::Length),
+                ],
+                any::<Digest>(),
+                proptest::option::of(encoding()),
+                1u64..,
+            ).prop_map(|(content, digest, encoding, revpos)| {
+                    Inner::ServerOrigin {
+                        content: content,
+                        digest: digest,
+                        encoding: encoding,
+                        revpos: revpos,
+                    }
+                });
+
+            let client_origin = any::<Vec<u8>>().prop_map(|content| {
+                Inner::ClientOrigin { content: ClientContent::Bytes(content) }
+            });
+
+            let follows = (any::<u64>(), proptest::option::of(encoding())).prop_map(|(content_length, encoding)| {
+                Inner::Follows {
+                    content_length: content_length,
+                    encoding: encoding,
+                }
+            });
+
+            (content_type(), prop_oneof![server_origin, client_origin, follows])
+                .prop_map(|(content_type, inner)| {
+                    Attachment {
+                        content_type: content_type,
+                        inner: inner,
+                    }
+                })
+                .boxed()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use {mime, serde_json};
+    use {mime, serde_json, tempdir};
 
     #[test]
     fn attachment_deserializes_as_stub() {
@@ -564,7 +1424,7 @@ mod tests {
         let expected = Attachment {
             content_type: mime::TEXT_PLAIN,
             inner: Inner::ServerOrigin {
-                content: Content::WithLength(1872),
+                content: Content::Length(1872),
                 digest: Digest::Md5 {
                     value: Vec::from(
                         b"\x21\xdb\x38\xd6\
@@ -596,7 +1456,7 @@ mod tests {
         let expected = Attachment {
             content_type: mime::IMAGE_GIF,
             inner: Inner::ServerOrigin {
-                content: Content::WithBytes(Vec::from(
+                content: Content::Bytes(Vec::from(
                     b"\x47\x49\x46\x38\
                     \x39\x61\x01\x00\
                     \x01\x00\x80\x00\
@@ -644,7 +1504,7 @@ mod tests {
         let expected = Attachment {
             content_type: mime::TEXT_PLAIN,
             inner: Inner::ServerOrigin {
-                content: Content::WithLength(1872),
+                content: Content::Length(1872),
                 digest: Digest::Md5 {
                     value: Vec::from(
                         b"\x21\xdb\x38\xd6\
@@ -666,6 +1526,331 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn digest_computes_md5() {
+        let got = Digest::compute_md5(b"");
+        assert_eq!(got.to_string(), "md5-1B2M2Y8AsgTpgAmY7PhCfg==");
+    }
+
+    #[test]
+    fn digest_computes_md5_from_reader() {
+        let content = b"Lorem ipsum dolor sit amet";
+        let got = Digest::compute_md5_from_reader(content.as_ref()).unwrap();
+        assert_eq!(got, Digest::compute_md5(content));
+    }
+
+    #[test]
+    fn digest_verifies_content() {
+        let digest = Digest::from_str("md5-2JdGiI2i2VELZKnwMers1Q==").unwrap();
+        assert!(digest.verify(
+            &base64::decode("R0lGODlhAQABAIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAIBRAA7").unwrap(),
+        ));
+        assert!(!digest.verify(b"bad content"));
+
+        let digest = Digest::from_str("sha-2JdGiI2i2VELZKnwMers1Q==").unwrap();
+        assert!(!digest.verify(b"bad content"));
+    }
+
+    #[test]
+    fn md5_digester_computes_incrementally() {
+        let mut digester = Md5Digester::new();
+        digester.update(b"Lorem ipsum ");
+        digester.update(b"dolor sit amet");
+        assert_eq!(
+            digester.finish(),
+            Digest::compute_md5(b"Lorem ipsum dolor sit amet")
+        );
+    }
+
+    #[test]
+    fn attachment_digest_matches_identity_content() {
+
+        let source = r#"{
+            "content_type": "image/gif",
+            "data": "R0lGODlhAQABAIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAIBRAA7",
+            "digest": "md5-2JdGiI2i2VELZKnwMers1Q==",
+            "revpos": 2
+        }"#;
+        let att: Attachment = serde_json::from_str(source).unwrap();
+        assert_eq!(att.digest_matches(), Some(true));
+
+        let source = r#"{
+            "content_type": "image/gif",
+            "data": "R0lGODlhAQABAIAAAAAAAP///yH5BAEAAAAALAAAAAABAAEAAAIBRAA7",
+            "digest": "md5-Ids41vtv725jyrN7iUvMcQ==",
+            "revpos": 2
+        }"#;
+        let att: Attachment = serde_json::from_str(source).unwrap();
+        assert_eq!(att.digest_matches(), Some(false));
+    }
+
+    #[test]
+    fn attachment_digest_matches_encoded_content() {
+
+        let encoded = Vec::from(b"pretend this is gzip".as_ref());
+
+        let source = json!({
+            "content_type": "text/plain",
+            "digest": Digest::compute_md5(&encoded).to_string(),
+            "encoding": "gzip",
+            "encoded_length": encoded.len(),
+            "length": 1872,
+            "revpos": 4,
+            "follows": true,
+        });
+        let att: Attachment = serde_json::from_value(source).unwrap();
+        let att = att.with_multipart_content(encoded, Some("gzip")).unwrap();
+        assert_eq!(att.digest_matches(), Some(true));
+    }
+
+    #[test]
+    fn attachment_digest_matches_is_unknown_without_content() {
+
+        let source = r#"{
+            "content_type": "text/plain",
+            "digest": "md5-Ids41vtv725jyrN7iUvMcQ==",
+            "length": 1872,
+            "revpos": 4,
+            "stub": true
+        }"#;
+        let att: Attachment = serde_json::from_str(source).unwrap();
+        assert_eq!(att.digest_matches(), None);
+
+        let att = Attachment::new(mime::TEXT_PLAIN, Vec::from(b"".as_ref()));
+        assert_eq!(att.digest_matches(), None);
+        assert_eq!(
+            att.content_digest().unwrap().to_string(),
+            "md5-1B2M2Y8AsgTpgAmY7PhCfg=="
+        );
+    }
+
+    #[test]
+    fn file_attachment_reads_content_from_file() {
+
+        let dir = tempdir::TempDir::new("couchdb-test").unwrap();
+        let path = dir.path().join("alpha.txt");
+        std::fs::write(&path, b"Lorem ipsum dolor sit amet").unwrap();
+
+        let att = Attachment::from_file(mime::TEXT_PLAIN, &path).unwrap();
+        assert!(att.is_client_origin());
+        assert_eq!(att.content(), None);
+        assert_eq!(att.content_length(), 26);
+        assert_eq!(att.to_multipart_stub().content_length(), 26);
+
+        // Unlike a reader, a file may be read more than once.
+        for _ in 0..2 {
+            let mut got = Vec::new();
+            att.content_reader()
+                .unwrap()
+                .unwrap()
+                .read_to_end(&mut got)
+                .unwrap();
+            assert_eq!(got, b"Lorem ipsum dolor sit amet");
+        }
+    }
+
+    #[test]
+    fn file_attachment_fails_for_nonexistent_file() {
+        let dir = tempdir::TempDir::new("couchdb-test").unwrap();
+        match Attachment::from_file(mime::TEXT_PLAIN, dir.path().join("alpha.txt")) {
+            Err(Error::Io { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn reader_attachment_is_read_once() {
+
+        let content = std::io::Cursor::new(Vec::from(b"hello".as_ref()));
+        let att = Attachment::from_reader(mime::TEXT_PLAIN, content, 5);
+        let clone = att.clone();
+        assert_eq!(att, clone);
+
+        let mut got = Vec::new();
+        clone
+            .content_reader()
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut got)
+            .unwrap();
+        assert_eq!(got, b"hello");
+
+        match att.content_reader() {
+            Err(Error::BadAttachment { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn reader_attachment_fails_when_reader_is_short() {
+
+        let content = std::io::Cursor::new(Vec::from(b"hello".as_ref()));
+        let att = Attachment::from_reader(mime::TEXT_PLAIN, content, 11);
+
+        let mut got = Vec::new();
+        let e = att.content_reader()
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut got)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(got, b"hello");
+    }
+
+    #[test]
+    fn file_attachment_fails_when_file_shrinks() {
+
+        let dir = tempdir::TempDir::new("couchdb-test").unwrap();
+        let path = dir.path().join("alpha.txt");
+        std::fs::write(&path, b"hello, world").unwrap();
+        let att = Attachment::from_file(mime::TEXT_PLAIN, &path).unwrap();
+        std::fs::write(&path, b"hello").unwrap();
+
+        let mut got = Vec::new();
+        let e = att.content_reader()
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut got)
+            .unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(got, b"hello");
+    }
+
+    #[test]
+    fn streaming_attachment_fails_to_serialize() {
+        let content = std::io::Cursor::new(Vec::from(b"hello".as_ref()));
+        let att = Attachment::from_reader(mime::TEXT_PLAIN, content, 5);
+        serde_json::to_vec(&att).unwrap_err();
+
+        let expected = json!({
+            "content_type": "text/plain",
+            "follows": true,
+            "length": 5,
+        });
+        assert_eq!(serde_json::to_value(att.to_multipart_stub()).unwrap(), expected);
+    }
+
+    #[test]
+    fn stub_attachment_has_no_content_reader() {
+        let att = Attachment::new(mime::TEXT_PLAIN, Vec::new()).to_multipart_stub();
+        assert!(att.content_reader().unwrap().is_none());
+    }
+
+    #[test]
+    fn gzipped_attachment_decodes_content() {
+
+        let gzipped = Compression::default().compress(b"hello");
+        let att = Attachment::new_gzipped(mime::TEXT_PLAIN, gzipped.clone()).unwrap();
+        assert_eq!(att.content(), None);
+        assert!(att.content_reader().unwrap().is_none());
+        assert_eq!(att.encoded_content(), Some(gzipped.as_slice()));
+        assert_eq!(att.content_length(), 5);
+        assert_eq!(att.encoding().unwrap().length(), gzipped.len() as u64);
+        assert_eq!(att.encoding().unwrap().codec(), "gzip");
+        assert_eq!(att.decoded_content().unwrap().unwrap().as_ref(), b"hello");
+        serde_json::to_vec(&att).unwrap_err();
+
+        let expected = json!({
+            "content_type": "text/plain",
+            "follows": true,
+            "length": 5,
+            "encoding": "gzip",
+            "encoded_length": gzipped.len(),
+        });
+        assert_eq!(serde_json::to_value(att.to_multipart_stub()).unwrap(), expected);
+    }
+
+    #[test]
+    fn gzipped_attachment_rejects_non_gzip_content() {
+        match Attachment::new_gzipped(mime::TEXT_PLAIN, Vec::from(b"hello".as_ref())) {
+            Err(Error::Io { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn server_origin_attachment_decodes_encoded_content() {
+
+        let encoded = Compression::default().compress(b"hello");
+
+        let source = json!({
+            "content_type": "text/plain",
+            "digest": Digest::compute_md5(&encoded).to_string(),
+            "encoding": "gzip",
+            "encoded_length": encoded.len(),
+            "length": 5,
+            "revpos": 4,
+            "follows": true,
+        });
+        let att: Attachment = serde_json::from_value(source.clone()).unwrap();
+        let att = att.with_multipart_content(encoded.clone(), Some("gzip")).unwrap();
+        assert_eq!(att.decoded_content().unwrap().unwrap().as_ref(), b"hello");
+
+        // The stub's length must match the decoded content's length.
+        let mut source = source;
+        source["length"] = json!(6);
+        let att: Attachment = serde_json::from_value(source).unwrap();
+        let att = att.with_multipart_content(encoded, Some("gzip")).unwrap();
+        match att.decoded_content() {
+            Err(Error::BadAttachment { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn attachment_decoded_content_borrows_identity_content() {
+        let att = Attachment::new(mime::TEXT_PLAIN, Vec::from(b"hello".as_ref()));
+        match att.decoded_content().unwrap() {
+            Some(Cow::Borrowed(x)) => assert_eq!(x, b"hello"),
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        let att = att.to_multipart_stub();
+        assert_eq!(att.decoded_content().unwrap(), None);
+    }
+
+    #[test]
+    fn compression_matches_compressible_types() {
+
+        let c = Compression::default();
+        assert_eq!(c.compression_level(), 8);
+        assert!(c.is_compressible(&mime::TEXT_PLAIN));
+        assert!(c.is_compressible(&mime::TEXT_HTML_UTF_8));
+        assert!(c.is_compressible(&mime::APPLICATION_JSON));
+        assert!(c.is_compressible(&"Application/XML".parse().unwrap()));
+        assert!(!c.is_compressible(&mime::IMAGE_PNG));
+        assert!(!c.is_compressible(&"application/atom+xml".parse().unwrap()));
+        assert!(!c.is_compressible(&"application/jsonx".parse().unwrap()));
+
+        let c = Compression::new(" image/*,*+xml ,, ", 1);
+        assert!(c.is_compressible(&mime::IMAGE_PNG));
+        assert!(c.is_compressible(&"application/atom+xml".parse().unwrap()));
+        assert!(!c.is_compressible(&mime::TEXT_PLAIN));
+
+        let c = Compression::new("*", 0);
+        assert!(!c.is_compressible(&mime::TEXT_PLAIN));
+    }
+
+    #[test]
+    fn compression_estimates_stored_length() {
+        let content = vec![b'a'; 1000];
+        let c = Compression::default();
+        assert!(c.stored_length(&mime::TEXT_PLAIN, &content) < 100);
+        assert_eq!(c.stored_length(&mime::IMAGE_PNG, &content), 1000);
+    }
+
+    #[test]
+    fn wildcard_matching() {
+        assert!(wildcard_matches("text/plain", "text/plain"));
+        assert!(!wildcard_matches("text/plain", "text/plainx"));
+        assert!(wildcard_matches("text/*", "text/"));
+        assert!(wildcard_matches("*", ""));
+        assert!(wildcard_matches("a*b*c", "abbc"));
+        assert!(!wildcard_matches("a*bc*c", "abc"));
+        assert!(wildcard_matches("*/*xml", "application/xml"));
+        assert!(!wildcard_matches("*/*xml", "application/xmlx"));
+    }
+
     #[test]
     fn client_origin_attachment_serializes_with_content() {
 
@@ -691,7 +1876,7 @@ mod tests {
         let source = Attachment {
             content_type: mime::TEXT_PLAIN,
             inner: Inner::ServerOrigin {
-                content: Content::WithLength(1872),
+                content: Content::Length(1872),
                 digest: Digest::Md5 {
                     value: Vec::from(
                         b"\x21\xdb\x38\xd6\
@@ -719,4 +1904,65 @@ mod tests {
 
         assert_eq!(decoded, expected);
     }
+
+    #[cfg(feature = "proptest")]
+    mod round_trip {
+        use super::super::*;
+        use proptest::prelude::*;
+        use serde_json;
+
+        // Constructs the JSON object the CouchDB server would send for the
+        // given server-origin attachment.
+        fn server_json(att: &Attachment) -> serde_json::Value {
+            let mut x = json!({
+                "content_type": att.content_type().to_string(),
+                "digest": att.digest().unwrap().to_string(),
+                "revpos": att.revision_sequence().unwrap(),
+            });
+            match att.content() {
+                Some(bytes) => x["data"] = json!(base64::encode(bytes)),
+                None => {
+                    x["length"] = json!(att.content_length());
+                    x["stub"] = json!(true);
+                }
+            }
+            if let Some(encoding) = att.encoding() {
+                let codec = match encoding.codec {
+                    EncodingCodec::Gzip => "gzip",
+                    EncodingCodec::Other(ref s) => s,
+                };
+                x["encoding"] = json!(codec);
+                x["encoded_length"] = json!(encoding.length());
+            }
+            x
+        }
+
+        proptest! {
+            #[test]
+            fn digest_round_trips(x in any::<Digest>()) {
+                prop_assert_eq!(Digest::from_str(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn server_origin_attachment_round_trips(
+                x in any::<Attachment>().prop_filter("server origin", |x| x.is_server_origin())
+            ) {
+                let got: Attachment = serde_json::from_value(server_json(&x)).unwrap();
+                prop_assert_eq!(got, x);
+            }
+
+            #[test]
+            fn client_origin_attachment_round_trips_its_content(
+                x in any::<Attachment>().prop_filter("client origin", |x| x.is_client_origin())
+            ) {
+                let encoded = serde_json::to_value(&x).unwrap();
+                let data = base64::decode(encoded["data"].as_str().unwrap()).unwrap();
+                prop_assert_eq!(Some(data.as_slice()), x.content());
+                prop_assert_eq!(
+                    encoded["content_type"].as_str().unwrap(),
+                    x.content_type().to_string()
+                );
+            }
+        }
+    }
 }
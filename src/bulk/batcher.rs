@@ -0,0 +1,426 @@
+use {Error, serde, serde_json, std};
+use bulk::BulkDocsRequest;
+
+const BODY_PREFIX: &[u8] = b"{\"docs\":[";
+const BODY_SUFFIX: &[u8] = b"]}";
+const NEW_EDITS_SUFFIX: &[u8] = b"],\"new_edits\":false}";
+
+/// `Batcher` splits a sequence of documents into request bodies for `POST
+/// /{db}/_bulk_docs` that the server accepts.
+///
+/// # Summary
+///
+/// * `Batcher` is an iterator adapter. It serializes each document once and
+///   yields batches of documents together with their already-serialized
+///   request body.
+///
+/// * Each batch stays within a maximum request size and a maximum number of
+///   documents.
+///
+/// * `Batcher` reports a document that can never fit in a request—or that the
+///   server would reject as too large—as a `BatchError`, and it continues with
+///   the remaining documents. It yields such an error as soon as it reaches the
+///   document, before yielding the batch in progress.
+///
+/// # Remarks
+///
+/// The CouchDB server rejects a request body larger than its
+/// `[httpd] max_http_request_size` option and a document larger than its
+/// `[couchdb] max_document_size` option. `Batcher` measures a document's size
+/// as the length of its JSON, including any inline attachments. This is
+/// stricter than some server versions, which exclude attachments from the
+/// document size.
+///
+/// The default limits are those of a default CouchDB 3.x configuration, with
+/// at most 500 documents per batch.
+///
+/// `Batcher` sizes only the JSON it serializes. It does not account for
+/// attachments sent outside the JSON—e.g., as parts of a `multipart/related`
+/// request—so an application sending such attachments must budget for them
+/// itself, such as by lowering the maximum request size.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// #[macro_use]
+/// extern crate serde_json;
+///
+/// # fn main() {
+/// let docs = vec![
+///     json!({"_id": "alpha"}),
+///     json!({"_id": "bravo", "big": "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx"}),
+///     json!({"_id": "charlie"}),
+/// ];
+///
+/// let mut batcher = couchdb::bulk::Batcher::new(docs.into_iter())
+///     .with_max_document_size(40)
+///     .with_max_count(10);
+///
+/// let error = batcher.next().unwrap().unwrap_err();
+/// assert_eq!(error.doc()["_id"], "bravo");
+///
+/// let batch = batcher.next().unwrap().unwrap();
+/// assert_eq!(batch.body(), br#"{"docs":[{"_id":"alpha"},{"_id":"charlie"}]}"#);
+///
+/// assert!(batcher.next().is_none());
+/// # }
+/// ```
+///
+#[derive(Debug)]
+pub struct Batcher<I: Iterator> {
+    docs: I,
+    max_request_size: u64,
+    max_document_size: u64,
+    max_count: usize,
+    new_edits: bool,
+    batch: Vec<I::Item>,
+    body: Vec<u8>,
+    pending: Option<(I::Item, Vec<u8>)>,
+}
+
+/// `Batch` is a batch of documents that a [`Batcher`](struct.Batcher.html)
+/// yields, together with the request body containing them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch<T> {
+    docs: Vec<T>,
+    body: Vec<u8>,
+    new_edits: bool,
+}
+
+/// `BatchError` is a document that a [`Batcher`](struct.Batcher.html) could not
+/// add to any batch.
+#[derive(Debug)]
+pub enum BatchError<T> {
+    /// The document is larger than the maximum document size.
+    DocumentTooLarge { doc: T, size: u64 },
+
+    /// A request body containing only the document is larger than the maximum
+    /// request size.
+    RequestTooLarge { doc: T, size: u64 },
+
+    /// The document failed to serialize as JSON.
+    Unserializable { doc: T, cause: Error },
+}
+
+impl<I> Batcher<I>
+where
+    I: Iterator,
+    I::Item: serde::Serialize,
+{
+    /// Constructs a new `Batcher` with the default limits.
+    pub fn new(docs: I) -> Self {
+        Batcher {
+            docs: docs,
+            max_request_size: 4_294_967_296,
+            max_document_size: 8_000_000,
+            max_count: 500,
+            new_edits: true,
+            batch: Vec::new(),
+            body: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// Sets the maximum size, in bytes, of each request body.
+    ///
+    /// This should be no greater than the server's `max_http_request_size`
+    /// option.
+    ///
+    pub fn with_max_request_size(mut self, max_request_size: u64) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    /// Sets the maximum size, in bytes, of each document.
+    ///
+    /// This should be no greater than the server's `max_document_size` option.
+    ///
+    pub fn with_max_document_size(mut self, max_document_size: u64) -> Self {
+        self.max_document_size = max_document_size;
+        self
+    }
+
+    /// Sets the maximum number of documents in each batch.
+    ///
+    /// Each batch contains at least one document, even if this is zero.
+    ///
+    pub fn with_max_count(mut self, max_count: usize) -> Self {
+        self.max_count = max_count;
+        self
+    }
+
+    /// Sets whether the request bodies enable the `new_edits` option of a
+    /// `_bulk_docs` request.
+    pub fn with_new_edits(mut self, new_edits: bool) -> Self {
+        self.new_edits = new_edits;
+        self
+    }
+
+    fn suffix(&self) -> &'static [u8] {
+        if self.new_edits {
+            BODY_SUFFIX
+        } else {
+            NEW_EDITS_SUFFIX
+        }
+    }
+
+    fn take_batch(&mut self) -> Batch<I::Item> {
+        let mut body = std::mem::take(&mut self.body);
+        body.extend_from_slice(self.suffix());
+        Batch {
+            docs: std::mem::take(&mut self.batch),
+            body: body,
+            new_edits: self.new_edits,
+        }
+    }
+}
+
+impl<I> Iterator for Batcher<I>
+where
+    I: Iterator,
+    I::Item: serde::Serialize,
+{
+    type Item = Result<Batch<I::Item>, BatchError<I::Item>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (doc, bytes) = match self.pending.take() {
+                Some(x) => x,
+                None => {
+                    match self.docs.next() {
+                        None => break,
+                        Some(doc) => {
+                            match serde_json::to_vec(&doc) {
+                                Ok(bytes) => (doc, bytes),
+                                Err(e) => {
+                                    return Some(Err(BatchError::Unserializable {
+                                        doc: doc,
+                                        cause: Error::from(("Failed to serialize the document", e)),
+                                    }))
+                                }
+                            }
+                        }
+                    }
+                }
+            };
+
+            let size = bytes.len() as u64;
+            if size > self.max_document_size {
+                return Some(Err(BatchError::DocumentTooLarge {
+                    doc: doc,
+                    size: size,
+                }));
+            }
+
+            let overhead = (BODY_PREFIX.len() + self.suffix().len()) as u64;
+            if overhead + size > self.max_request_size {
+                return Some(Err(BatchError::RequestTooLarge {
+                    doc: doc,
+                    size: overhead + size,
+                }));
+            }
+
+            if !self.batch.is_empty() {
+                let batch_size = (self.body.len() + 1 + self.suffix().len()) as u64 + size;
+                if batch_size > self.max_request_size || self.batch.len() >= self.max_count {
+                    self.pending = Some((doc, bytes));
+                    return Some(Ok(self.take_batch()));
+                }
+                self.body.push(b',');
+            } else {
+                self.body.extend_from_slice(BODY_PREFIX);
+            }
+
+            self.body.extend_from_slice(&bytes);
+            self.batch.push(doc);
+        }
+
+        if self.batch.is_empty() {
+            None
+        } else {
+            Some(Ok(self.take_batch()))
+        }
+    }
+}
+
+impl<T> Batch<T> {
+    /// Borrows the batch's documents.
+    pub fn docs(&self) -> &[T] {
+        &self.docs
+    }
+
+    /// Borrows the request body, which is a JSON object.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Converts the batch into its documents and request body.
+    pub fn into_parts(self) -> (Vec<T>, Vec<u8>) {
+        (self.docs, self.body)
+    }
+
+    /// Converts the batch into a `BulkDocsRequest` for the same documents—e.g.,
+    /// to match the documents with the response rows.
+    pub fn into_request(self) -> BulkDocsRequest<T> {
+        BulkDocsRequest::new(self.docs).with_new_edits(self.new_edits)
+    }
+}
+
+impl<T> BatchError<T> {
+    /// Borrows the document that could not be batched.
+    pub fn doc(&self) -> &T {
+        match *self {
+            BatchError::DocumentTooLarge { ref doc, .. } |
+            BatchError::RequestTooLarge { ref doc, .. } |
+            BatchError::Unserializable { ref doc, .. } => doc,
+        }
+    }
+
+    /// Converts the error into the document that could not be batched.
+    pub fn into_doc(self) -> T {
+        match self {
+            BatchError::DocumentTooLarge { doc, .. } |
+            BatchError::RequestTooLarge { doc, .. } |
+            BatchError::Unserializable { doc, .. } => doc,
+        }
+    }
+}
+
+impl<T> std::fmt::Display for BatchError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            BatchError::DocumentTooLarge { size, .. } => {
+                write!(f, "The document is too large ({} bytes)", size)
+            }
+            BatchError::RequestTooLarge { size, .. } => {
+                write!(f, "The document does not fit in a request ({} bytes)", size)
+            }
+            BatchError::Unserializable { ref cause, .. } => cause.fmt(f),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for BatchError<T> {
+    fn description(&self) -> &str {
+        match *self {
+            BatchError::DocumentTooLarge { .. } => "The document is too large",
+            BatchError::RequestTooLarge { .. } => "The document does not fit in a request",
+            BatchError::Unserializable { .. } => "The document failed to serialize",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Serialize, Serializer};
+
+    fn collect<I>(batcher: Batcher<I>) -> Vec<Result<Vec<u8>, String>>
+    where
+        I: Iterator,
+        I::Item: serde::Serialize,
+    {
+        batcher
+            .map(|x| match x {
+                Ok(batch) => Ok(batch.into_parts().1),
+                Err(e) => Err(e.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batcher_yields_nothing_for_no_documents() {
+        let docs: Vec<serde_json::Value> = Vec::new();
+        assert!(Batcher::new(docs.into_iter()).next().is_none());
+    }
+
+    #[test]
+    fn batcher_limits_count() {
+        let docs = (0..5).map(|x| json!(x)).collect::<Vec<_>>();
+        let got = collect(Batcher::new(docs.into_iter()).with_max_count(2));
+        assert_eq!(
+            got,
+            vec![
+                Ok(b"{\"docs\":[0,1]}".to_vec()),
+                Ok(b"{\"docs\":[2,3]}".to_vec()),
+                Ok(b"{\"docs\":[4]}".to_vec()),
+            ]
+        );
+
+        let docs = (0..2).map(|x| json!(x)).collect::<Vec<_>>();
+        let got = collect(Batcher::new(docs.into_iter()).with_max_count(0));
+        assert_eq!(got.len(), 2);
+    }
+
+    #[test]
+    fn batcher_limits_request_size() {
+        // `{"docs":[1,22]}` is exactly 15 bytes.
+        let docs = vec![json!(1), json!(22), json!(333)];
+        let got = collect(Batcher::new(docs.into_iter()).with_max_request_size(15));
+        assert_eq!(
+            got,
+            vec![
+                Ok(b"{\"docs\":[1,22]}".to_vec()),
+                Ok(b"{\"docs\":[333]}".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn batcher_reports_oversized_documents() {
+        let docs = vec![json!(1), json!("xxxxxxxxxx"), json!(22), json!(123456789)];
+        let mut batcher = Batcher::new(docs.into_iter())
+            .with_max_document_size(9)
+            .with_max_request_size(19);
+
+        match batcher.next() {
+            Some(Err(BatchError::DocumentTooLarge { ref doc, size: 12 })) if doc == "xxxxxxxxxx" => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        match batcher.next() {
+            Some(Err(BatchError::RequestTooLarge { ref doc, size: 20 })) if doc == 123456789 => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        let batch = batcher.next().unwrap().unwrap();
+        assert_eq!(batch.docs(), &[json!(1), json!(22)]);
+        assert_eq!(batch.body(), b"{\"docs\":[1,22]}");
+        assert!(batcher.next().is_none());
+    }
+
+    #[test]
+    fn batcher_reports_unserializable_documents() {
+        struct Bad;
+
+        impl Serialize for Bad {
+            fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("bad document"))
+            }
+        }
+
+        let mut batcher = Batcher::new(vec![Bad].into_iter());
+        match batcher.next() {
+            Some(Err(BatchError::Unserializable { .. })) => {}
+            Some(Err(x)) => panic!("Got unexpected error {}", x),
+            _ => panic!("Got unexpected result"),
+        }
+        assert!(batcher.next().is_none());
+    }
+
+    #[test]
+    fn batcher_disables_new_edits() {
+        let docs = vec![json!({"_id": "alpha", "_rev": "1-967a00dff5e02add41819138abb3284d"})];
+        let batch = Batcher::new(docs.clone().into_iter())
+            .with_new_edits(false)
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(batch.body()).unwrap(),
+            json!({"docs": docs, "new_edits": false})
+        );
+        let request = batch.into_request();
+        assert!(!request.new_edits());
+        assert_eq!(request.docs(), &docs[..]);
+    }
+}
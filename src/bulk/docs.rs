@@ -0,0 +1,517 @@
+use {DocumentId, Error, Revision, serde, serde_json, std};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::marker::PhantomData;
+
+/// `BulkDocsRequest` is the body of a `POST /{db}/_bulk_docs` request.
+///
+/// # Summary
+///
+/// * `BulkDocsRequest` implements `Serialize`, producing a JSON object with a
+///   `docs` array and, if not the default, the `new_edits` and
+///   `all_or_nothing` options.
+///
+/// * `BulkDocsRequest` zips the response rows back to its documents, updates
+///   each written document's revision, and sorts the documents into written,
+///   conflicting, rejected, and failed.
+///
+/// # Remarks
+///
+/// With `new_edits` disabled—e.g., when replicating—the server writes each
+/// document with the revision it already has and reports only the documents it
+/// fails to write. Thus the response rows do not line up with the request's
+/// documents, and the zipping methods return an error.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// #[macro_use]
+/// extern crate serde_json;
+///
+/// # fn main() {
+/// let mut request = couchdb::bulk::BulkDocsRequest::new(vec![
+///     json!({"_id": "alpha", "value": 1}),
+///     json!({"_id": "bravo", "value": 2}),
+/// ]);
+///
+/// assert_eq!(
+///     serde_json::to_value(&request).unwrap(),
+///     json!({"docs": [{"_id": "alpha", "value": 1}, {"_id": "bravo", "value": 2}]})
+/// );
+///
+/// let response = br#"[
+///     {"ok": true, "id": "alpha", "rev": "1-967a00dff5e02add41819138abb3284d"},
+///     {"id": "bravo", "error": "conflict", "reason": "Document update conflict."}
+/// ]"#;
+///
+/// let results: Vec<couchdb::bulk::BulkDocsResult> = serde_json::from_slice(response).unwrap();
+///
+/// request.update_revisions(&results).unwrap();
+/// assert_eq!(request.docs()[0]["_rev"], "1-967a00dff5e02add41819138abb3284d");
+///
+/// let outcome = request.into_outcome(results).unwrap();
+/// assert_eq!(outcome.written.len(), 1);
+/// assert_eq!(outcome.conflicts.len(), 1);
+/// # }
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct BulkDocsRequest<T> {
+    docs: Vec<T>,
+    new_edits: bool,
+    all_or_nothing: bool,
+}
+
+/// `BulkDocsResult` is one row of the response to a `POST /{db}/_bulk_docs`
+/// request.
+///
+/// # Summary
+///
+/// * `BulkDocsResult` implements `Deserialize`.
+///
+/// * Each row either reports the document's new revision (`Ok`) or why the
+///   server did not write the document (`Err`).
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum BulkDocsResult {
+    /// The server wrote the document, thereby creating the given revision.
+    Ok { id: DocumentId, rev: Revision },
+
+    /// The server did not write the document.
+    Err {
+        id: DocumentId,
+        error: BulkDocsErrorKind,
+        reason: String,
+    },
+}
+
+/// `BulkDocsErrorKind` is the `error` string of a `_bulk_docs` response row
+/// for a document the server did not write.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum BulkDocsErrorKind {
+    /// The document's revision is not the current revision (`conflict`).
+    Conflict,
+
+    /// A design document's validation function rejected the document
+    /// (`forbidden`).
+    Forbidden,
+
+    /// A design document's validation function rejected the document owing to
+    /// the user's credentials (`unauthorized`).
+    Unauthorized,
+
+    /// Some other error, such as `not_found` or `missing_stub`.
+    Other(String),
+}
+
+/// `BulkDocsOutcome` contains the documents of a `_bulk_docs` request, sorted
+/// according to what the server did with them.
+///
+/// # Compatibility
+///
+/// `BulkDocsOutcome` contains a dummy private member in order to prevent
+/// applications from directly constructing a `BulkDocsOutcome` instance. This
+/// allows new fields to be added to `BulkDocsOutcome` in future releases
+/// without it being a breaking change.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct BulkDocsOutcome<T> {
+    /// The documents the server wrote, each with its new revision.
+    pub written: Vec<(T, Revision)>,
+
+    /// The documents the server did not write owing to a conflict.
+    pub conflicts: Vec<T>,
+
+    /// The documents a validation function rejected, each with the reason.
+    pub rejected: Vec<(T, String)>,
+
+    /// The documents the server did not write for some other reason.
+    pub failed: Vec<(T, BulkDocsErrorKind, String)>,
+
+    _private_guard: PhantomData<()>,
+}
+
+impl<T> BulkDocsRequest<T> {
+    /// Constructs a new request to write the given documents, with the
+    /// default options.
+    pub fn new(docs: Vec<T>) -> Self {
+        BulkDocsRequest {
+            docs: docs,
+            new_edits: true,
+            all_or_nothing: false,
+        }
+    }
+
+    /// Sets whether the server assigns new revisions (`new_edits`).
+    ///
+    /// By default, the server assigns new revisions. Disable this to write
+    /// documents with the revisions they already have—e.g., when replicating.
+    ///
+    pub fn with_new_edits(mut self, new_edits: bool) -> Self {
+        self.new_edits = new_edits;
+        self
+    }
+
+    /// Sets whether the server writes the documents without checking for
+    /// conflicts (`all_or_nothing`).
+    ///
+    /// CouchDB 2.x and later ignore this option.
+    ///
+    pub fn with_all_or_nothing(mut self, all_or_nothing: bool) -> Self {
+        self.all_or_nothing = all_or_nothing;
+        self
+    }
+
+    /// Borrows the request's documents.
+    pub fn docs(&self) -> &[T] {
+        &self.docs
+    }
+
+    /// Mutably borrows the request's documents.
+    pub fn docs_mut(&mut self) -> &mut [T] {
+        &mut self.docs
+    }
+
+    /// Converts the request into its documents.
+    pub fn into_docs(self) -> Vec<T> {
+        self.docs
+    }
+
+    /// Returns whether the server assigns new revisions.
+    pub fn new_edits(&self) -> bool {
+        self.new_edits
+    }
+
+    /// Returns whether the server writes the documents without checking for
+    /// conflicts.
+    pub fn all_or_nothing(&self) -> bool {
+        self.all_or_nothing
+    }
+
+    /// Pairs each of the request's documents with its response row.
+    ///
+    /// Returns an error if the number of rows differs from the number of
+    /// documents.
+    ///
+    pub fn zip<'a>(&'a self, results: &'a [BulkDocsResult]) -> Result<Vec<(&'a T, &'a BulkDocsResult)>, Error> {
+        check_row_count(self.docs.len(), results.len())?;
+        Ok(self.docs.iter().zip(results).collect())
+    }
+
+    /// Calls the given function for each document the server wrote, passing
+    /// the document and its new revision.
+    ///
+    /// Returns an error if the number of rows differs from the number of
+    /// documents, in which case no document is updated.
+    ///
+    pub fn update_revisions_with<F>(&mut self, results: &[BulkDocsResult], mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut T, &Revision),
+    {
+        check_row_count(self.docs.len(), results.len())?;
+        for (doc, result) in self.docs.iter_mut().zip(results) {
+            if let BulkDocsResult::Ok { ref rev, .. } = *result {
+                f(doc, rev);
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts the request into its documents, sorted according to the
+    /// response rows.
+    ///
+    /// Returns an error if the number of rows differs from the number of
+    /// documents.
+    ///
+    pub fn into_outcome(self, results: Vec<BulkDocsResult>) -> Result<BulkDocsOutcome<T>, Error> {
+
+        check_row_count(self.docs.len(), results.len())?;
+
+        let mut outcome = BulkDocsOutcome {
+            written: Vec::new(),
+            conflicts: Vec::new(),
+            rejected: Vec::new(),
+            failed: Vec::new(),
+            _private_guard: PhantomData,
+        };
+
+        for (doc, result) in self.docs.into_iter().zip(results) {
+            match result {
+                BulkDocsResult::Ok { rev, .. } => outcome.written.push((doc, rev)),
+                BulkDocsResult::Err { error: BulkDocsErrorKind::Conflict, .. } => outcome.conflicts.push(doc),
+                BulkDocsResult::Err {
+                    error: BulkDocsErrorKind::Forbidden,
+                    reason,
+                    ..
+                } |
+                BulkDocsResult::Err {
+                    error: BulkDocsErrorKind::Unauthorized,
+                    reason,
+                    ..
+                } => outcome.rejected.push((doc, reason)),
+                BulkDocsResult::Err { error, reason, .. } => outcome.failed.push((doc, error, reason)),
+            }
+        }
+
+        Ok(outcome)
+    }
+}
+
+impl BulkDocsRequest<serde_json::Value> {
+    /// Sets the `_rev` field of each document the server wrote to the
+    /// document's new revision.
+    ///
+    /// Returns an error if the number of rows differs from the number of
+    /// documents, in which case no document is updated.
+    ///
+    pub fn update_revisions(&mut self, results: &[BulkDocsResult]) -> Result<(), Error> {
+        self.update_revisions_with(results, |doc, rev| if let Some(x) = doc.as_object_mut() {
+            x.insert(String::from("_rev"), serde_json::Value::String(rev.to_string()));
+        })
+    }
+}
+
+fn check_row_count(docs: usize, results: usize) -> Result<(), Error> {
+    if docs != results {
+        return Err(Error::bad_response(
+            "The number of _bulk_docs results does not match the number of documents",
+        ));
+    }
+    Ok(())
+}
+
+impl<T: Serialize> Serialize for BulkDocsRequest<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut len = 1;
+        if !self.new_edits {
+            len += 1;
+        }
+        if self.all_or_nothing {
+            len += 1;
+        }
+
+        let mut s = serializer.serialize_struct("BulkDocsRequest", len)?;
+        if self.all_or_nothing {
+            s.serialize_field("all_or_nothing", &true)?;
+        }
+        s.serialize_field("docs", &self.docs)?;
+        if !self.new_edits {
+            s.serialize_field("new_edits", &false)?;
+        }
+        s.end()
+    }
+}
+
+impl BulkDocsResult {
+    /// Borrows the document id of the row.
+    pub fn id(&self) -> &DocumentId {
+        match *self {
+            BulkDocsResult::Ok { ref id, .. } |
+            BulkDocsResult::Err { ref id, .. } => id,
+        }
+    }
+
+    /// Returns whether the server wrote the document.
+    pub fn is_ok(&self) -> bool {
+        match *self {
+            BulkDocsResult::Ok { .. } => true,
+            BulkDocsResult::Err { .. } => false,
+        }
+    }
+
+    /// Borrows the document's new revision, if the server wrote the document.
+    pub fn revision(&self) -> Option<&Revision> {
+        match *self {
+            BulkDocsResult::Ok { ref rev, .. } => Some(rev),
+            BulkDocsResult::Err { .. } => None,
+        }
+    }
+
+    /// Borrows the error kind, if the server did not write the document.
+    pub fn error_kind(&self) -> Option<&BulkDocsErrorKind> {
+        match *self {
+            BulkDocsResult::Ok { .. } => None,
+            BulkDocsResult::Err { ref error, .. } => Some(error),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BulkDocsResult {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Row {
+            id: DocumentId,
+            rev: Option<Revision>,
+            error: Option<String>,
+            reason: Option<String>,
+        }
+
+        let row = Row::deserialize(deserializer)?;
+
+        match (row.error, row.rev) {
+            (Some(error), _) => Ok(BulkDocsResult::Err {
+                id: row.id,
+                error: BulkDocsErrorKind::from(error),
+                reason: row.reason.unwrap_or_default(),
+            }),
+            (None, Some(rev)) => Ok(BulkDocsResult::Ok {
+                id: row.id,
+                rev: rev,
+            }),
+            (None, None) => Err(serde::de::Error::missing_field("rev")),
+        }
+    }
+}
+
+impl BulkDocsErrorKind {
+    /// Borrows the error string as the server sends it—e.g., `conflict`.
+    pub fn as_str(&self) -> &str {
+        match *self {
+            BulkDocsErrorKind::Conflict => "conflict",
+            BulkDocsErrorKind::Forbidden => "forbidden",
+            BulkDocsErrorKind::Unauthorized => "unauthorized",
+            BulkDocsErrorKind::Other(ref x) => x,
+        }
+    }
+}
+
+impl std::fmt::Display for BulkDocsErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<String> for BulkDocsErrorKind {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "conflict" => BulkDocsErrorKind::Conflict,
+            "forbidden" => BulkDocsErrorKind::Forbidden,
+            "unauthorized" => BulkDocsErrorKind::Unauthorized,
+            _ => BulkDocsErrorKind::Other(s),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for BulkDocsErrorKind {
+    fn from(s: &'a str) -> Self {
+        BulkDocsErrorKind::from(String::from(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Vec<BulkDocsResult> {
+        serde_json::from_str(
+            r#"[
+                {"ok": true, "id": "alpha", "rev": "1-967a00dff5e02add41819138abb3284d"},
+                {"id": "bravo", "error": "conflict", "reason": "Document update conflict."},
+                {"id": "charlie", "error": "forbidden", "reason": "Missing field."},
+                {"id": "delta", "error": "unauthorized", "reason": "Not an admin."},
+                {"id": "echo", "error": "not_found", "reason": "missing"}
+            ]"#,
+        ).unwrap()
+    }
+
+    fn request() -> BulkDocsRequest<serde_json::Value> {
+        BulkDocsRequest::new(vec![
+            json!({"_id": "alpha"}),
+            json!({"_id": "bravo", "_rev": "1-7051cbe5c8faecd085a3fa619e6e6337"}),
+            json!({"_id": "charlie"}),
+            json!({"_id": "delta"}),
+            json!({"_id": "echo"}),
+        ])
+    }
+
+    #[test]
+    fn request_serializes_options() {
+        let docs = vec![json!({"_id": "alpha"})];
+        assert_eq!(
+            serde_json::to_string(&BulkDocsRequest::new(docs.clone())).unwrap(),
+            r#"{"docs":[{"_id":"alpha"}]}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&BulkDocsRequest::new(docs)
+                .with_new_edits(false)
+                .with_all_or_nothing(true)).unwrap(),
+            r#"{"all_or_nothing":true,"docs":[{"_id":"alpha"}],"new_edits":false}"#
+        );
+    }
+
+    #[test]
+    fn result_deserializes_ok() {
+        let got = results();
+        assert_eq!(
+            got[0],
+            BulkDocsResult::Ok {
+                id: DocumentId::from("alpha"),
+                rev: Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap(),
+            }
+        );
+        assert_eq!(
+            got[1],
+            BulkDocsResult::Err {
+                id: DocumentId::from("bravo"),
+                error: BulkDocsErrorKind::Conflict,
+                reason: String::from("Document update conflict."),
+            }
+        );
+        assert_eq!(got[4].error_kind(), Some(&BulkDocsErrorKind::Other(String::from("not_found"))));
+        assert_eq!(got[4].id(), &DocumentId::from("echo"));
+        assert!(got[0].is_ok());
+        assert!(!got[1].is_ok());
+
+        serde_json::from_str::<BulkDocsResult>(r#"{"id": "alpha"}"#).unwrap_err();
+    }
+
+    #[test]
+    fn zip_pairs_rows_with_documents() {
+        let request = request();
+        let results = results();
+        let got = request.zip(&results).unwrap();
+        assert_eq!(got.len(), 5);
+        assert_eq!(got[2].0["_id"], "charlie");
+        assert_eq!(got[2].1.id(), &DocumentId::from("charlie"));
+
+        match request.zip(&results[1..]) {
+            Err(Error::BadResponse { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn update_revisions_sets_rev_of_written_documents() {
+        let mut request = request();
+        request.update_revisions(&results()).unwrap();
+        assert_eq!(request.docs()[0]["_rev"], "1-967a00dff5e02add41819138abb3284d");
+        assert_eq!(request.docs()[1]["_rev"], "1-7051cbe5c8faecd085a3fa619e6e6337");
+        assert!(request.docs()[2].get("_rev").is_none());
+
+        let mut request = request.clone();
+        let before = request.clone();
+        request.update_revisions(&results()[1..]).unwrap_err();
+        assert_eq!(request, before);
+    }
+
+    #[test]
+    fn into_outcome_sorts_documents() {
+        let outcome = request().into_outcome(results()).unwrap();
+        assert_eq!(outcome.written.len(), 1);
+        assert_eq!(outcome.written[0].0["_id"], "alpha");
+        assert_eq!(outcome.conflicts.len(), 1);
+        assert_eq!(outcome.conflicts[0]["_id"], "bravo");
+        assert_eq!(outcome.rejected.len(), 2);
+        assert_eq!(outcome.rejected[0].1, "Missing field.");
+        assert_eq!(outcome.failed.len(), 1);
+        assert_eq!(outcome.failed[0].1.as_str(), "not_found");
+    }
+}
@@ -0,0 +1,451 @@
+use {DocumentId, Revision, Root};
+use serde::{Deserialize, Deserializer};
+use serde::de::{Error as DeError, Unexpected};
+use std::marker::PhantomData;
+
+/// `BulkGetRequest` is the body of a `POST /{db}/_bulk_get` request.
+///
+/// # Summary
+///
+/// * `BulkGetRequest` implements `Serialize`, producing a JSON object with a
+///   `docs` array.
+///
+/// * Each `BulkGetItem` specifies a document and, optionally, a revision and
+///   the revisions whose attachments the application already has.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::bulk::{BulkGetItem, BulkGetRequest};
+///
+/// let rev = couchdb::Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap();
+/// let request = BulkGetRequest::new(vec![
+///     BulkGetItem::new("alpha").with_revision(rev),
+///     BulkGetItem::new("bravo"),
+/// ]);
+///
+/// assert_eq!(
+///     serde_json::to_string(&request).unwrap(),
+///     r#"{"docs":[{"id":"alpha","rev":"2-7051cbe5c8faecd085a3fa619e6e6337"},{"id":"bravo"}]}"#
+/// );
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize)]
+pub struct BulkGetRequest {
+    docs: Vec<BulkGetItem>,
+}
+
+/// `BulkGetItem` specifies one document of a `_bulk_get` request.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize)]
+pub struct BulkGetItem {
+    id: DocumentId,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rev: Option<Revision>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    atts_since: Vec<Revision>,
+}
+
+/// `BulkGetResponse` contains the JSON response to a `POST /{db}/_bulk_get`
+/// request.
+///
+/// # Summary
+///
+/// * `BulkGetResponse` has public members instead of accessor methods because
+///   there are no invariants restricting the data.
+///
+/// * `BulkGetResponse` implements `Deserialize`. The type parameter is the
+///   document type, which may use the crate's `Attachment` type for the
+///   document's `_attachments` field.
+///
+/// # Remarks
+///
+/// The response has one result per requested item. Each result lists a
+/// document revision—or an error—for each revision the item specifies. With
+/// the `revs=true` query parameter, each document includes its `_revisions`
+/// field, which the [`Revisions`](../struct.Revisions.html) type decodes.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// #[macro_use]
+/// extern crate serde_derive;
+/// extern crate serde_json;
+///
+/// use couchdb::bulk::{BulkGetDoc, BulkGetResponse};
+/// use std::collections::HashMap;
+///
+/// #[derive(Deserialize)]
+/// struct Doc {
+///     _rev: couchdb::Revision,
+///     #[serde(default)]
+///     _attachments: HashMap<couchdb::AttachmentName, couchdb::Attachment>,
+/// }
+///
+/// # fn main() {
+/// let body = br#"{"results": [
+///     {"id": "alpha", "docs": [{"ok": {
+///         "_id": "alpha",
+///         "_rev": "1-967a00dff5e02add41819138abb3284d",
+///         "_attachments": {"a.txt": {
+///             "content_type": "text/plain",
+///             "digest": "md5-XUFAKrxLKna5cZ2REBfFkg==",
+///             "length": 5,
+///             "revpos": 1,
+///             "stub": true
+///         }}
+///     }}]},
+///     {"id": "bravo", "docs": [{"error": {
+///         "id": "bravo",
+///         "rev": "undefined",
+///         "error": "not_found",
+///         "reason": "missing"
+///     }}]}
+/// ]}"#;
+///
+/// let response: BulkGetResponse<Doc> = serde_json::from_slice(body).unwrap();
+///
+/// match response.results[0].docs[0] {
+///     BulkGetDoc::Ok(ref doc) => assert_eq!(doc._attachments.len(), 1),
+///     _ => panic!("Expected a document"),
+/// }
+///
+/// match response.results[1].docs[0] {
+///     BulkGetDoc::Error(ref e) => {
+///         assert_eq!(e.error, "not_found");
+///         assert_eq!(e.rev, None);
+///     }
+///     _ => panic!("Expected an error"),
+/// }
+/// # }
+/// ```
+///
+/// # Compatibility
+///
+/// `BulkGetResponse` contains a dummy private member in order to prevent
+/// applications from directly constructing a `BulkGetResponse` instance. This
+/// allows new fields to be added to `BulkGetResponse` in future releases
+/// without it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct BulkGetResponse<T> {
+    pub results: Vec<BulkGetResult<T>>,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `BulkGetResult` contains the result for one item of a `_bulk_get` request.
+///
+/// # Compatibility
+///
+/// `BulkGetResult` contains a dummy private member in order to prevent
+/// applications from directly constructing a `BulkGetResult` instance. This
+/// allows new fields to be added to `BulkGetResult` in future releases without
+/// it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct BulkGetResult<T> {
+    pub id: DocumentId,
+    pub docs: Vec<BulkGetDoc<T>>,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `BulkGetDoc` is one document revision, or an error, within a `_bulk_get`
+/// result.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub enum BulkGetDoc<T> {
+    /// The server found the revision.
+    #[serde(rename = "ok")]
+    Ok(T),
+
+    /// The server failed to get the revision.
+    #[serde(rename = "error")]
+    Error(BulkGetError),
+}
+
+/// `BulkGetError` describes why the server failed to get a document revision
+/// within a `_bulk_get` result.
+///
+/// The `rev` field is `None` if the request did not specify a revision.
+///
+/// # Compatibility
+///
+/// `BulkGetError` contains a dummy private member in order to prevent
+/// applications from directly constructing a `BulkGetError` instance. This
+/// allows new fields to be added to `BulkGetError` in future releases without
+/// it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct BulkGetError {
+    pub id: DocumentId,
+
+    #[serde(default, deserialize_with = "deserialize_optional_revision")]
+    pub rev: Option<Revision>,
+
+    pub error: String,
+    pub reason: String,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `BulkGetStrategy` describes how an application should get many document
+/// revisions from a given server.
+///
+/// # Remarks
+///
+/// CouchDB 2.0 and later—as well as Couchbase Sync Gateway—support `POST
+/// /{db}/_bulk_get`. Older servers require a separate `GET
+/// /{db}/{doc}?open_revs=[…]` request for each document, whose response the
+/// [`OpenRevision`](../multipart/enum.OpenRevision.html) type decodes.
+///
+/// Even so, an application should fall back to `open_revs` requests if a
+/// `_bulk_get` request fails with `404 Not Found` or `405 Method Not Allowed`,
+/// as some proxies and compatible servers misreport their version.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::bulk::BulkGetStrategy;
+///
+/// let root: couchdb::Root = serde_json::from_str(r#"{
+///     "couchdb": "Welcome",
+///     "uuid": "0762dcce5f0d7f6f79157f852186f149",
+///     "version": "2.1.1",
+///     "vendor": {"name": "The Apache Software Foundation"}
+/// }"#).unwrap();
+///
+/// assert_eq!(BulkGetStrategy::from_root(&root), BulkGetStrategy::BulkGet);
+/// ```
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BulkGetStrategy {
+    /// Use `POST /{db}/_bulk_get` requests.
+    BulkGet,
+
+    /// Use a `GET /{db}/{doc}?open_revs=[…]` request for each document.
+    OpenRevs,
+}
+
+impl BulkGetRequest {
+    /// Constructs a new request for the given items.
+    pub fn new(docs: Vec<BulkGetItem>) -> Self {
+        BulkGetRequest { docs: docs }
+    }
+
+    /// Borrows the request's items.
+    pub fn docs(&self) -> &[BulkGetItem] {
+        &self.docs
+    }
+
+    /// Appends an item to the request.
+    pub fn push(&mut self, item: BulkGetItem) {
+        self.docs.push(item);
+    }
+}
+
+impl From<Vec<BulkGetItem>> for BulkGetRequest {
+    fn from(docs: Vec<BulkGetItem>) -> Self {
+        BulkGetRequest::new(docs)
+    }
+}
+
+impl BulkGetItem {
+    /// Constructs a new item for the given document's winning revision.
+    pub fn new<T: Into<DocumentId>>(id: T) -> Self {
+        BulkGetItem {
+            id: id.into(),
+            rev: None,
+            atts_since: Vec::new(),
+        }
+    }
+
+    /// Sets the revision to get.
+    pub fn with_revision(mut self, rev: Revision) -> Self {
+        self.rev = Some(rev);
+        self
+    }
+
+    /// Sets the revisions whose attachments the application already has.
+    ///
+    /// With the `attachments=true` query parameter, the server omits the
+    /// content of any attachment that has not changed since one of these
+    /// revisions.
+    ///
+    pub fn with_atts_since(mut self, atts_since: Vec<Revision>) -> Self {
+        self.atts_since = atts_since;
+        self
+    }
+
+    /// Borrows the item's document id.
+    pub fn id(&self) -> &DocumentId {
+        &self.id
+    }
+
+    /// Borrows the item's revision, if any.
+    pub fn revision(&self) -> Option<&Revision> {
+        self.rev.as_ref()
+    }
+
+    /// Borrows the revisions whose attachments the application already has.
+    pub fn atts_since(&self) -> &[Revision] {
+        &self.atts_since
+    }
+}
+
+impl BulkGetStrategy {
+    /// Chooses a strategy according to the server's root resource.
+    ///
+    /// The strategy is `BulkGet` if the server is CouchDB 2.0 or later—as
+    /// indicated by its version or by its having a `features` field—or
+    /// Couchbase Sync Gateway. Otherwise, including if the server's version is
+    /// unknown, the strategy is `OpenRevs`.
+    ///
+    pub fn from_root(root: &Root) -> Self {
+        if !root.features.is_empty() || root.vendor.name.contains("Sync Gateway") {
+            return BulkGetStrategy::BulkGet;
+        }
+        match root.version.triple() {
+            Some((major, _, _)) if major >= 2 => BulkGetStrategy::BulkGet,
+            _ => BulkGetStrategy::OpenRevs,
+        }
+    }
+}
+
+// The server sends the string `undefined` as the revision of an error for an
+// item that specifies no revision. Any other string must be a revision.
+fn deserialize_optional_revision<'de, D>(deserializer: D) -> Result<Option<Revision>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(ref x) if x == "undefined" => Ok(None),
+        Some(x) => Revision::parse_tolerant(&x).map(Some).map_err(|_| {
+            D::Error::invalid_value(Unexpected::Str(&x), &"a CouchDB document revision or `undefined`")
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn request_serializes_ok() {
+        let rev = Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap();
+        let mut request = BulkGetRequest::default();
+        request.push(BulkGetItem::new("alpha").with_revision(rev.clone()).with_atts_since(vec![
+            Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap(),
+        ]));
+        request.push(BulkGetItem::new("bravo"));
+
+        assert_eq!(request.docs()[0].revision(), Some(&rev));
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({"docs": [
+                {
+                    "id": "alpha",
+                    "rev": "2-7051cbe5c8faecd085a3fa619e6e6337",
+                    "atts_since": ["1-967a00dff5e02add41819138abb3284d"]
+                },
+                {"id": "bravo"}
+            ]})
+        );
+    }
+
+    #[test]
+    fn response_deserializes_ok() {
+        let source = r#"{"results": [
+            {"id": "alpha", "docs": [
+                {"ok": {"_id": "alpha", "_rev": "2-7051cbe5c8faecd085a3fa619e6e6337"}},
+                {"error": {
+                    "id": "alpha",
+                    "rev": "3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb",
+                    "error": "not_found",
+                    "reason": "missing"
+                }}
+            ]}
+        ]}"#;
+
+        let got: BulkGetResponse<serde_json::Value> = serde_json::from_str(source).unwrap();
+        assert_eq!(got.results.len(), 1);
+        assert_eq!(got.results[0].id, DocumentId::from("alpha"));
+        match got.results[0].docs[0] {
+            BulkGetDoc::Ok(ref doc) => assert_eq!(doc["_rev"], "2-7051cbe5c8faecd085a3fa619e6e6337"),
+            ref x => panic!("Got unexpected result {:?}", x),
+        }
+        match got.results[0].docs[1] {
+            BulkGetDoc::Error(ref e) => {
+                assert_eq!(e.rev, Some(Revision::parse("3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb").unwrap()));
+                assert_eq!(e.reason, "missing");
+            }
+            ref x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn error_revision_is_undefined_or_valid() {
+        let got: BulkGetError =
+            serde_json::from_str(r#"{"id": "alpha", "rev": "undefined", "error": "not_found", "reason": "missing"}"#)
+                .unwrap();
+        assert_eq!(got.rev, None);
+
+        let got: BulkGetError =
+            serde_json::from_str(r#"{"id": "alpha", "error": "not_found", "reason": "missing"}"#).unwrap();
+        assert_eq!(got.rev, None);
+
+        match serde_json::from_str::<BulkGetError>(
+            r#"{"id": "alpha", "rev": "bravo", "error": "not_found", "reason": "missing"}"#,
+        ) {
+            Err(ref e) if e.is_data() => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn strategy_from_root() {
+        let root = |version: &str, vendor: &str| -> Root {
+            serde_json::from_value(json!({
+                "couchdb": "Welcome",
+                "uuid": "0762dcce5f0d7f6f79157f852186f149",
+                "version": version,
+                "vendor": {"name": vendor, "version": version}
+            })).unwrap()
+        };
+
+        assert_eq!(
+            BulkGetStrategy::from_root(&root("1.6.1", "The Apache Software Foundation")),
+            BulkGetStrategy::OpenRevs
+        );
+        assert_eq!(
+            BulkGetStrategy::from_root(&root("2.0.0", "The Apache Software Foundation")),
+            BulkGetStrategy::BulkGet
+        );
+        assert_eq!(
+            BulkGetStrategy::from_root(&root("3.3.3", "The Apache Software Foundation")),
+            BulkGetStrategy::BulkGet
+        );
+        assert_eq!(
+            BulkGetStrategy::from_root(&root("Couchbase Sync Gateway/1.4", "Couchbase Sync Gateway")),
+            BulkGetStrategy::BulkGet
+        );
+        assert_eq!(BulkGetStrategy::from_root(&root("bad", "Unknown")), BulkGetStrategy::OpenRevs);
+
+        let mut x = root("bad", "Unknown");
+        x.features.push(String::from("scheduler"));
+        assert_eq!(BulkGetStrategy::from_root(&x), BulkGetStrategy::BulkGet);
+    }
+}
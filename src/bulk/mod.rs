@@ -0,0 +1,37 @@
+//! The `bulk` module provides types for reading and writing many documents in
+//! a single request.
+//!
+//! # Summary
+//!
+//! * `BulkDocsRequest` constructs the body of a `POST /{db}/_bulk_docs`
+//!   request, and `BulkDocsResult` decodes each row of the response.
+//!
+//! * `BulkDocsRequest` matches the response rows with the request's
+//!   documents—e.g., to update each document's revision or to separate
+//!   conflicts from validation failures.
+//!
+//! * `Batcher` splits many documents into request bodies that stay within the
+//!   server's size limits.
+//!
+//! * `BulkGetRequest` constructs the body of a `POST /{db}/_bulk_get` request,
+//!   and `BulkGetResponse` decodes its JSON response. `BulkGetStrategy` tells
+//!   whether the server supports `_bulk_get` at all.
+//!
+//! # Remarks
+//!
+//! A bulk write is not a transaction. The server accepts or rejects each
+//! document separately, and the response has one row per document, in the
+//! same order as the request's documents.
+//!
+//! See the [CouchDB
+//! documentation](http://docs.couchdb.org/en/2.0.0/api/database/bulk-api.html)
+//! for details.
+
+mod batcher;
+mod docs;
+mod get;
+
+pub use self::batcher::{Batch, BatchError, Batcher};
+pub use self::docs::{BulkDocsErrorKind, BulkDocsOutcome, BulkDocsRequest, BulkDocsResult};
+pub use self::get::{BulkGetDoc, BulkGetError, BulkGetItem, BulkGetRequest, BulkGetResponse, BulkGetResult,
+                    BulkGetStrategy};
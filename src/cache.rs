@@ -0,0 +1,462 @@
+//! The `cache` module provides a client-side document cache.
+//!
+//! # Summary
+//!
+//! * `DocumentCache` stores documents in memory, keyed by `DocumentPath`,
+//!   together with each document's revision and entity tag.
+//!
+//! * `DocumentCache` is bounded by a capacity in bytes and evicts the least
+//!   recently used documents to stay within its capacity. The application
+//!   specifies each document's size when inserting it—e.g., the size of the
+//!   HTTP response body.
+//!
+//! * `DocumentCache` supports revalidating a cached document via a conditional
+//!   `GET` request with the `If-None-Match` header.
+//!
+//! * `DocumentCache` applies changes from a database's changes feed, evicting
+//!   each cached document that has changed or, when the changes feed includes
+//!   documents, updating it.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate couchdb;
+//! #[macro_use]
+//! extern crate serde_json;
+//!
+//! use couchdb::cache::{CacheEntry, DocumentCache};
+//!
+//! # fn main() {
+//! let path = couchdb::DocumentPath::parse("/db/alpha").unwrap();
+//! let rev = couchdb::Revision::parse("1-917fa2381192822767f010b95b45325b").unwrap();
+//!
+//! let mut cache = DocumentCache::new(1024 * 1024);
+//! cache.insert(path.clone(), CacheEntry::new(json!({"foo": 17}), rev, 12));
+//!
+//! // Revalidate the document by sending a GET request with this header.
+//! let precondition = cache.precondition(&path).unwrap();
+//! assert_eq!(precondition.header_name(), "If-None-Match");
+//!
+//! // The server responded with `304 Not Modified`.
+//! let entry = cache.revalidate(&path, 304).unwrap();
+//! assert_eq!(entry.doc()["foo"], 17);
+//! # }
+//! ```
+
+use {ChangeResult, DatabaseName, DocumentPath, Error, Revision, serde_json};
+use etag::{ETag, Outcome, Precondition};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+
+/// `DocumentCache` is an in-memory, size-bounded cache of documents.
+///
+/// See the [module documentation](index.html) for details.
+///
+#[derive(Clone, Debug)]
+pub struct DocumentCache<T> {
+    capacity: u64,
+    size: u64,
+    tick: u64,
+    slots: HashMap<DocumentPath, Slot<T>>,
+
+    // Recency order, from least to most recently used.
+    lru: BTreeMap<u64, DocumentPath>,
+}
+
+#[derive(Clone, Debug)]
+struct Slot<T> {
+    entry: CacheEntry<T>,
+    tick: u64,
+}
+
+/// `CacheEntry` is a document stored in a `DocumentCache`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheEntry<T> {
+    doc: T,
+    revision: Revision,
+    etag: ETag,
+    size: u64,
+}
+
+impl<T> CacheEntry<T> {
+    /// Constructs a new cache entry.
+    ///
+    /// The entry's entity tag is derived from the revision, as the CouchDB
+    /// server does for documents. The size is the number of bytes the entry
+    /// counts against the cache's capacity.
+    ///
+    pub fn new(doc: T, revision: Revision, size: u64) -> Self {
+        CacheEntry {
+            doc: doc,
+            etag: ETag::from(&revision),
+            revision: revision,
+            size: size,
+        }
+    }
+
+    /// Replaces the entry's entity tag—e.g., with the `ETag` header the server
+    /// sent.
+    pub fn with_etag(mut self, etag: ETag) -> Self {
+        self.etag = etag;
+        self
+    }
+
+    /// Borrows the cached document.
+    pub fn doc(&self) -> &T {
+        &self.doc
+    }
+
+    /// Borrows the cached document's revision.
+    pub fn revision(&self) -> &Revision {
+        &self.revision
+    }
+
+    /// Borrows the cached document's entity tag.
+    pub fn etag(&self) -> &ETag {
+        &self.etag
+    }
+
+    /// Returns the number of bytes the entry counts against the cache's
+    /// capacity.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Converts the entry into the cached document.
+    pub fn into_doc(self) -> T {
+        self.doc
+    }
+}
+
+impl<T> DocumentCache<T> {
+    /// Constructs a new, empty cache having the given capacity, in bytes.
+    pub fn new(capacity: u64) -> Self {
+        DocumentCache {
+            capacity: capacity,
+            size: 0,
+            tick: 0,
+            slots: HashMap::new(),
+            lru: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the cache's capacity, in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Returns the total size of all cached entries, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the number of cached entries.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Returns whether the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Borrows the entry for the given path, if cached, and marks it as most
+    /// recently used.
+    pub fn get(&mut self, path: &DocumentPath) -> Option<&CacheEntry<T>> {
+        let tick = self.next_tick();
+        let slot = self.slots.get_mut(path)?;
+        self.lru.remove(&slot.tick);
+        self.lru.insert(tick, path.clone());
+        slot.tick = tick;
+        Some(&slot.entry)
+    }
+
+    /// Borrows the entry for the given path, if cached, without marking it as
+    /// used.
+    pub fn peek(&self, path: &DocumentPath) -> Option<&CacheEntry<T>> {
+        self.slots.get(path).map(|x| &x.entry)
+    }
+
+    /// Returns whether the cache has an entry for the given path.
+    pub fn contains(&self, path: &DocumentPath) -> bool {
+        self.slots.contains_key(path)
+    }
+
+    /// Inserts an entry, replacing any existing entry for the same path.
+    ///
+    /// To make room for the entry, the cache evicts the least recently used
+    /// entries as needed. An entry larger than the cache's capacity is not
+    /// cached, though it still replaces any existing entry.
+    ///
+    pub fn insert(&mut self, path: DocumentPath, entry: CacheEntry<T>) {
+
+        self.remove(&path);
+
+        if self.capacity < entry.size {
+            return;
+        }
+
+        while self.capacity - self.size < entry.size {
+            let victim = match self.lru.iter().next() {
+                Some((_, path)) => path.clone(),
+                None => break,
+            };
+            self.remove(&victim);
+        }
+
+        let tick = self.next_tick();
+        self.size += entry.size;
+        self.lru.insert(tick, path.clone());
+        self.slots.insert(
+            path,
+            Slot {
+                entry: entry,
+                tick: tick,
+            },
+        );
+    }
+
+    /// Removes the entry for the given path, if cached.
+    pub fn remove(&mut self, path: &DocumentPath) -> Option<CacheEntry<T>> {
+        let slot = self.slots.remove(path)?;
+        self.lru.remove(&slot.tick);
+        self.size -= slot.entry.size;
+        Some(slot.entry)
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+        self.lru.clear();
+        self.size = 0;
+    }
+
+    /// Constructs the precondition for revalidating the entry for the given
+    /// path, if cached.
+    ///
+    /// The application should send the precondition as a header of a `GET`
+    /// request for the document and then pass the response's status code to
+    /// the `revalidate` method.
+    ///
+    pub fn precondition(&self, path: &DocumentPath) -> Option<Precondition> {
+        self.peek(path).map(|x| Precondition::if_none_match(x.etag.clone()))
+    }
+
+    /// Interprets the status code of the response to a revalidation request.
+    ///
+    /// If the server responded with `304 Not Modified`, this method returns the
+    /// cached entry and marks it as most recently used. Otherwise, the entry is
+    /// stale or gone, so this method removes it and returns `None`, and the
+    /// application should insert the document from the response, if any.
+    ///
+    pub fn revalidate(&mut self, path: &DocumentPath, status_code: u16) -> Option<&CacheEntry<T>> {
+        let etag = match self.peek(path) {
+            None => return None,
+            Some(x) => x.etag.clone(),
+        };
+        match Precondition::if_none_match(etag).outcome(status_code) {
+            Outcome::NotModified => self.get(path),
+            _ => {
+                self.remove(path);
+                None
+            }
+        }
+    }
+
+    /// Applies a change from the given database's changes feed.
+    ///
+    /// If the document is cached and its revision differs from the change's
+    /// winning revision, or the change deletes the document, the cache evicts
+    /// the document. This method never inserts documents.
+    ///
+    pub fn apply_change(&mut self, db_name: &DatabaseName, change: &ChangeResult) {
+        let path = db_name.clone().with_document_id(change.id.clone());
+        let is_current = match self.peek(&path) {
+            None => return,
+            Some(x) => !change.deleted && change.winning_revision() == Some(&x.revision),
+        };
+        if !is_current {
+            self.remove(&path);
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl<T: DeserializeOwned> DocumentCache<T> {
+    /// Applies a change from the given database's changes feed, updating the
+    /// cached document if the change includes it.
+    ///
+    /// This method is like the `apply_change` method, except that if the
+    /// document is cached and the change includes the document (i.e., the
+    /// changes request has the `include_docs=true` query parameter), the cache
+    /// updates the entry rather than evicting it. The updated entry's size is
+    /// the size of the document serialized as JSON.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the change's document fails to
+    /// deserialize, in which case the cache evicts the document.
+    ///
+    pub fn apply_change_with_doc(&mut self, db_name: &DatabaseName, change: &ChangeResult) -> Result<(), Error> {
+
+        let path = db_name.clone().with_document_id(change.id.clone());
+
+        let (doc, rev) = match (self.contains(&path), change.deleted, change.doc.as_ref(), change.winning_revision()) {
+            (true, false, Some(doc), Some(rev)) => (doc, rev),
+            _ => {
+                self.apply_change(db_name, change);
+                return Ok(());
+            }
+        };
+
+        if self.peek(&path).map(|x| &x.revision) == Some(rev) {
+            return Ok(());
+        }
+
+        self.remove(&path);
+
+        let size = serde_json::to_vec(doc).map_err(|e| {
+            Error::from(("Failed to serialize changed document as JSON", e))
+        })?
+            .len() as u64;
+
+        let doc = serde_json::from_value(doc.clone()).map_err(|e| {
+            Error::from(("Failed to deserialize changed document", e))
+        })?;
+
+        self.insert(path, CacheEntry::new(doc, rev.clone(), size));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> DocumentPath {
+        DocumentPath::parse(s).unwrap()
+    }
+
+    fn rev(s: &str) -> Revision {
+        Revision::parse(s).unwrap()
+    }
+
+    const REV_1: &str = "1-917fa2381192822767f010b95b45325b";
+    const REV_2: &str = "2-c1c6c44c4bc3c9344b037c8690468605";
+
+    fn change(id: &str, rev: &str, deleted: bool, doc: Option<serde_json::Value>) -> ChangeResult {
+        serde_json::from_value(json!({
+            "seq": 1,
+            "id": id,
+            "changes": [{"rev": rev}],
+            "deleted": deleted,
+            "doc": doc,
+        })).unwrap()
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+
+        let mut cache = DocumentCache::new(10);
+        cache.insert(path("/db/alpha"), CacheEntry::new(1, rev(REV_1), 4));
+        cache.insert(path("/db/bravo"), CacheEntry::new(2, rev(REV_1), 4));
+        assert_eq!(cache.size(), 8);
+
+        // Use alpha so that bravo is least recently used.
+        assert_eq!(cache.get(&path("/db/alpha")).map(|x| *x.doc()), Some(1));
+
+        cache.insert(path("/db/charlie"), CacheEntry::new(3, rev(REV_1), 4));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.size(), 8);
+        assert!(cache.contains(&path("/db/alpha")));
+        assert!(!cache.contains(&path("/db/bravo")));
+        assert!(cache.contains(&path("/db/charlie")));
+    }
+
+    #[test]
+    fn cache_replaces_entry_for_same_path() {
+        let mut cache = DocumentCache::new(10);
+        cache.insert(path("/db/alpha"), CacheEntry::new(1, rev(REV_1), 4));
+        cache.insert(path("/db/alpha"), CacheEntry::new(2, rev(REV_2), 6));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 6);
+        assert_eq!(cache.peek(&path("/db/alpha")).unwrap().revision(), &rev(REV_2));
+    }
+
+    #[test]
+    fn cache_skips_entry_larger_than_capacity() {
+        let mut cache = DocumentCache::new(10);
+        cache.insert(path("/db/alpha"), CacheEntry::new(1, rev(REV_1), 4));
+        cache.insert(path("/db/alpha"), CacheEntry::new(2, rev(REV_2), 11));
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn cache_revalidates_entry() {
+
+        let mut cache = DocumentCache::new(10);
+        cache.insert(path("/db/alpha"), CacheEntry::new(1, rev(REV_1), 4));
+
+        let precondition = cache.precondition(&path("/db/alpha")).unwrap();
+        assert_eq!(precondition.header_value(), format!("\"{}\"", REV_1));
+        assert_eq!(cache.precondition(&path("/db/bravo")), None);
+
+        assert!(cache.revalidate(&path("/db/alpha"), 304).is_some());
+        assert!(cache.revalidate(&path("/db/alpha"), 200).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_applies_changes() {
+
+        let db_name = DatabaseName::new("db");
+        let mut cache = DocumentCache::new(100);
+        cache.insert(path("/db/alpha"), CacheEntry::new(1, rev(REV_1), 4));
+        cache.insert(path("/db/bravo"), CacheEntry::new(2, rev(REV_1), 4));
+        cache.insert(path("/db/charlie"), CacheEntry::new(3, rev(REV_1), 4));
+        cache.insert(path("/other/alpha"), CacheEntry::new(4, rev(REV_1), 4));
+
+        cache.apply_change(&db_name, &change("alpha", REV_1, false, None));
+        cache.apply_change(&db_name, &change("bravo", REV_2, false, None));
+        cache.apply_change(&db_name, &change("charlie", REV_1, true, None));
+        cache.apply_change(&db_name, &change("delta", REV_1, false, None));
+
+        assert!(cache.contains(&path("/db/alpha")));
+        assert!(!cache.contains(&path("/db/bravo")));
+        assert!(!cache.contains(&path("/db/charlie")));
+        assert!(!cache.contains(&path("/db/delta")));
+        assert!(cache.contains(&path("/other/alpha")));
+    }
+
+    #[test]
+    fn cache_applies_changes_with_docs() {
+
+        let db_name = DatabaseName::new("db");
+        let mut cache: DocumentCache<serde_json::Value> = DocumentCache::new(100);
+        cache.insert(path("/db/alpha"), CacheEntry::new(json!({"foo": 1}), rev(REV_1), 4));
+
+        let doc = json!({"_id": "alpha", "_rev": REV_2, "foo": 2});
+        cache
+            .apply_change_with_doc(&db_name, &change("alpha", REV_2, false, Some(doc.clone())))
+            .unwrap();
+        cache
+            .apply_change_with_doc(&db_name, &change("bravo", REV_2, false, Some(doc.clone())))
+            .unwrap();
+
+        let entry = cache.peek(&path("/db/alpha")).unwrap();
+        assert_eq!(entry.revision(), &rev(REV_2));
+        assert_eq!(entry.doc(), &doc);
+        assert_eq!(entry.size(), serde_json::to_vec(&doc).unwrap().len() as u64);
+        assert!(!cache.contains(&path("/db/bravo")));
+
+        cache
+            .apply_change_with_doc(&db_name, &change("alpha", REV_2, true, Some(doc)))
+            .unwrap();
+        assert!(cache.is_empty());
+    }
+}
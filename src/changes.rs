@@ -0,0 +1,265 @@
+use {DocumentId, Revision, serde_json, std};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::marker::PhantomData;
+
+/// `Changes` contains the content of a database's changes feed.
+///
+/// # Summary
+///
+/// * `Changes` has public members instead of accessor methods because there
+///   are no invariants restricting the data.
+///
+/// * `Changes` implements `Deserialize`.
+///
+/// # Remarks
+///
+/// An application may obtain a database's changes by sending an HTTP request
+/// to GET `/{db}/_changes`, using the `normal` or `longpoll` feed type.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"{
+///     "results": [
+///         {
+///             "seq": "2-g1AAAAB",
+///             "id": "alpha",
+///             "changes": [{"rev": "2-7051cbe5c8faecd085a3fa619e6e6337"}],
+///             "deleted": true
+///         }
+///     ],
+///     "last_seq": "2-g1AAAAB",
+///     "pending": 0
+/// }"#;
+///
+/// let changes: couchdb::Changes = serde_json::from_slice(body).unwrap();
+///
+/// assert_eq!(changes.results[0].id, couchdb::DocumentId::from("alpha"));
+/// assert!(changes.results[0].deleted);
+/// assert_eq!(changes.last_seq.to_string(), "2-g1AAAAB");
+/// ```
+///
+/// # Compatibility
+///
+/// `Changes` contains a dummy private member in order to prevent applications
+/// from directly constructing a `Changes` instance. This allows new fields to be
+/// added to `Changes` in future releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Changes {
+    pub results: Vec<ChangeResult>,
+    pub last_seq: Sequence,
+
+    #[serde(default)]
+    pub pending: Option<u64>,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ChangeResult` contains one document change within a database's changes
+/// feed.
+///
+/// # Summary
+///
+/// * `ChangeResult` has public members instead of accessor methods because
+///   there are no invariants restricting the data.
+///
+/// * `ChangeResult` implements `Deserialize`.
+///
+/// # Remarks
+///
+/// `ChangeResult` is normally part of a [`Changes`](struct.Changes.html)
+/// instance, though an application may also deserialize a `ChangeResult` from
+/// each line of a `continuous` changes feed.
+///
+/// The `changes` field lists the document's winning revision first. With the
+/// `style=all_docs` query parameter, it also lists the document's conflicting
+/// revisions.
+///
+/// The `doc` field contains the document if and only if the request has the
+/// `include_docs=true` query parameter.
+///
+/// # Compatibility
+///
+/// `ChangeResult` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ChangeResult` instance. This
+/// allows new fields to be added to `ChangeResult` in future releases without it
+/// being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ChangeResult {
+    pub seq: Sequence,
+    pub id: DocumentId,
+    pub changes: Vec<ChangeRevision>,
+
+    #[serde(default)]
+    pub deleted: bool,
+
+    #[serde(default)]
+    pub doc: Option<serde_json::Value>,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ChangeRevision` contains one revision of a document change.
+///
+/// # Compatibility
+///
+/// `ChangeRevision` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ChangeRevision` instance. This
+/// allows new fields to be added to `ChangeRevision` in future releases without
+/// it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct ChangeRevision {
+    pub rev: Revision,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `Sequence` is a database update sequence, as used in the changes feed.
+///
+/// # Summary
+///
+/// * `Sequence` is opaque. CouchDB 1.x uses integers, whereas CouchDB 2.x uses
+///   strings that encode the state of each database shard.
+///
+/// * `Sequence` implements `Deserialize` and `Serialize`, and it serializes
+///   exactly as the server sent it.
+///
+/// * `Sequence` implements `Display`, producing a string suitable as the
+///   `since` query parameter of a changes request.
+///
+/// # Remarks
+///
+/// Sequences are not comparable in general. For a CouchDB 2.x server, only the
+/// server can determine which of two sequences is later.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sequence(serde_json::Value);
+
+impl ChangeResult {
+    /// Borrows the document's winning revision, if any.
+    pub fn winning_revision(&self) -> Option<&Revision> {
+        self.changes.first().map(|x| &x.rev)
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Self {
+        Sequence::from(0)
+    }
+}
+
+impl std::fmt::Display for Sequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match self.0 {
+            serde_json::Value::String(ref s) => f.write_str(s),
+            ref x => write!(f, "{}", x),
+        }
+    }
+}
+
+impl From<u64> for Sequence {
+    fn from(n: u64) -> Self {
+        Sequence(serde_json::Value::from(n))
+    }
+}
+
+impl From<String> for Sequence {
+    fn from(s: String) -> Self {
+        Sequence(serde_json::Value::String(s))
+    }
+}
+
+impl<'a> From<&'a str> for Sequence {
+    fn from(s: &'a str) -> Self {
+        Sequence(serde_json::Value::String(String::from(s)))
+    }
+}
+
+impl<'a> Deserialize<'a> for Sequence {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'a>,
+    {
+        // CouchDB 2.x sometimes sends a sequence as an array of a number and a
+        // string, so accept any JSON value.
+        serde_json::Value::deserialize(deserializer).map(Sequence)
+    }
+}
+
+impl Serialize for Sequence {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_deserializes_ok() {
+
+        let source = r#"{
+            "results": [
+                {
+                    "seq": 1,
+                    "id": "alpha",
+                    "changes": [
+                        {"rev": "2-7051cbe5c8faecd085a3fa619e6e6337"},
+                        {"rev": "2-eec205a9d413992850a6e32678485900"}
+                    ]
+                },
+                {
+                    "seq": 2,
+                    "id": "bravo",
+                    "changes": [{"rev": "1-967a00dff5e02add41819138abb3284d"}],
+                    "deleted": true,
+                    "doc": {"_id": "bravo", "_rev": "1-967a00dff5e02add41819138abb3284d", "_deleted": true}
+                }
+            ],
+            "last_seq": 2
+        }"#;
+
+        let got: Changes = serde_json::from_str(source).unwrap();
+        assert_eq!(got.results.len(), 2);
+        assert_eq!(got.last_seq, Sequence::from(2));
+        assert_eq!(got.pending, None);
+
+        assert_eq!(got.results[0].id, DocumentId::from("alpha"));
+        assert_eq!(
+            got.results[0].winning_revision(),
+            Some(&Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap())
+        );
+        assert!(!got.results[0].deleted);
+        assert_eq!(got.results[0].doc, None);
+
+        assert!(got.results[1].deleted);
+        assert_eq!(got.results[1].doc.as_ref().unwrap()["_id"], "bravo");
+    }
+
+    #[test]
+    fn sequence_round_trips() {
+        for source in &["42", r#""42-g1AAAAB""#, r#"[42,"g1AAAAB"]"#] {
+            let got: Sequence = serde_json::from_str(source).unwrap();
+            assert_eq!(serde_json::to_string(&got).unwrap(), *source);
+        }
+    }
+
+    #[test]
+    fn sequence_displays_as_since_parameter() {
+        assert_eq!(Sequence::from(42).to_string(), "42");
+        assert_eq!(Sequence::from("42-g1AAAAB").to_string(), "42-g1AAAAB");
+    }
+}
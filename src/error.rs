@@ -1,39 +1,109 @@
-use std;
+use {serde_json, std};
 use std::borrow::Cow;
 
 /// `Error` is the principal type of the `couchdb` crate.
 #[derive(Debug)]
 pub enum Error {
+    #[doc(hidden)]
+    BadAttachment { what: &'static str },
+
     BadDesignDocumentId,
 
     #[doc(hidden)]
     BadDigest,
 
+    #[doc(hidden)]
+    BadETag { what: &'static str },
+
+    #[doc(hidden)]
+    BadMultipart { what: &'static str },
+
     #[doc(hidden)]
     BadPath { what: &'static str },
 
+    #[doc(hidden)]
+    BadRange { what: &'static str },
+
+    #[doc(hidden)]
+    BadResponse { what: &'static str },
+
     BadRevision,
 
+    #[doc(hidden)]
+    BadRevisionPath { what: &'static str },
+
+    #[doc(hidden)]
+    BadSelector { what: &'static str },
+
     #[doc(hidden)]
     Io {
         what: Cow<'static, str>,
         cause: std::io::Error,
     },
+
+    #[doc(hidden)]
+    Json {
+        what: Cow<'static, str>,
+        cause: serde_json::Error,
+    },
 }
 
 impl Error {
+    #[doc(hidden)]
+    pub fn bad_attachment(what: &'static str) -> Self {
+        Error::BadAttachment { what: what }
+    }
+
+    #[doc(hidden)]
+    pub fn bad_etag(what: &'static str) -> Self {
+        Error::BadETag { what: what }
+    }
+
     #[doc(hidden)]
     pub fn bad_path(what: &'static str) -> Self {
         Error::BadPath { what: what }
     }
+
+    #[doc(hidden)]
+    pub fn bad_multipart(what: &'static str) -> Self {
+        Error::BadMultipart { what: what }
+    }
+
+    #[doc(hidden)]
+    pub fn bad_range(what: &'static str) -> Self {
+        Error::BadRange { what: what }
+    }
+
+    #[doc(hidden)]
+    pub fn bad_response(what: &'static str) -> Self {
+        Error::BadResponse { what: what }
+    }
+
+    #[doc(hidden)]
+    pub fn bad_revision_path(what: &'static str) -> Self {
+        Error::BadRevisionPath { what: what }
+    }
+
+    #[doc(hidden)]
+    pub fn bad_selector(what: &'static str) -> Self {
+        Error::BadSelector { what: what }
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         let d = std::error::Error::description(self);
         match *self {
+            Error::BadAttachment { what } => write!(f, "{}: {}", d, what),
+            Error::BadETag { what } => write!(f, "{}: {}", d, what),
+            Error::BadMultipart { what } => write!(f, "{}: {}", d, what),
             Error::BadPath { what } => write!(f, "{}: {}", d, what),
+            Error::BadRange { what } => write!(f, "{}: {}", d, what),
+            Error::BadResponse { what } => write!(f, "{}: {}", d, what),
+            Error::BadRevisionPath { what } => write!(f, "{}: {}", d, what),
+            Error::BadSelector { what } => write!(f, "{}: {}", d, what),
             Error::Io { ref cause, .. } => write!(f, "{}: {}", d, cause),
+            Error::Json { ref cause, .. } => write!(f, "{}: {}", d, cause),
             _ => f.write_str(d),
         }
     }
@@ -42,17 +112,26 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::BadAttachment { .. } => "The attachment content is not available",
             Error::BadDesignDocumentId => "The string is not a valid CouchDB design document id",
             Error::BadDigest => "The string is not a valid CouchDB attachment digest",
+            Error::BadETag { .. } => "The string is not a valid HTTP entity tag",
+            Error::BadMultipart { .. } => "The multipart content is not valid",
             Error::BadPath { .. } => "The CouchDB path is not valid",
+            Error::BadRange { .. } => "The string is not a valid HTTP byte range",
+            Error::BadResponse { .. } => "The server response is not valid",
             Error::BadRevision => "The string is not a valid CouchDB document revision",
+            Error::BadRevisionPath { .. } => "The revision path is not valid",
+            Error::BadSelector { .. } => "The Mango selector is not valid",
             Error::Io { ref what, .. } => what.as_ref(),
+            Error::Json { ref what, .. } => what.as_ref(),
         }
     }
 
     fn cause(&self) -> Option<&std::error::Error> {
         match *self {
             Error::Io { ref cause, .. } => Some(cause),
+            Error::Json { ref cause, .. } => Some(cause),
             _ => None,
         }
     }
@@ -66,3 +145,12 @@ impl<T: Into<Cow<'static, str>>> From<(T, std::io::Error)> for Error {
         }
     }
 }
+
+impl<T: Into<Cow<'static, str>>> From<(T, serde_json::Error)> for Error {
+    fn from((what, cause): (T, serde_json::Error)) -> Self {
+        Error::Json {
+            what: what.into(),
+            cause: cause,
+        }
+    }
+}
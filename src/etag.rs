@@ -0,0 +1,350 @@
+//! The `etag` module provides types for working with HTTP entity tags and
+//! conditional requests.
+//!
+//! # Summary
+//!
+//! * The CouchDB server sends an `ETag` header when getting a document, an
+//!   attachment, or a view. For a document, the entity tag is the document's
+//!   revision. For an attachment, the entity tag is the base64-encoding of the
+//!   attachment's digest. For a view, the entity tag is opaque.
+//!
+//! * `ETag` parses and formats entity tags and converts them to and from
+//!   `Revision` and `Digest` values.
+//!
+//! * `Precondition` constructs an `If-Match` or `If-None-Match` header for a
+//!   conditional request, and it interprets the response's status code as an
+//!   `Outcome`.
+//!
+//! # Remarks
+//!
+//! Conditional requests let an application:
+//!
+//! * Cache a document, attachment, or view result and cheaply revalidate it via
+//!   `GET` with `If-None-Match`, to which the server responds with `304 Not
+//!   Modified` if nothing changed.
+//!
+//! * Update or delete a document via `PUT` or `DELETE` with `If-Match`, which
+//!   the server treats like a `rev` query parameter. Thus the application need
+//!   not put the revision in the URL.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate couchdb;
+//!
+//! use couchdb::etag::{ETag, Outcome, Precondition};
+//!
+//! let rev = couchdb::Revision::parse("1-917fa2381192822767f010b95b45325b").unwrap();
+//!
+//! // Get the document only if it changed.
+//! let precondition = Precondition::if_none_match(&rev);
+//! assert_eq!(precondition.header_name(), "If-None-Match");
+//! assert_eq!(precondition.header_value(), "\"1-917fa2381192822767f010b95b45325b\"");
+//! assert_eq!(precondition.outcome(304), Outcome::NotModified);
+//!
+//! // Parse the `ETag` header of a response.
+//! let etag: ETag = "\"2-c1c6c44c4bc3c9344b037c8690468605\"".parse().unwrap();
+//! assert_eq!(etag.to_revision().unwrap().sequence_number(), 2);
+//! ```
+
+use {Digest, Error, Revision, base64, std};
+use std::str::FromStr;
+
+/// `ETag` is an HTTP entity tag—e.g., the value of an `ETag` header.
+///
+/// An entity tag is either strong (e.g., `"abc"`) or weak (e.g., `W/"abc"`).
+/// The CouchDB server sends only strong entity tags.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+/// `Precondition` is an HTTP precondition for a conditional request.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Precondition {
+    /// The request proceeds only if the resource's current entity tag matches
+    /// (`If-Match`).
+    IfMatch(ETag),
+
+    /// The request proceeds only if the resource's current entity tag does not
+    /// match (`If-None-Match`).
+    IfNoneMatch(ETag),
+}
+
+/// `Outcome` is the result of a conditional request, according to the
+/// response's status code.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Outcome {
+    /// The precondition held and the server performed the request.
+    Performed,
+
+    /// The resource has not changed (`304 Not Modified`). The application may
+    /// use its cached copy.
+    NotModified,
+
+    /// The precondition did not hold (`412 Precondition Failed`), or, for
+    /// `If-Match`, the server rejected the revision as stale (`409
+    /// Conflict`).
+    PreconditionFailed,
+
+    /// The response has some other status code, which the application should
+    /// handle as it would for an unconditional request.
+    Other,
+}
+
+impl ETag {
+    /// Constructs a new strong entity tag.
+    ///
+    /// The tag is the entity tag's value without quotes—e.g., `abc` for the
+    /// entity tag `"abc"`.
+    ///
+    pub fn new<T: Into<String>>(tag: T) -> Self {
+        ETag {
+            tag: tag.into(),
+            weak: false,
+        }
+    }
+
+    /// Constructs a new weak entity tag.
+    pub fn new_weak<T: Into<String>>(tag: T) -> Self {
+        ETag {
+            tag: tag.into(),
+            weak: true,
+        }
+    }
+
+    /// Borrows the entity tag's value without quotes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Returns whether the entity tag is weak.
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Returns whether the entity tag matches another according to strong
+    /// comparison—i.e., both are strong and have the same value.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Returns whether the entity tag matches another according to weak
+    /// comparison—i.e., both have the same value.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Converts the entity tag into a document revision, if possible.
+    ///
+    /// The conversion succeeds for the entity tag of a document, including one
+    /// whose revision digest is not MD5.
+    ///
+    pub fn to_revision(&self) -> Option<Revision> {
+        Revision::parse_tolerant(&self.tag).ok()
+    }
+
+    /// Converts the entity tag into an MD5 attachment digest, if possible.
+    ///
+    /// The conversion succeeds for the entity tag of an attachment having an
+    /// MD5 digest.
+    ///
+    pub fn to_digest(&self) -> Option<Digest> {
+        match base64::decode(&self.tag) {
+            Ok(ref value) if value.len() == 16 => Some(Digest::Md5 { value: value.clone() }),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ETag {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+impl FromStr for ETag {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        let s = s.trim();
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+            return Err(Error::bad_etag("Entity tag is not quoted"));
+        }
+
+        let tag = &s[1..s.len() - 1];
+        if tag.contains('"') {
+            return Err(Error::bad_etag("Entity tag contains a quote"));
+        }
+
+        Ok(ETag {
+            tag: String::from(tag),
+            weak: weak,
+        })
+    }
+}
+
+impl From<Revision> for ETag {
+    fn from(rev: Revision) -> Self {
+        ETag::new(rev.to_string())
+    }
+}
+
+impl<'a> From<&'a Revision> for ETag {
+    fn from(rev: &'a Revision) -> Self {
+        ETag::new(rev.to_string())
+    }
+}
+
+impl From<Digest> for ETag {
+    fn from(digest: Digest) -> Self {
+        ETag::from(&digest)
+    }
+}
+
+impl<'a> From<&'a Digest> for ETag {
+    fn from(digest: &'a Digest) -> Self {
+        ETag::new(base64::encode(digest.bytes()))
+    }
+}
+
+impl Precondition {
+    /// Constructs an `If-Match` precondition.
+    ///
+    /// Use this, for example, with a document's revision to update or delete
+    /// the document via `PUT` or `DELETE` only if the document has not changed.
+    ///
+    pub fn if_match<T: Into<ETag>>(etag: T) -> Self {
+        Precondition::IfMatch(etag.into())
+    }
+
+    /// Constructs an `If-None-Match` precondition.
+    ///
+    /// Use this, for example, with a cached document's revision to get the
+    /// document via `GET` only if the document has changed.
+    ///
+    pub fn if_none_match<T: Into<ETag>>(etag: T) -> Self {
+        Precondition::IfNoneMatch(etag.into())
+    }
+
+    /// Returns the name of the precondition's HTTP header.
+    pub fn header_name(&self) -> &'static str {
+        match *self {
+            Precondition::IfMatch(_) => "If-Match",
+            Precondition::IfNoneMatch(_) => "If-None-Match",
+        }
+    }
+
+    /// Formats the value of the precondition's HTTP header.
+    pub fn header_value(&self) -> String {
+        match *self {
+            Precondition::IfMatch(ref etag) |
+            Precondition::IfNoneMatch(ref etag) => etag.to_string(),
+        }
+    }
+
+    /// Interprets the status code of the response to a request having this
+    /// precondition.
+    pub fn outcome(&self, status_code: u16) -> Outcome {
+        match (self, status_code) {
+            (_, 200..=299) => Outcome::Performed,
+            (&Precondition::IfNoneMatch(_), 304) => Outcome::NotModified,
+            (_, 412) => Outcome::PreconditionFailed,
+            (&Precondition::IfMatch(_), 409) => Outcome::PreconditionFailed,
+            _ => Outcome::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etag_display_and_parse() {
+        let cases = vec![
+            ("\"abc\"", ETag::new("abc")),
+            ("W/\"abc\"", ETag::new_weak("abc")),
+            ("\"\"", ETag::new("")),
+        ];
+        for (s, etag) in cases {
+            assert_eq!(etag.to_string(), s);
+            assert_eq!(ETag::from_str(s).unwrap(), etag);
+        }
+
+        for s in &["", "abc", "\"abc", "w/\"abc\"", "\"a\"b\""] {
+            match ETag::from_str(s) {
+                Err(Error::BadETag { .. }) => {}
+                x => panic!("Got unexpected result {:?} for {:?}", x, s),
+            }
+        }
+    }
+
+    #[test]
+    fn etag_comparison() {
+        let a = ETag::new("abc");
+        let b = ETag::new_weak("abc");
+        assert!(a.strong_eq(&a));
+        assert!(!a.strong_eq(&b));
+        assert!(a.weak_eq(&b));
+        assert!(!a.weak_eq(&ETag::new("abd")));
+    }
+
+    #[test]
+    fn etag_converts_to_and_from_revision() {
+        let rev = Revision::parse("1-917fa2381192822767f010b95b45325b").unwrap();
+        let etag = ETag::from(&rev);
+        assert_eq!(etag.to_string(), "\"1-917fa2381192822767f010b95b45325b\"");
+        assert_eq!(etag.to_revision(), Some(rev));
+        assert_eq!(ETag::new("not a revision").to_revision(), None);
+
+        let rev = Revision::parse_tolerant("2-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e").unwrap();
+        assert_eq!(ETag::from(&rev).to_revision(), Some(rev));
+    }
+
+    #[test]
+    fn etag_converts_to_and_from_digest() {
+        let digest = Digest::from_str("md5-Ids41vtv725jyrN7iUvMcQ==").unwrap();
+        let etag = ETag::from(&digest);
+        assert_eq!(etag.to_string(), "\"Ids41vtv725jyrN7iUvMcQ==\"");
+        assert_eq!(etag.to_digest(), Some(digest));
+        assert_eq!(ETag::new("1-917fa2381192822767f010b95b45325b").to_digest(), None);
+    }
+
+    #[test]
+    fn precondition_formats_header() {
+        let p = Precondition::if_match(ETag::new("abc"));
+        assert_eq!(p.header_name(), "If-Match");
+        assert_eq!(p.header_value(), "\"abc\"");
+
+        let p = Precondition::if_none_match(ETag::new_weak("abc"));
+        assert_eq!(p.header_name(), "If-None-Match");
+        assert_eq!(p.header_value(), "W/\"abc\"");
+    }
+
+    #[test]
+    fn precondition_interprets_status_code() {
+        let p = Precondition::if_none_match(ETag::new("abc"));
+        assert_eq!(p.outcome(200), Outcome::Performed);
+        assert_eq!(p.outcome(304), Outcome::NotModified);
+        assert_eq!(p.outcome(412), Outcome::PreconditionFailed);
+        assert_eq!(p.outcome(409), Outcome::Other);
+        assert_eq!(p.outcome(404), Outcome::Other);
+
+        let p = Precondition::if_match(ETag::new("abc"));
+        assert_eq!(p.outcome(201), Outcome::Performed);
+        assert_eq!(p.outcome(304), Outcome::Other);
+        assert_eq!(p.outcome(409), Outcome::PreconditionFailed);
+        assert_eq!(p.outcome(412), Outcome::PreconditionFailed);
+    }
+}
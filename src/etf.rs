@@ -0,0 +1,393 @@
+//! The `etf` module implements the subset of Erlang's external term format
+//! needed to compute document revisions and replication ids the same way the
+//! CouchDB server does—i.e., the encoding `term_to_binary(Term, [{minor_version,
+//! 1}])` produces.
+
+use {serde_json, std};
+use serde::{Deserialize, Deserializer};
+use serde::de::{Error as DeError, MapAccess, SeqAccess, Visitor};
+
+const VERSION: u8 = 131;
+const NEW_FLOAT_EXT: u8 = 70;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+
+/// `Term` is an Erlang term.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Atom(&'static str),
+    Binary(Vec<u8>),
+    Float(f64),
+    Integer { negative: bool, magnitude: u64 },
+    List(Vec<Term>),
+
+    /// An Erlang string—i.e., a list of bytes, as `binary_to_list` produces.
+    String(Vec<u8>),
+
+    Tuple(Vec<Term>),
+}
+
+/// `Ejson` is a JSON value decoded into its Erlang representation, as the
+/// CouchDB server decodes it.
+///
+/// An object becomes a one-tuple containing a list of key-value pairs, in the
+/// same order as in the JSON text. A string becomes a binary, `true`, `false`,
+/// and `null` become atoms, and an array becomes a list.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ejson(pub Term);
+
+/// Decodes JSON text into its Erlang representation—e.g., an `Ejson` or
+/// `EjsonObject`.
+///
+/// This fails for an integer whose magnitude exceeds 64 bits, which serde_json
+/// would otherwise decode as a float and thus misrepresent. Floats decode
+/// exactly because the crate enables serde_json's `float_roundtrip` feature.
+///
+pub fn from_slice<'a, T: Deserialize<'a>>(json: &'a [u8]) -> Result<T, serde_json::Error> {
+    check_integer_literals(json)?;
+    serde_json::from_slice(json)
+}
+
+/// `EjsonObject` is a JSON object decoded into the members of its Erlang
+/// representation, in the same order as in the JSON text.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EjsonObject(pub Vec<(String, Term)>);
+
+impl Term {
+    pub fn integer(n: i64) -> Self {
+        Term::Integer {
+            negative: n < 0,
+            magnitude: if n < 0 { (n as u64).wrapping_neg() } else { n as u64 },
+        }
+    }
+
+    /// Encodes the term, including the leading version byte.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut out = vec![VERSION];
+        self.encode(&mut out);
+        out
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Term::Atom(name) => {
+                out.push(ATOM_EXT);
+                push_u16(out, name.len() as u16);
+                out.extend_from_slice(name.as_bytes());
+            }
+            Term::Binary(ref bytes) => {
+                out.push(BINARY_EXT);
+                push_u32(out, bytes.len() as u32);
+                out.extend_from_slice(bytes);
+            }
+            Term::Float(x) => {
+                out.push(NEW_FLOAT_EXT);
+                push_u64(out, x.to_bits());
+            }
+            Term::Integer { negative, magnitude } => {
+                if !negative && magnitude < 256 {
+                    out.push(SMALL_INTEGER_EXT);
+                    out.push(magnitude as u8);
+                } else if (!negative && magnitude <= i32::MAX as u64) ||
+                           (negative && magnitude <= 1 << 31)
+                {
+                    out.push(INTEGER_EXT);
+                    let n = if negative {
+                        (magnitude as i64).wrapping_neg()
+                    } else {
+                        magnitude as i64
+                    };
+                    push_u32(out, n as i32 as u32);
+                } else {
+                    let mut digits = Vec::new();
+                    let mut m = magnitude;
+                    while m != 0 {
+                        digits.push(m as u8);
+                        m >>= 8;
+                    }
+                    out.push(SMALL_BIG_EXT);
+                    out.push(digits.len() as u8);
+                    out.push(if negative { 1 } else { 0 });
+                    out.extend_from_slice(&digits);
+                }
+            }
+            Term::List(ref elements) => {
+                if !elements.is_empty() {
+                    out.push(LIST_EXT);
+                    push_u32(out, elements.len() as u32);
+                    for x in elements {
+                        x.encode(out);
+                    }
+                }
+                out.push(NIL_EXT);
+            }
+            Term::String(ref bytes) => {
+                if bytes.is_empty() {
+                    out.push(NIL_EXT);
+                } else if bytes.len() <= u16::MAX as usize {
+                    out.push(STRING_EXT);
+                    push_u16(out, bytes.len() as u16);
+                    out.extend_from_slice(bytes);
+                } else {
+                    let elements = bytes.iter().map(|&x| Term::integer(x as i64)).collect();
+                    Term::List(elements).encode(out);
+                }
+            }
+            Term::Tuple(ref elements) => {
+                out.push(SMALL_TUPLE_EXT);
+                out.push(elements.len() as u8);
+                for x in elements {
+                    x.encode(out);
+                }
+            }
+        }
+    }
+}
+
+fn push_u16(out: &mut Vec<u8>, n: u16) {
+    out.push((n >> 8) as u8);
+    out.push(n as u8);
+}
+
+fn push_u32(out: &mut Vec<u8>, n: u32) {
+    push_u16(out, (n >> 16) as u16);
+    push_u16(out, n as u16);
+}
+
+fn push_u64(out: &mut Vec<u8>, n: u64) {
+    push_u32(out, (n >> 32) as u32);
+    push_u32(out, n as u32);
+}
+
+// Scans the JSON text for integer literals that do not fit in 64 bits. Text
+// that is not valid JSON is left for serde_json to report.
+fn check_integer_literals(json: &[u8]) -> Result<(), serde_json::Error> {
+    let mut i = 0;
+    while i < json.len() {
+        match json[i] {
+            b'"' => {
+                i += 1;
+                while i < json.len() && json[i] != b'"' {
+                    if json[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            c if c == b'-' || c.is_ascii_digit() => {
+                let start = i;
+                i += 1;
+                while i < json.len() && json[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let is_integer = match json.get(i) {
+                    Some(c) => !b".eE".contains(c),
+                    None => true,
+                };
+                let token = std::str::from_utf8(&json[start..i]).unwrap();
+                if is_integer && token != "-" && token.parse::<i64>().is_err() && token.parse::<u64>().is_err() {
+                    return Err(serde_json::Error::custom(
+                        "Integers whose magnitude exceeds 64 bits are not supported",
+                    ));
+                }
+                while i < json.len() && (json[i].is_ascii_digit() || b".eE+-".contains(&json[i])) {
+                    i += 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(())
+}
+
+impl<'de> Deserialize<'de> for Ejson {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct EjsonVisitor;
+
+        impl<'de> Visitor<'de> for EjsonVisitor {
+            type Value = Ejson;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+                write!(f, "a JSON value")
+            }
+
+            fn visit_bool<E: DeError>(self, v: bool) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::Atom(if v { "true" } else { "false" })))
+            }
+
+            fn visit_i64<E: DeError>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::integer(v)))
+            }
+
+            fn visit_u64<E: DeError>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::Integer {
+                    negative: false,
+                    magnitude: v,
+                }))
+            }
+
+            fn visit_f64<E: DeError>(self, v: f64) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::Float(v)))
+            }
+
+            fn visit_str<E: DeError>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::Binary(Vec::from(v.as_bytes()))))
+            }
+
+            fn visit_unit<E: DeError>(self) -> Result<Self::Value, E> {
+                Ok(Ejson(Term::Atom("null")))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut elements = Vec::new();
+                while let Some(Ejson(x)) = seq.next_element()? {
+                    elements.push(x);
+                }
+                Ok(Ejson(Term::List(elements)))
+            }
+
+            fn visit_map<A>(self, map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                ObjectVisitor.visit_map(map).map(|x| Ejson(x.into_term()))
+            }
+        }
+
+        deserializer.deserialize_any(EjsonVisitor)
+    }
+}
+
+impl EjsonObject {
+    /// Converts the object into its Erlang representation.
+    pub fn into_term(self) -> Term {
+        Term::Tuple(vec![
+            Term::List(
+                self.0
+                    .into_iter()
+                    .map(|(k, v)| Term::Tuple(vec![Term::Binary(k.into_bytes()), v]))
+                    .collect(),
+            ),
+        ])
+    }
+}
+
+struct ObjectVisitor;
+
+impl<'de> Visitor<'de> for ObjectVisitor {
+    type Value = EjsonObject;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "a JSON object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut members = Vec::new();
+        while let Some((k, Ejson(v))) = map.next_entry::<String, Ejson>()? {
+            members.push((k, v));
+        }
+        Ok(EjsonObject(members))
+    }
+}
+
+impl<'de> Deserialize<'de> for EjsonObject {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(ObjectVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn encode_scalars() {
+        assert_eq!(Term::Atom("true").to_binary(), b"\x83d\x00\x04true");
+        assert_eq!(Term::Binary(b"ab".to_vec()).to_binary(), b"\x83m\x00\x00\x00\x02ab");
+        assert_eq!(
+            Term::Float(2.5).to_binary(),
+            b"\x83F\x40\x04\x00\x00\x00\x00\x00\x00"
+        );
+        assert_eq!(Term::integer(255).to_binary(), b"\x83a\xff");
+        assert_eq!(Term::integer(256).to_binary(), b"\x83b\x00\x00\x01\x00");
+        assert_eq!(Term::integer(-1).to_binary(), b"\x83b\xff\xff\xff\xff");
+        assert_eq!(
+            Term::integer(-2147483648).to_binary(),
+            b"\x83b\x80\x00\x00\x00"
+        );
+        assert_eq!(
+            Term::integer(2147483648).to_binary(),
+            b"\x83n\x04\x00\x00\x00\x00\x80"
+        );
+        assert_eq!(
+            Term::integer(-2147483649).to_binary(),
+            b"\x83n\x04\x01\x01\x00\x00\x80"
+        );
+    }
+
+    #[test]
+    fn encode_compounds() {
+        assert_eq!(Term::List(vec![]).to_binary(), b"\x83j");
+        assert_eq!(
+            Term::List(vec![Term::integer(1), Term::integer(2)]).to_binary(),
+            b"\x83l\x00\x00\x00\x02a\x01a\x02j"
+        );
+        assert_eq!(
+            Term::Tuple(vec![Term::integer(1), Term::Atom("a")]).to_binary(),
+            b"\x83h\x02a\x01d\x00\x01a"
+        );
+        assert_eq!(Term::String(vec![]).to_binary(), b"\x83j");
+        assert_eq!(Term::String(b"ab".to_vec()).to_binary(), b"\x83k\x00\x02ab");
+    }
+
+    #[test]
+    fn ejson_preserves_member_order() {
+        let Ejson(got) = serde_json::from_str(r#"{"b":[true,null],"a":1.5}"#).unwrap();
+        let expected = Term::Tuple(vec![
+            Term::List(vec![
+                Term::Tuple(vec![
+                    Term::Binary(b"b".to_vec()),
+                    Term::List(vec![Term::Atom("true"), Term::Atom("null")]),
+                ]),
+                Term::Tuple(vec![Term::Binary(b"a".to_vec()), Term::Float(1.5)]),
+            ]),
+        ]);
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn ejson_decodes_floats_exactly() {
+        let Ejson(got) = from_slice(b"1.0729132283643165e-38").unwrap();
+        assert_eq!(got, Term::Float("1.0729132283643165e-38".parse().unwrap()));
+    }
+
+    #[test]
+    fn ejson_rejects_integers_exceeding_64_bits() {
+        from_slice::<Ejson>(b"[18446744073709551615, -9223372036854775808]").unwrap();
+        from_slice::<Ejson>(br#"{"a":"18446744073709551616","b":1.8446744073709551616e19}"#).unwrap();
+        from_slice::<Ejson>(b"[18446744073709551616]").unwrap_err();
+        from_slice::<Ejson>(br#"{"a\"":-9223372036854775809}"#).unwrap_err();
+    }
+}
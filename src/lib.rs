@@ -34,32 +34,49 @@
 //! [reqwest](https://crates.io/crates/reqwest).
 
 extern crate base64;
+extern crate flate2;
+extern crate md5;
 extern crate mime;
+#[cfg(feature = "proptest")]
+extern crate proptest;
 extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
-#[cfg(test)]
-#[macro_use]
+#[cfg_attr(test, macro_use)]
 extern crate serde_json;
 extern crate tempdir;
 extern crate url;
 extern crate uuid;
 
 pub mod attachment;
+pub mod bulk;
+pub mod cache;
+pub mod etag;
+pub mod mango;
+pub mod multipart;
 pub mod path;
+pub mod range;
+pub mod replication;
 pub mod testing;
 
+mod changes;
 mod database;
 mod error;
+mod etf;
 mod nok;
 mod revision;
+mod revision_tree;
+mod revisions;
 mod root;
 
-pub use attachment::Attachment;
+pub use attachment::{Attachment, ContentReader, Digest, Md5Digester};
+pub use changes::{ChangeResult, ChangeRevision, Changes, Sequence};
 pub use database::Database;
 pub use error::Error;
 pub use nok::Nok;
 pub use path::*;
-pub use revision::Revision;
+pub use revision::{Revision, new_revision, winning_revision};
+pub use revision_tree::{MergeOutcome, RevisionTree};
+pub use revisions::{RevisionInfo, RevisionStatus, Revisions};
 pub use root::{Root, Vendor, Version};
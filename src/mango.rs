@@ -0,0 +1,724 @@
+//! The `mango` module provides types for constructing and parsing Mango
+//! selectors, which CouchDB 2.0 and later use for `POST /{db}/_find` queries
+//! and selector-filtered replications.
+//!
+//! # Summary
+//!
+//! * `Selector` is a Mango selector. It implements `Serialize`, producing the
+//!   JSON the `selector` field of a `_find` request accepts, and
+//!   `Deserialize`, parsing existing selectors back.
+//!
+//! * `FieldPath` specifies a document field, possibly nested—e.g.,
+//!   `imdb.rating`. Its methods construct a `Selector` that applies a
+//!   `Condition` to the field.
+//!
+//! * The `Selector::and`, `Selector::or`, and `Selector::nor` methods combine
+//!   selectors, and the `!` operator negates one.
+//!
+//! # Remarks
+//!
+//! A `Selector` serializes to the explicit form into which CouchDB normalizes
+//! selectors. Every condition has an operator—e.g., `{"year": {"$eq": 2010}}`
+//! instead of `{"year": 2010}`—and multiple selectors combine via an explicit
+//! `$and` operator. Parsing accepts the implicit forms, too, so a selector
+//! that is not in explicit form does not serialize back to the same JSON,
+//! though it does to an equivalent one.
+//!
+//! A dot in a field path separates the names of nested fields. A field name
+//! that contains a dot escapes it with a backslash—e.g., `my\.field`.
+//!
+//! An empty field path denotes the value itself, which is useful within
+//! `$elemMatch`, `$allMatch`, and `$keyMapMatch`, where the selector applies
+//! to each element.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate couchdb;
+//! extern crate serde_json;
+//!
+//! use couchdb::mango::{FieldPath, Selector, field};
+//!
+//! let selector = Selector::and(vec![
+//!     field("director").eq("Lars von Trier"),
+//!     field("imdb.rating").gte(8),
+//!     field("genre").elem_match(FieldPath::root().is_in(vec!["Drama", "Horror"])),
+//! ]);
+//!
+//! let expected = r#"{"$and":[{"director":{"$eq":"Lars von Trier"}},{"imdb.rating":{"$gte":8}},{"genre":{"$elemMatch":{"$in":["Drama","Horror"]}}}]}"#;
+//! assert_eq!(serde_json::to_string(&selector).unwrap(), expected);
+//!
+//! let parsed: Selector = serde_json::from_str(expected).unwrap();
+//! assert_eq!(parsed, selector);
+//! ```
+
+use {Error, serde, std};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeMap;
+use serde_json::{Map, Value};
+
+/// `Selector` is a Mango selector.
+///
+/// # Remarks
+///
+/// See the [module-level documentation](index.html) for how a `Selector`
+/// serializes and parses.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Selector {
+    /// Matches if all the selectors match—`$and`.
+    And(Vec<Selector>),
+
+    /// Matches if any of the selectors match—`$or`.
+    Or(Vec<Selector>),
+
+    /// Matches if none of the selectors match—`$nor`.
+    Nor(Vec<Selector>),
+
+    /// Matches if the selector does not match—`$not`.
+    Not(Box<Selector>),
+
+    /// Matches if the field satisfies the condition.
+    Field(FieldPath, Condition),
+}
+
+/// `Condition` is a condition that a field must satisfy.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    /// `$eq`
+    Eq(Value),
+
+    /// `$ne`
+    Ne(Value),
+
+    /// `$lt`
+    Lt(Value),
+
+    /// `$lte`
+    Lte(Value),
+
+    /// `$gt`
+    Gt(Value),
+
+    /// `$gte`
+    Gte(Value),
+
+    /// `$in`—the field equals any of the values.
+    In(Vec<Value>),
+
+    /// `$nin`—the field equals none of the values.
+    Nin(Vec<Value>),
+
+    /// `$exists`—the field exists or does not exist.
+    Exists(bool),
+
+    /// `$type`—the field is of the JSON type.
+    Type(JsonType),
+
+    /// `$regex`—the field is a string matching the Erlang regular
+    /// expression.
+    Regex(String),
+
+    /// `$mod`—the field is an integer with the remainder when divided by the
+    /// divisor.
+    Mod { divisor: i64, remainder: i64 },
+
+    /// `$size`—the field is an array of the length.
+    Size(u64),
+
+    /// `$beginsWith`—the field is a string with the prefix—CouchDB 3.3 and
+    /// later.
+    BeginsWith(String),
+
+    /// `$all`—the field is an array containing all the values.
+    All(Vec<Value>),
+
+    /// `$elemMatch`—the field is an array with an element matching the
+    /// selector.
+    ElemMatch(Box<Selector>),
+
+    /// `$allMatch`—the field is an array whose elements all match the
+    /// selector.
+    AllMatch(Box<Selector>),
+
+    /// `$keyMapMatch`—the field is an object with a key matching the
+    /// selector.
+    KeyMapMatch(Box<Selector>),
+}
+
+/// `JsonType` is the type of a JSON value, as the `$type` operator specifies
+/// it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum JsonType {
+    Null,
+    Boolean,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+/// `FieldPath` specifies a document field, possibly nested.
+///
+/// # Summary
+///
+/// * `FieldPath` is a sequence of field names, which the `parse` method and
+///   the `From` implementations split at each unescaped dot.
+///
+/// * `FieldPath` implements `Display`, escaping any dot within a field name.
+///
+/// * `FieldPath` has methods, such as `eq` and `exists`, that construct a
+///   `Selector` applying a condition to the field.
+///
+/// # Example
+///
+/// ```rust
+/// use couchdb::mango::FieldPath;
+///
+/// let path = FieldPath::parse(r"imdb.my\.rating");
+/// assert_eq!(path.segments(), &[String::from("imdb"), String::from("my.rating")]);
+/// assert_eq!(path, FieldPath::root().with_segment("imdb").with_segment("my.rating"));
+/// assert_eq!(path.to_string(), r"imdb.my\.rating");
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FieldPath {
+    segments: Vec<String>,
+}
+
+/// Constructs a field path by parsing a string—i.e., `FieldPath::parse`.
+pub fn field<T: AsRef<str>>(path: T) -> FieldPath {
+    FieldPath::parse(path.as_ref())
+}
+
+impl Selector {
+    /// Constructs a selector that matches if all the selectors match.
+    pub fn and(selectors: Vec<Selector>) -> Self {
+        Selector::And(selectors)
+    }
+
+    /// Constructs a selector that matches if any of the selectors match.
+    pub fn or(selectors: Vec<Selector>) -> Self {
+        Selector::Or(selectors)
+    }
+
+    /// Constructs a selector that matches if none of the selectors match.
+    pub fn nor(selectors: Vec<Selector>) -> Self {
+        Selector::Nor(selectors)
+    }
+
+    /// Parses a selector from JSON, which may be in explicit or implicit
+    /// form.
+    pub fn from_value(value: &Value) -> Result<Self, Error> {
+        match *value {
+            Value::Object(ref m) => parse_object(m, &FieldPath::root()),
+            _ => Err(Error::bad_selector("Selector is not a JSON object")),
+        }
+    }
+}
+
+impl Condition {
+    /// Returns the condition's operator—e.g., `$eq`.
+    pub fn operator(&self) -> &'static str {
+        match *self {
+            Condition::Eq(..) => "$eq",
+            Condition::Ne(..) => "$ne",
+            Condition::Lt(..) => "$lt",
+            Condition::Lte(..) => "$lte",
+            Condition::Gt(..) => "$gt",
+            Condition::Gte(..) => "$gte",
+            Condition::In(..) => "$in",
+            Condition::Nin(..) => "$nin",
+            Condition::Exists(..) => "$exists",
+            Condition::Type(..) => "$type",
+            Condition::Regex(..) => "$regex",
+            Condition::Mod { .. } => "$mod",
+            Condition::Size(..) => "$size",
+            Condition::BeginsWith(..) => "$beginsWith",
+            Condition::All(..) => "$all",
+            Condition::ElemMatch(..) => "$elemMatch",
+            Condition::AllMatch(..) => "$allMatch",
+            Condition::KeyMapMatch(..) => "$keyMapMatch",
+        }
+    }
+}
+
+impl JsonType {
+    /// Borrows the type's name as the `$type` operator specifies it—e.g.,
+    /// `number`.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            JsonType::Null => "null",
+            JsonType::Boolean => "boolean",
+            JsonType::Number => "number",
+            JsonType::String => "string",
+            JsonType::Array => "array",
+            JsonType::Object => "object",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "null" => Some(JsonType::Null),
+            "boolean" => Some(JsonType::Boolean),
+            "number" => Some(JsonType::Number),
+            "string" => Some(JsonType::String),
+            "array" => Some(JsonType::Array),
+            "object" => Some(JsonType::Object),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for JsonType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FieldPath {
+    /// Constructs the empty path, which denotes the value itself—e.g., each
+    /// element within `$elemMatch`.
+    pub fn root() -> Self {
+        FieldPath { segments: Vec::new() }
+    }
+
+    /// Parses a path, splitting it at each dot not escaped with a backslash.
+    ///
+    /// The empty string is the empty path.
+    ///
+    pub fn parse(s: &str) -> Self {
+
+        if s.is_empty() {
+            return FieldPath::root();
+        }
+
+        let mut segments = Vec::new();
+        let mut segment = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'.') => {
+                    segment.push('.');
+                    chars.next();
+                }
+                '.' => segments.push(std::mem::take(&mut segment)),
+                _ => segment.push(c),
+            }
+        }
+
+        segments.push(segment);
+        FieldPath { segments: segments }
+    }
+
+    /// Appends a field name, which is not parsed and so may contain dots.
+    pub fn with_segment<T: Into<String>>(mut self, name: T) -> Self {
+        self.segments.push(name.into());
+        self
+    }
+
+    /// Borrows the path's field names.
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Returns whether the path is the empty path.
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Constructs a selector that applies the condition to the field.
+    pub fn matches(self, condition: Condition) -> Selector {
+        Selector::Field(self, condition)
+    }
+
+    /// Constructs an `$eq` selector.
+    pub fn eq<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Eq(value.into()))
+    }
+
+    /// Constructs a `$ne` selector.
+    pub fn ne<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Ne(value.into()))
+    }
+
+    /// Constructs an `$lt` selector.
+    pub fn lt<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Lt(value.into()))
+    }
+
+    /// Constructs an `$lte` selector.
+    pub fn lte<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Lte(value.into()))
+    }
+
+    /// Constructs a `$gt` selector.
+    pub fn gt<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Gt(value.into()))
+    }
+
+    /// Constructs a `$gte` selector.
+    pub fn gte<T: Into<Value>>(self, value: T) -> Selector {
+        self.matches(Condition::Gte(value.into()))
+    }
+
+    /// Constructs an `$in` selector.
+    pub fn is_in<T: Into<Value>>(self, values: Vec<T>) -> Selector {
+        self.matches(Condition::In(values.into_iter().map(Into::into).collect()))
+    }
+
+    /// Constructs a `$nin` selector.
+    pub fn not_in<T: Into<Value>>(self, values: Vec<T>) -> Selector {
+        self.matches(Condition::Nin(values.into_iter().map(Into::into).collect()))
+    }
+
+    /// Constructs an `$exists` selector.
+    pub fn exists(self, exists: bool) -> Selector {
+        self.matches(Condition::Exists(exists))
+    }
+
+    /// Constructs a `$type` selector.
+    pub fn has_type(self, json_type: JsonType) -> Selector {
+        self.matches(Condition::Type(json_type))
+    }
+
+    /// Constructs a `$regex` selector.
+    pub fn regex<T: Into<String>>(self, pattern: T) -> Selector {
+        self.matches(Condition::Regex(pattern.into()))
+    }
+
+    /// Constructs a `$mod` selector.
+    pub fn modulo(self, divisor: i64, remainder: i64) -> Selector {
+        self.matches(Condition::Mod {
+            divisor: divisor,
+            remainder: remainder,
+        })
+    }
+
+    /// Constructs a `$size` selector.
+    pub fn size(self, size: u64) -> Selector {
+        self.matches(Condition::Size(size))
+    }
+
+    /// Constructs a `$beginsWith` selector.
+    pub fn begins_with<T: Into<String>>(self, prefix: T) -> Selector {
+        self.matches(Condition::BeginsWith(prefix.into()))
+    }
+
+    /// Constructs an `$all` selector.
+    pub fn all<T: Into<Value>>(self, values: Vec<T>) -> Selector {
+        self.matches(Condition::All(values.into_iter().map(Into::into).collect()))
+    }
+
+    /// Constructs an `$elemMatch` selector.
+    pub fn elem_match(self, selector: Selector) -> Selector {
+        self.matches(Condition::ElemMatch(Box::new(selector)))
+    }
+
+    /// Constructs an `$allMatch` selector.
+    pub fn all_match(self, selector: Selector) -> Selector {
+        self.matches(Condition::AllMatch(Box::new(selector)))
+    }
+
+    /// Constructs a `$keyMapMatch` selector.
+    pub fn key_map_match(self, selector: Selector) -> Selector {
+        self.matches(Condition::KeyMapMatch(Box::new(selector)))
+    }
+}
+
+impl std::fmt::Display for FieldPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i != 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(&segment.replace('.', "\\."))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a str> for FieldPath {
+    fn from(s: &'a str) -> Self {
+        FieldPath::parse(s)
+    }
+}
+
+impl From<String> for FieldPath {
+    fn from(s: String) -> Self {
+        FieldPath::parse(&s)
+    }
+}
+
+impl std::ops::Not for Selector {
+    type Output = Selector;
+
+    /// Constructs a selector that matches if the selector does not match.
+    fn not(self) -> Selector {
+        Selector::Not(Box::new(self))
+    }
+}
+
+impl Serialize for Selector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+
+        if let Selector::Field(ref path, ref condition) = *self {
+            if path.is_root() {
+                return condition.serialize(serializer);
+            }
+        }
+
+        let mut map = serializer.serialize_map(Some(1))?;
+        match *self {
+            Selector::And(ref x) => map.serialize_entry("$and", x)?,
+            Selector::Or(ref x) => map.serialize_entry("$or", x)?,
+            Selector::Nor(ref x) => map.serialize_entry("$nor", x)?,
+            Selector::Not(ref x) => map.serialize_entry("$not", x)?,
+            Selector::Field(ref path, ref condition) => map.serialize_entry(&path.to_string(), condition)?,
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Selector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Selector::from_value(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(1))?;
+        let op = self.operator();
+        match *self {
+            Condition::Eq(ref x) |
+            Condition::Ne(ref x) |
+            Condition::Lt(ref x) |
+            Condition::Lte(ref x) |
+            Condition::Gt(ref x) |
+            Condition::Gte(ref x) => map.serialize_entry(op, x)?,
+            Condition::In(ref x) | Condition::Nin(ref x) | Condition::All(ref x) => map.serialize_entry(op, x)?,
+            Condition::Exists(x) => map.serialize_entry(op, &x)?,
+            Condition::Type(x) => map.serialize_entry(op, x.as_str())?,
+            Condition::Regex(ref x) | Condition::BeginsWith(ref x) => map.serialize_entry(op, x)?,
+            Condition::Mod { divisor, remainder } => map.serialize_entry(op, &[divisor, remainder])?,
+            Condition::Size(x) => map.serialize_entry(op, &x)?,
+            Condition::ElemMatch(ref x) | Condition::AllMatch(ref x) | Condition::KeyMapMatch(ref x) => {
+                map.serialize_entry(op, x)?
+            }
+        }
+        map.end()
+    }
+}
+
+// Parses a JSON object as the selector for the given path. Each member is a
+// selector, and the object is their conjunction.
+fn parse_object(m: &Map<String, Value>, path: &FieldPath) -> Result<Selector, Error> {
+    let mut selectors = m.iter()
+        .map(|(k, v)| parse_member(k, v, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if selectors.len() == 1 {
+        Ok(selectors.pop().unwrap())
+    } else {
+        Ok(Selector::And(selectors))
+    }
+}
+
+fn parse_member(key: &str, value: &Value, path: &FieldPath) -> Result<Selector, Error> {
+
+    let parse_list = |value: &Value| -> Result<Vec<Selector>, Error> {
+        value
+            .as_array()
+            .ok_or_else(|| Error::bad_selector("Combination operator argument is not an array"))?
+            .iter()
+            .map(|x| {
+                x.as_object()
+                    .ok_or_else(|| Error::bad_selector("Selector is not a JSON object"))
+                    .and_then(|m| parse_object(m, path))
+            })
+            .collect()
+    };
+
+    match key {
+        "$and" => Ok(Selector::And(parse_list(value)?)),
+        "$or" => Ok(Selector::Or(parse_list(value)?)),
+        "$nor" => Ok(Selector::Nor(parse_list(value)?)),
+        "$not" => {
+            let m = value.as_object().ok_or_else(|| {
+                Error::bad_selector("$not argument is not a JSON object")
+            })?;
+            Ok(!parse_object(m, path)?)
+        }
+        _ if key.starts_with('$') => Ok(Selector::Field(path.clone(), parse_condition(key, value)?)),
+        _ => {
+            let mut path = path.clone();
+            path.segments.extend(FieldPath::parse(key).segments);
+            match *value {
+                Value::Object(ref m) if !m.is_empty() => parse_object(m, &path),
+                _ => Ok(Selector::Field(path, Condition::Eq(value.clone()))),
+            }
+        }
+    }
+}
+
+fn parse_condition(op: &str, value: &Value) -> Result<Condition, Error> {
+
+    fn array(value: &Value) -> Result<Vec<Value>, Error> {
+        value.as_array().cloned().ok_or_else(|| {
+            Error::bad_selector("Operator argument is not an array")
+        })
+    }
+
+    fn string(value: &Value) -> Result<String, Error> {
+        value.as_str().map(String::from).ok_or_else(|| {
+            Error::bad_selector("Operator argument is not a string")
+        })
+    }
+
+    fn selector(value: &Value) -> Result<Box<Selector>, Error> {
+        match *value {
+            Value::Object(ref m) => parse_object(m, &FieldPath::root()).map(Box::new),
+            _ => Err(Error::bad_selector("Operator argument is not a JSON object")),
+        }
+    }
+
+    Ok(match op {
+        "$eq" => Condition::Eq(value.clone()),
+        "$ne" => Condition::Ne(value.clone()),
+        "$lt" => Condition::Lt(value.clone()),
+        "$lte" => Condition::Lte(value.clone()),
+        "$gt" => Condition::Gt(value.clone()),
+        "$gte" => Condition::Gte(value.clone()),
+        "$in" => Condition::In(array(value)?),
+        "$nin" => Condition::Nin(array(value)?),
+        "$all" => Condition::All(array(value)?),
+        "$exists" => Condition::Exists(value.as_bool().ok_or_else(|| {
+            Error::bad_selector("$exists argument is not a boolean")
+        })?),
+        "$type" => Condition::Type(value.as_str().and_then(JsonType::parse).ok_or_else(|| {
+            Error::bad_selector("$type argument is not a JSON type name")
+        })?),
+        "$regex" => Condition::Regex(string(value)?),
+        "$beginsWith" => Condition::BeginsWith(string(value)?),
+        "$mod" => {
+            let args = array(value)?;
+            match (args.first().and_then(Value::as_i64), args.get(1).and_then(Value::as_i64)) {
+                (Some(divisor), Some(remainder)) if args.len() == 2 => Condition::Mod {
+                    divisor: divisor,
+                    remainder: remainder,
+                },
+                _ => return Err(Error::bad_selector("$mod argument is not a pair of integers")),
+            }
+        }
+        "$size" => Condition::Size(value.as_u64().ok_or_else(|| {
+            Error::bad_selector("$size argument is not a non-negative integer")
+        })?),
+        "$elemMatch" => Condition::ElemMatch(selector(value)?),
+        "$allMatch" => Condition::AllMatch(selector(value)?),
+        "$keyMapMatch" => Condition::KeyMapMatch(selector(value)?),
+        _ => return Err(Error::bad_selector("Operator is unknown")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn field_path_parses_escaped_dots() {
+        assert_eq!(FieldPath::parse("").segments(), &[] as &[String]);
+        assert_eq!(FieldPath::parse("alpha").segments(), &["alpha"]);
+        assert_eq!(FieldPath::parse("alpha.bravo").segments(), &["alpha", "bravo"]);
+        assert_eq!(FieldPath::parse(r"alpha\.bravo").segments(), &["alpha.bravo"]);
+        assert_eq!(FieldPath::parse(r"alpha\bravo").segments(), &[r"alpha\bravo"]);
+        assert_eq!(FieldPath::parse("alpha..bravo").segments(), &["alpha", "", "bravo"]);
+
+        for s in &["alpha", "alpha.bravo", r"alpha\.bravo.charlie"] {
+            assert_eq!(FieldPath::parse(s).to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn selector_serializes_every_operator() {
+        let selector = Selector::or(vec![
+            Selector::nor(vec![field("a").ne(1), field("b").lt(2), field("c").lte(3)]),
+            !field("d").gt(4),
+            field("e").not_in(vec![5, 6]),
+            field("f").has_type(JsonType::String),
+            field("g").regex("^h"),
+            field("i").modulo(4, 1),
+            field("j").size(3),
+            field("k").begins_with("l"),
+            field("m").all(vec!["n", "o"]),
+            field("p").all_match(field("q").exists(false)),
+            field("r").key_map_match(FieldPath::root().eq("s")),
+        ]);
+
+        let expected = json!({"$or": [
+            {"$nor": [{"a": {"$ne": 1}}, {"b": {"$lt": 2}}, {"c": {"$lte": 3}}]},
+            {"$not": {"d": {"$gt": 4}}},
+            {"e": {"$nin": [5, 6]}},
+            {"f": {"$type": "string"}},
+            {"g": {"$regex": "^h"}},
+            {"i": {"$mod": [4, 1]}},
+            {"j": {"$size": 3}},
+            {"k": {"$beginsWith": "l"}},
+            {"m": {"$all": ["n", "o"]}},
+            {"p": {"$allMatch": {"q": {"$exists": false}}}},
+            {"r": {"$keyMapMatch": {"$eq": "s"}}},
+        ]});
+
+        assert_eq!(serde_json::to_value(&selector).unwrap(), expected);
+        assert_eq!(Selector::from_value(&expected).unwrap(), selector);
+    }
+
+    #[test]
+    fn selector_parses_implicit_form() {
+        let source = json!({
+            "alpha": "bravo",
+            "charlie": {"delta": 1, "$exists": true},
+            r"echo\.foxtrot": {"$gt": 2, "$lt": 3},
+            "golf": {"$not": {"$eq": {}}},
+            "hotel": {"$or": [{"$eq": 4}, {"india": 5}]},
+            "juliet": {},
+        });
+
+        let expected = Selector::and(vec![
+            field("alpha").eq("bravo"),
+            Selector::and(vec![field("charlie").exists(true), field("charlie.delta").eq(1)]),
+            Selector::and(vec![
+                FieldPath::root().with_segment("echo.foxtrot").gt(2),
+                FieldPath::root().with_segment("echo.foxtrot").lt(3),
+            ]),
+            !field("golf").eq(json!({})),
+            Selector::or(vec![field("hotel").eq(4), field("hotel.india").eq(5)]),
+            field("juliet").eq(json!({})),
+        ]);
+
+        assert_eq!(Selector::from_value(&source).unwrap(), expected);
+    }
+
+    #[test]
+    fn selector_parsing_rejects_bad_operators() {
+        for source in &[
+            json!([]),
+            json!({"$and": {}}),
+            json!({"alpha": {"$bogus": 1}}),
+            json!({"alpha": {"$in": 1}}),
+            json!({"alpha": {"$exists": "yes"}}),
+            json!({"alpha": {"$type": "integer"}}),
+            json!({"alpha": {"$mod": [1]}}),
+            json!({"alpha": {"$size": -1}}),
+            json!({"alpha": {"$elemMatch": []}}),
+        ]
+        {
+            match Selector::from_value(source) {
+                Err(Error::BadSelector { .. }) => {}
+                x => panic!("Got unexpected result {:?} for {}", x, source),
+            }
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use {Attachment, AttachmentName, Error, serde, serde_json, std};
+use mime::{self, Mime};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use super::parser::{self, Part};
+
+/// `RelatedDocument` contains a document and its attachments decoded from a
+/// `multipart/related` body.
+///
+/// # Summary
+///
+/// * `RelatedDocument` decodes the response body the CouchDB server sends for
+///   `GET /{db}/{doc}?attachments=true` when the request has the header
+///   `Accept: multipart/related`.
+///
+/// * Each attachment in a `RelatedDocument` originates from the server. An
+///   attachment the server sent as a part has content, whereas an attachment
+///   the server sent as a stub (e.g., owing to the `atts_since` query
+///   parameter) has none.
+///
+/// * `RelatedDocument` matches each part to its attachment by the part's
+///   filename. A part without a filename matches the next attachment in the
+///   order in which the attachments appear in the document's JSON. A part whose
+///   filename matches no attachment is an error. `RelatedDocument` checks each
+///   part's length against the attachment's length.
+///
+/// * An attachment sent gzip-encoded (e.g., owing to the `att_encoding_info`
+///   query parameter) has its compressed content available via the
+///   `Attachment::encoded_content` method.
+///
+/// # Remarks
+///
+/// The document type, `T`, is deserialized from the document's JSON part as is.
+/// If `T` captures the document's `_attachments` field, then those attachments
+/// are content-less stubs. Use the `RelatedDocument::attachments` method to
+/// obtain the attachments with content.
+///
+/// # Example
+///
+/// ```rust
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let content_type = "multipart/related; boundary=\"abc123\"".parse().unwrap();
+///
+/// let body = b"--abc123\r\n\
+///     Content-Type: application/json\r\n\
+///     \r\n\
+///     {\"_id\":\"secret\",\"_rev\":\"2-c1c6c44c4bc3c9344b037c8690468605\",\
+///     \"_attachments\":{\"recipe.txt\":{\"content_type\":\"text/plain\",\
+///     \"revpos\":2,\"digest\":\"md5-HV9aXJdEnu0xnMQYTKgOFA==\",\"length\":11,\
+///     \"follows\":true}}}\r\n\
+///     --abc123\r\n\
+///     Content-Disposition: attachment; filename=\"recipe.txt\"\r\n\
+///     Content-Type: text/plain\r\n\
+///     Content-Length: 11\r\n\
+///     \r\n\
+///     Take apples\r\n\
+///     --abc123--";
+///
+/// let doc = couchdb::multipart::RelatedDocument::<serde_json::Value>::decode(
+///     &content_type,
+///     body,
+/// ).unwrap();
+///
+/// assert_eq!(doc.document()["_id"], "secret");
+///
+/// let att = &doc.attachments()[&couchdb::AttachmentName::new("recipe.txt")];
+/// assert!(att.is_server_origin());
+/// assert_eq!(att.content(), Some(b"Take apples".as_ref()));
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelatedDocument<T> {
+    document: T,
+    attachments: BTreeMap<AttachmentName, Attachment>,
+}
+
+impl<T: DeserializeOwned> RelatedDocument<T> {
+    /// Decodes a `multipart/related` body.
+    ///
+    /// The content type must be the MIME type the server sent in the
+    /// response's `Content-Type` header, which specifies the multipart
+    /// boundary.
+    ///
+    pub fn decode(content_type: &Mime, body: &[u8]) -> Result<Self, Error> {
+        let boundary = parser::boundary(content_type, "related")?;
+        let parts = parser::parse(boundary, body)?;
+        decode_parts(&parts)
+    }
+}
+
+// Constructs a document from the parts of a multipart/related body.
+pub fn decode_parts<T: DeserializeOwned>(parts: &[Part]) -> Result<RelatedDocument<T>, Error> {
+
+    let (doc_part, att_parts) = parts.split_first().ok_or(Error::bad_multipart(
+        "Body has no document part",
+    ))?;
+
+    match doc_part.content_type()? {
+        Some(ref x) if x.type_() != mime::APPLICATION || x.subtype() != mime::JSON => {
+            return Err(Error::bad_multipart("Document part is not JSON"));
+        }
+        _ => {}
+    }
+
+    let document = serde_json::from_slice(doc_part.body()).map_err(|e| {
+        Error::from(("Failed to deserialize document JSON", e))
+    })?;
+
+    let stubs: Stubs = serde_json::from_slice(doc_part.body()).map_err(|e| {
+        Error::from(("Failed to deserialize document attachments", e))
+    })?;
+
+    let mut attachments = BTreeMap::new();
+    let mut pending = Vec::new();
+
+    for (att_name, stub) in stubs.attachments.0 {
+        if stub.follows {
+            pending.push((att_name, stub.attachment));
+        } else {
+            attachments.insert(AttachmentName::from(att_name), stub.attachment);
+        }
+    }
+
+    for part in att_parts {
+
+        if pending.is_empty() {
+            return Err(Error::bad_multipart("Body has more parts than attachments"));
+        }
+
+        let index = match part.filename() {
+            None => 0,
+            Some(filename) => {
+                pending
+                    .iter()
+                    .position(|(x, _)| *x == filename)
+                    .ok_or(Error::bad_multipart(
+                        "Part filename matches no pending attachment",
+                    ))?
+            }
+        };
+        let (att_name, stub) = pending.remove(index);
+
+        let attachment = stub.with_multipart_content(
+            Vec::from(part.body()),
+            part.header("Content-Encoding"),
+        )?;
+        attachments.insert(AttachmentName::from(att_name), attachment);
+    }
+
+    if !pending.is_empty() {
+        return Err(Error::bad_multipart("Body is missing attachment parts"));
+    }
+
+    Ok(RelatedDocument {
+        document: document,
+        attachments: attachments,
+    })
+}
+
+impl<T> RelatedDocument<T> {
+    /// Borrows the document.
+    pub fn document(&self) -> &T {
+        &self.document
+    }
+
+    /// Borrows the document's attachments.
+    pub fn attachments(&self) -> &BTreeMap<AttachmentName, Attachment> {
+        &self.attachments
+    }
+
+    /// Converts the `RelatedDocument` into its document and attachments.
+    pub fn into_parts(self) -> (T, BTreeMap<AttachmentName, Attachment>) {
+        (self.document, self.attachments)
+    }
+}
+
+#[derive(Deserialize)]
+struct Stubs {
+    #[serde(default, rename = "_attachments")]
+    attachments: OrderedStubs,
+}
+
+// OrderedStubs retains the order in which the attachments appear in the JSON,
+// which is the order of the attachment parts.
+#[derive(Default)]
+struct OrderedStubs(Vec<(String, Stub)>);
+
+struct Stub {
+    attachment: Attachment,
+    follows: bool,
+}
+
+impl<'a> Deserialize<'a> for OrderedStubs {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        struct Visitor;
+
+        impl<'b> serde::de::Visitor<'b> for Visitor {
+            type Value = OrderedStubs;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+                write!(f, "a JSON object containing CouchDB attachments")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'b>,
+            {
+                let mut stubs = Vec::new();
+                while let Some((att_name, value)) = map.next_entry::<String, serde_json::Value>()? {
+                    let follows = value.get("follows") == Some(&serde_json::Value::Bool(true));
+                    let attachment = Attachment::deserialize(value).map_err(serde::de::Error::custom)?;
+                    stubs.push((
+                        att_name,
+                        Stub {
+                            attachment: attachment,
+                            follows: follows,
+                        },
+                    ));
+                }
+                Ok(OrderedStubs(stubs))
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(body: &[u8]) -> Result<RelatedDocument<serde_json::Value>, Error> {
+        let content_type = "multipart/related; boundary=abc123".parse().unwrap();
+        RelatedDocument::decode(&content_type, body)
+    }
+
+    #[test]
+    fn decode_matches_parts_by_filename() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_id\":\"alpha\",\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":5,\"follows\":true},\
+            \"charlie\":{\"content_type\":\"text/html\",\"revpos\":3,\
+            \"digest\":\"md5-2JdGiI2i2VELZKnwMers1Q==\",\"length\":4,\"follows\":true},\
+            \"delta\":{\"content_type\":\"text/plain\",\"revpos\":1,\
+            \"digest\":\"md5-2JdGiI2i2VELZKnwMers1Q==\",\"length\":1872,\"stub\":true}\
+            }}\r\n\
+            --abc123\r\n\
+            Content-Disposition: attachment; filename=\"charlie\"\r\n\
+            \r\n\
+            echo\r\n\
+            --abc123\r\n\
+            Content-Disposition: attachment; filename=\"bravo\"\r\n\
+            \r\n\
+            hello\r\n\
+            --abc123--";
+
+        let got = decode(body).unwrap();
+        assert_eq!(got.document()["_id"], "alpha");
+
+        let attachments = got.attachments();
+        assert_eq!(attachments.len(), 3);
+
+        let bravo = &attachments[&AttachmentName::new("bravo")];
+        assert!(bravo.is_server_origin());
+        assert_eq!(bravo.content(), Some(b"hello".as_ref()));
+        assert_eq!(bravo.revision_sequence(), Some(2));
+
+        let charlie = &attachments[&AttachmentName::new("charlie")];
+        assert_eq!(charlie.content(), Some(b"echo".as_ref()));
+        assert_eq!(charlie.content_type(), &mime::TEXT_HTML);
+
+        let delta = &attachments[&AttachmentName::new("delta")];
+        assert_eq!(delta.content(), None);
+        assert_eq!(delta.content_length(), 1872);
+    }
+
+    #[test]
+    fn decode_matches_parts_by_order() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_attachments\":{\
+            \"charlie\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":4,\"follows\":true},\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":3,\
+            \"digest\":\"md5-2JdGiI2i2VELZKnwMers1Q==\",\"length\":5,\"follows\":true}\
+            }}\r\n\
+            --abc123\r\n\
+            \r\n\
+            echo\r\n\
+            --abc123\r\n\
+            \r\n\
+            hello\r\n\
+            --abc123--";
+
+        let got = decode(body).unwrap();
+        let attachments = got.attachments();
+        assert_eq!(
+            attachments[&AttachmentName::new("charlie")].content(),
+            Some(b"echo".as_ref())
+        );
+        assert_eq!(
+            attachments[&AttachmentName::new("bravo")].content(),
+            Some(b"hello".as_ref())
+        );
+    }
+
+    #[test]
+    fn decode_handles_gzip_encoded_parts() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":1872,\
+            \"encoding\":\"gzip\",\"encoded_length\":3,\"follows\":true}\
+            }}\r\n\
+            --abc123\r\n\
+            Content-Disposition: attachment; filename=\"bravo\"\r\n\
+            Content-Encoding: gzip\r\n\
+            \r\n\
+            xyz\r\n\
+            --abc123--";
+
+        let got = decode(body).unwrap();
+        let bravo = &got.attachments()[&AttachmentName::new("bravo")];
+        assert_eq!(bravo.content(), None);
+        assert_eq!(bravo.encoded_content(), Some(b"xyz".as_ref()));
+        assert_eq!(bravo.content_length(), 1872);
+        assert!(bravo.encoding().unwrap().is_gzip());
+        assert_eq!(bravo.encoding().unwrap().length(), 3);
+    }
+
+    #[test]
+    fn decode_fails_on_length_mismatch() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":6,\"follows\":true}\
+            }}\r\n\
+            --abc123\r\n\
+            \r\n\
+            hello\r\n\
+            --abc123--";
+
+        match decode(body) {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_fails_on_missing_part() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":5,\"follows\":true}\
+            }}\r\n\
+            --abc123--";
+
+        match decode(body) {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_fails_on_extra_part() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {}\r\n\
+            --abc123\r\n\
+            \r\n\
+            hello\r\n\
+            --abc123--";
+
+        match decode(body) {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_fails_on_unknown_filename() {
+
+        let body = b"--abc123\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":5,\"follows\":true}\
+            }}\r\n\
+            --abc123\r\n\
+            Content-Disposition: attachment; filename=\"charlie\"\r\n\
+            \r\n\
+            hello\r\n\
+            --abc123--";
+
+        match decode(body) {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,533 @@
+use {Attachment, AttachmentName, Error, serde, serde_json, std};
+use attachment::ContentReader;
+use mime::Mime;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Read;
+use super::CRLF;
+use uuid::Uuid;
+
+const ATTACHMENTS_FIELD: &str = "_attachments";
+
+/// `RelatedEncoder` constructs a `multipart/related` body containing a
+/// document and its attachments.
+///
+/// # Summary
+///
+/// * `RelatedEncoder` serializes the document as JSON, replacing each enclosed
+///   attachment with a stub that has `"follows": true`.
+///
+/// * `RelatedEncoder` emits each attachment's content as its own part, in the
+///   same order as the attachment stubs appear in the JSON.
+///
+/// * An attachment originating from the server that has no content (i.e., a
+///   stub) is kept in the document as a stub and is not emitted as a part.
+///
+/// * The resulting `RelatedBody` implements `Read` and borrows attachment
+///   content rather than copying it. Attachment content in a file or reader is
+///   streamed as the body is read.
+///
+/// * An attachment constructed from gzip-encoded content (via
+///   `Attachment::new_gzipped`) is emitted in encoded form, and its stub
+///   describes the encoding so that the server stores the content as is.
+///
+/// # Example
+///
+/// ```rust
+/// extern crate couchdb;
+/// extern crate mime;
+/// #[macro_use]
+/// extern crate serde_derive;
+///
+/// #[derive(Serialize)]
+/// struct Recipe {
+///     title: String,
+/// }
+///
+/// # fn main() {
+/// let doc = Recipe { title: String::from("Apple pie") };
+/// let photo = couchdb::Attachment::new(mime::IMAGE_PNG, vec![0x89, 0x50, 0x4e, 0x47]);
+///
+/// let mut encoder = couchdb::multipart::RelatedEncoder::new(&doc).unwrap();
+/// encoder.attach("photo.png", &photo);
+/// let mut body = encoder.finish().unwrap();
+///
+/// // Send these as the `Content-Type` and `Content-Length` headers.
+/// assert_eq!(body.content_type().type_(), mime::MULTIPART);
+/// assert_eq!(body.content_type().subtype(), "related");
+/// let content_length = body.content_length();
+///
+/// // Send this as the request body.
+/// let mut encoded = Vec::new();
+/// std::io::copy(&mut body, &mut encoded).unwrap();
+/// assert_eq!(encoded.len() as u64, content_length);
+/// # }
+/// ```
+///
+#[derive(Debug)]
+pub struct RelatedEncoder<'a> {
+    boundary: String,
+    doc: serde_json::Map<String, serde_json::Value>,
+    attachments: BTreeMap<AttachmentName, &'a Attachment>,
+}
+
+/// `RelatedBody` is an encoded `multipart/related` body.
+///
+/// An application obtains a `RelatedBody` via the `RelatedEncoder::finish`
+/// method. The body's content is available by reading from it.
+///
+#[derive(Debug)]
+pub struct RelatedBody<'a> {
+    content_type: Mime,
+    content_length: u64,
+    segments: VecDeque<Segment<'a>>,
+}
+
+#[derive(Debug)]
+enum Segment<'a> {
+    Owned(std::io::Cursor<Vec<u8>>),
+    Borrowed(&'a [u8]),
+    Content(ContentReader<'a>),
+}
+
+impl<'a> RelatedEncoder<'a> {
+    /// Constructs a new encoder for the given document.
+    ///
+    /// The document must serialize as a JSON object. Any attachments already
+    /// in the document's `_attachments` field are kept as is, unless replaced
+    /// via the `attach` method.
+    ///
+    pub fn new<T: serde::Serialize>(doc: &T) -> Result<Self, Error> {
+        RelatedEncoder::with_boundary(doc, Uuid::new_v4().simple().to_string())
+    }
+
+    fn with_boundary<T: serde::Serialize>(doc: &T, boundary: String) -> Result<Self, Error> {
+
+        let doc = match serde_json::to_value(doc).map_err(|e| {
+            Error::from(("Failed to serialize document as JSON", e))
+        })? {
+            serde_json::Value::Object(x) => x,
+            _ => return Err(Error::bad_multipart("Document does not serialize as a JSON object")),
+        };
+
+        Ok(RelatedEncoder {
+            boundary: boundary,
+            doc: doc,
+            attachments: BTreeMap::new(),
+        })
+    }
+
+    /// Adds an attachment to the document, replacing any existing attachment
+    /// with the same name.
+    pub fn attach<N: Into<AttachmentName>>(&mut self, att_name: N, attachment: &'a Attachment) -> &mut Self {
+        self.attachments.insert(att_name.into(), attachment);
+        self
+    }
+
+    /// Encodes the document and its attachments.
+    ///
+    /// The encoding fails if any attachment has no content and cannot be
+    /// stubbed—e.g., the attachment was constructed via
+    /// `Attachment::to_multipart_stub`—or if any attachment's content fails to
+    /// open for reading.
+    ///
+    pub fn finish(self) -> Result<RelatedBody<'a>, Error> {
+
+        let RelatedEncoder {
+            boundary,
+            mut doc,
+            attachments,
+        } = self;
+
+        let mut follows = BTreeMap::new();
+
+        {
+            let stubs = match *doc.entry(ATTACHMENTS_FIELD).or_insert_with(
+                || serde_json::Value::Object(serde_json::Map::new()),
+            ) {
+                serde_json::Value::Object(ref mut x) => x,
+                _ => return Err(Error::bad_multipart("Document's attachments field is not a JSON object")),
+            };
+
+            for (att_name, attachment) in attachments {
+                let content = match attachment.encoded_content() {
+                    Some(bytes) if attachment.is_client_origin() => Some(Segment::Borrowed(bytes)),
+                    _ => attachment.content_reader()?.map(Segment::Content),
+                };
+                let stub = match content {
+                    Some(content) => {
+                        follows.insert(String::from(att_name.as_ref()), (attachment, content));
+                        attachment.to_multipart_stub()
+                    }
+                    None => attachment.to_stub().ok_or(
+                        Error::bad_multipart("Attachment has no content"),
+                    )?,
+                };
+                let stub = serde_json::to_value(&stub).map_err(|e| {
+                    Error::from(("Failed to serialize attachment stub as JSON", e))
+                })?;
+                stubs.insert(String::from(att_name), stub);
+            }
+        }
+
+        let json = serde_json::to_vec(&doc).map_err(|e| {
+            Error::from(("Failed to serialize document as JSON", e))
+        })?;
+
+        let mut segments = VecDeque::new();
+        segments.push_back(Segment::from(format!(
+            "--{}{}Content-Type: application/json{}{}",
+            boundary,
+            CRLF,
+            CRLF,
+            CRLF
+        )));
+        segments.push_back(Segment::from(json));
+
+        // Emit the attachment parts in the same order as their stubs appear in
+        // the serialized JSON.
+
+        let order = match doc[ATTACHMENTS_FIELD] {
+            serde_json::Value::Object(ref x) => x.keys(),
+            _ => unreachable!(),
+        };
+
+        for att_name in order {
+            if let Some((attachment, content)) = follows.remove(att_name) {
+                let content_encoding = match attachment.encoded_content().and(attachment.encoding()) {
+                    Some(encoding) => format!("Content-Encoding: {}{}", encoding.codec(), CRLF),
+                    None => String::new(),
+                };
+                segments.push_back(Segment::from(format!(
+                    "{}--{}{}\
+                     Content-Disposition: attachment; filename=\"{}\"{}\
+                     Content-Type: {}{}\
+                     {}\
+                     Content-Length: {}{}{}",
+                    CRLF,
+                    boundary,
+                    CRLF,
+                    escape_quoted_string(att_name),
+                    CRLF,
+                    attachment.content_type(),
+                    CRLF,
+                    content_encoding,
+                    content.len(),
+                    CRLF,
+                    CRLF
+                )));
+                segments.push_back(content);
+            }
+        }
+
+        segments.push_back(Segment::from(format!("{}--{}--", CRLF, boundary)));
+
+        let content_length = segments.iter().map(|x| x.len()).sum();
+
+        Ok(RelatedBody {
+            content_type: format!("multipart/related; boundary={}", boundary)
+                .parse()
+                .unwrap(),
+            content_length: content_length,
+            segments: segments,
+        })
+    }
+}
+
+impl<'a> RelatedBody<'a> {
+    /// Borrows the body's MIME type, which includes the multipart boundary.
+    ///
+    /// The application should send this as the HTTP request's `Content-Type`
+    /// header.
+    ///
+    pub fn content_type(&self) -> &Mime {
+        &self.content_type
+    }
+
+    /// Returns the total size of the body, in bytes.
+    ///
+    /// The application should send this as the HTTP request's `Content-Length`
+    /// header.
+    ///
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+}
+
+impl<'a> Read for RelatedBody<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let n = match self.segments.front_mut() {
+                None => return Ok(0),
+                Some(&mut Segment::Owned(ref mut x)) => x.read(buf)?,
+                Some(&mut Segment::Borrowed(ref mut x)) => x.read(buf)?,
+                Some(&mut Segment::Content(ref mut x)) => x.read(buf)?,
+            };
+            if n != 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            self.segments.pop_front();
+        }
+    }
+}
+
+impl<'a> Segment<'a> {
+    fn len(&self) -> u64 {
+        match *self {
+            Segment::Owned(ref x) => x.get_ref().len() as u64,
+            Segment::Borrowed(x) => x.len() as u64,
+            Segment::Content(ref x) => x.content_length(),
+        }
+    }
+}
+
+impl<'a> From<String> for Segment<'a> {
+    fn from(s: String) -> Self {
+        Segment::Owned(std::io::Cursor::new(s.into_bytes()))
+    }
+}
+
+impl<'a> From<Vec<u8>> for Segment<'a> {
+    fn from(v: Vec<u8>) -> Self {
+        Segment::Owned(std::io::Cursor::new(v))
+    }
+}
+
+fn escape_quoted_string(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mime;
+
+    fn read_body(mut body: RelatedBody) -> String {
+        let mut v = Vec::new();
+        body.read_to_end(&mut v).unwrap();
+        assert_eq!(v.len() as u64, body.content_length());
+        String::from_utf8(v).unwrap()
+    }
+
+    #[test]
+    fn encoder_emits_document_without_attachments() {
+        let doc = json!({"foo": 17});
+        let encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        let body = encoder.finish().unwrap();
+        assert_eq!(
+            body.content_type().to_string(),
+            "multipart/related; boundary=abc123"
+        );
+        assert_eq!(
+            read_body(body),
+            "--abc123\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {\"_attachments\":{},\"foo\":17}\r\n\
+             --abc123--"
+        );
+    }
+
+    #[test]
+    fn encoder_emits_attachments_in_json_order() {
+
+        let doc = json!({"foo": 17});
+        let alpha = Attachment::new(mime::TEXT_PLAIN, Vec::from(b"this is 21 chars long".as_ref()));
+        let bravo = Attachment::new(mime::TEXT_HTML, Vec::from(b"this is 20 chars lon".as_ref()));
+
+        let mut encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        encoder.attach("bravo\"", &bravo).attach("alpha", &alpha);
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(
+            read_body(body),
+            "--abc123\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {\"_attachments\":{\
+             \"alpha\":{\"content_type\":\"text/plain\",\"follows\":true,\"length\":21},\
+             \"bravo\\\"\":{\"content_type\":\"text/html\",\"follows\":true,\"length\":20}\
+             },\"foo\":17}\r\n\
+             --abc123\r\n\
+             Content-Disposition: attachment; filename=\"alpha\"\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: 21\r\n\
+             \r\n\
+             this is 21 chars long\r\n\
+             --abc123\r\n\
+             Content-Disposition: attachment; filename=\"bravo\\\"\"\r\n\
+             Content-Type: text/html\r\n\
+             Content-Length: 20\r\n\
+             \r\n\
+             this is 20 chars lon\r\n\
+             --abc123--"
+        );
+    }
+
+    #[test]
+    fn encoder_keeps_server_stubs_in_document() {
+
+        let doc = json!({
+            "_attachments": {
+                "bravo": {
+                    "content_type": "text/plain",
+                    "digest": "md5-Ids41vtv725jyrN7iUvMcQ==",
+                    "length": 1872,
+                    "revpos": 4,
+                    "stub": true
+                }
+            }
+        });
+        let charlie: Attachment = serde_json::from_value(doc["_attachments"]["bravo"].clone()).unwrap();
+        let alpha = Attachment::new(mime::TEXT_PLAIN, Vec::from(b"hello".as_ref()));
+
+        let mut encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        encoder.attach("alpha", &alpha).attach("charlie", &charlie);
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(
+            read_body(body),
+            "--abc123\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {\"_attachments\":{\
+             \"alpha\":{\"content_type\":\"text/plain\",\"follows\":true,\"length\":5},\
+             \"bravo\":{\"content_type\":\"text/plain\",\"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\
+             \"length\":1872,\"revpos\":4,\"stub\":true},\
+             \"charlie\":{\"content_type\":\"text/plain\",\"stub\":true}\
+             }}\r\n\
+             --abc123\r\n\
+             Content-Disposition: attachment; filename=\"alpha\"\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: 5\r\n\
+             \r\n\
+             hello\r\n\
+             --abc123--"
+        );
+    }
+
+    #[test]
+    fn encoder_streams_attachment_from_reader() {
+
+        let doc = json!({});
+        let content = std::io::Cursor::new(Vec::from(b"hello, world".as_ref()));
+        let alpha = Attachment::from_reader(mime::TEXT_PLAIN, content, 5);
+
+        let mut encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        encoder.attach("alpha", &alpha);
+        let body = encoder.finish().unwrap();
+
+        assert_eq!(
+            read_body(body),
+            "--abc123\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {\"_attachments\":{\
+             \"alpha\":{\"content_type\":\"text/plain\",\"follows\":true,\"length\":5}\
+             }}\r\n\
+             --abc123\r\n\
+             Content-Disposition: attachment; filename=\"alpha\"\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Length: 5\r\n\
+             \r\n\
+             hello\r\n\
+             --abc123--"
+        );
+    }
+
+    #[test]
+    fn body_fails_when_attachment_reader_is_short() {
+
+        let doc = json!({});
+        let content = std::io::Cursor::new(Vec::from(b"hello".as_ref()));
+        let alpha = Attachment::from_reader(mime::TEXT_PLAIN, content, 11);
+
+        let mut encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        encoder.attach("alpha", &alpha);
+        let mut body = encoder.finish().unwrap();
+
+        let mut got = Vec::new();
+        let e = body.read_to_end(&mut got).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!((got.len() as u64) < body.content_length());
+    }
+
+    #[test]
+    fn encoder_emits_gzipped_attachment_as_encoded() {
+
+        let doc = json!({});
+        let gzipped = ::attachment::Compression::default().compress(b"hello");
+        let alpha = Attachment::new_gzipped(mime::TEXT_PLAIN, gzipped.clone()).unwrap();
+
+        let mut encoder = RelatedEncoder::with_boundary(&doc, String::from("abc123")).unwrap();
+        encoder.attach("alpha", &alpha);
+        let mut body = encoder.finish().unwrap();
+
+        let mut got = Vec::new();
+        body.read_to_end(&mut got).unwrap();
+        assert_eq!(got.len() as u64, body.content_length());
+
+        let mut expected = Vec::from(format!(
+            "--abc123\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {{\"_attachments\":{{\
+             \"alpha\":{{\"content_type\":\"text/plain\",\"encoded_length\":{},\
+             \"encoding\":\"gzip\",\"follows\":true,\"length\":5}}\
+             }}}}\r\n\
+             --abc123\r\n\
+             Content-Disposition: attachment; filename=\"alpha\"\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Encoding: gzip\r\n\
+             Content-Length: {}\r\n\
+             \r\n",
+            gzipped.len(),
+            gzipped.len()
+        ).as_bytes());
+        expected.extend_from_slice(&gzipped);
+        expected.extend_from_slice(b"\r\n--abc123--");
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn encoder_rejects_non_object_document() {
+        match RelatedEncoder::new(&json!([1, 2, 3])) {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn encoder_rejects_attachment_without_content() {
+        let doc = json!({});
+        let att = Attachment::new(mime::TEXT_PLAIN, Vec::new()).to_multipart_stub();
+        let mut encoder = RelatedEncoder::new(&doc).unwrap();
+        encoder.attach("alpha", &att);
+        match encoder.finish() {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn body_reads_in_small_chunks() {
+        let doc = json!({});
+        let att = Attachment::new(mime::TEXT_PLAIN, Vec::from(b"hello".as_ref()));
+        let mut encoder = RelatedEncoder::new(&doc).unwrap();
+        encoder.attach("alpha", &att);
+        let mut body = encoder.finish().unwrap();
+        let expected_length = body.content_length();
+
+        let mut got = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            let n = body.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            got.extend_from_slice(&buf[..n]);
+        }
+
+        assert_eq!(got.len() as u64, expected_length);
+    }
+}
@@ -0,0 +1,280 @@
+use {DocumentId, Error, Nok, Revision, serde_json, std};
+use mime::{self, Mime};
+use serde::de::DeserializeOwned;
+use super::RelatedDocument;
+use super::decoder::decode_parts;
+use super::parser::{self, Part};
+
+/// `OpenRevision` is one revision of a document decoded from a
+/// `multipart/mixed` body.
+///
+/// # Summary
+///
+/// * `OpenRevision` decodes the response body the CouchDB server sends for
+///   replication-style fetches when the request has the header `Accept:
+///   multipart/mixed`—i.e., `GET /{db}/{doc}?open_revs=…` and `POST
+///   /{db}/_bulk_get`.
+///
+/// * Each part of the body yields one `OpenRevision`, in order.
+///
+/// * A revision the server has is `Found` and contains the document together
+///   with its attachments, which are decoded as described for
+///   [`RelatedDocument`](struct.RelatedDocument.html).
+///
+/// * A revision the server does not have is `Missing` and contains the
+///   requested revision and, if the server specified it, the document id.
+///
+/// * Any other error the server reports for a revision is `Failed` and
+///   contains the error.
+///
+/// # Example
+///
+/// ```rust
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::multipart::OpenRevision;
+///
+/// let content_type = "multipart/mixed; boundary=\"abc123\"".parse().unwrap();
+///
+/// let body = b"--abc123\r\n\
+///     Content-Type: application/json\r\n\
+///     \r\n\
+///     {\"_id\":\"alpha\",\"_rev\":\"1-917fa2381192822767f010b95b45325b\"}\r\n\
+///     --abc123\r\n\
+///     Content-Type: application/json; error=\"true\"\r\n\
+///     \r\n\
+///     {\"missing\":\"3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb\"}\r\n\
+///     --abc123--";
+///
+/// let revs = OpenRevision::<serde_json::Value>::decode_all(&content_type, body)
+///     .unwrap();
+///
+/// assert_eq!(revs.len(), 2);
+///
+/// match revs[0] {
+///     OpenRevision::Found(ref doc) => assert_eq!(doc.document()["_id"], "alpha"),
+///     _ => panic!("Expected a found revision"),
+/// }
+///
+/// match revs[1] {
+///     OpenRevision::Missing { ref rev, .. } => assert_eq!(rev.sequence_number(), 3),
+///     _ => panic!("Expected a missing revision"),
+/// }
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpenRevision<T> {
+    /// The server has the revision and sent the document and its
+    /// attachments.
+    Found(RelatedDocument<T>),
+
+    /// The server does not have the revision.
+    Missing {
+        /// The document id, from the error body's `id` field or the part's
+        /// `X-Doc-Id` header. An `open_revs` response does not specify it.
+        id: Option<DocumentId>,
+
+        /// The requested revision.
+        rev: Revision,
+    },
+
+    /// The server reported some other error for the revision.
+    Failed(Nok),
+}
+
+impl<T: DeserializeOwned> OpenRevision<T> {
+    /// Decodes all revisions from a `multipart/mixed` body.
+    ///
+    /// The content type must be the MIME type the server sent in the
+    /// response's `Content-Type` header, which specifies the multipart
+    /// boundary.
+    ///
+    pub fn decode_all(content_type: &Mime, body: &[u8]) -> Result<Vec<Self>, Error> {
+        let boundary = parser::boundary(content_type, "mixed")?;
+        parser::parse(boundary, body)?
+            .iter()
+            .map(OpenRevision::decode_part)
+            .collect()
+    }
+
+    fn decode_part(part: &Part) -> Result<Self, Error> {
+
+        let content_type = part.content_type()?.unwrap_or(mime::APPLICATION_JSON);
+
+        if content_type.type_() == mime::MULTIPART {
+            let boundary = parser::boundary(&content_type, "related")?;
+            let parts = parser::parse(boundary, part.body())?;
+            return Ok(OpenRevision::Found(decode_parts(&parts)?));
+        }
+
+        let is_error = content_type
+            .get_param("error")
+            .map(|x| x == "true")
+            .unwrap_or(false);
+
+        if !is_error {
+            return Ok(OpenRevision::Found(decode_parts(std::slice::from_ref(part))?));
+        }
+
+        #[derive(Deserialize)]
+        struct ErrorBody {
+            missing: Option<Revision>,
+            error: Option<String>,
+            id: Option<DocumentId>,
+            rev: Option<Revision>,
+        }
+
+        let x: ErrorBody = serde_json::from_slice(part.body()).map_err(|e| {
+            Error::from(("Failed to deserialize revision error JSON", e))
+        })?;
+
+        let id = x.id.or_else(|| part.header("X-Doc-Id").map(DocumentId::from));
+
+        match x {
+            ErrorBody { missing: Some(rev), .. } => Ok(OpenRevision::Missing { id: id, rev: rev }),
+            ErrorBody {
+                error: Some(ref error),
+                rev: Some(ref rev),
+                ..
+            } if error == "not_found" => Ok(OpenRevision::Missing {
+                id: id,
+                rev: rev.clone(),
+            }),
+            _ => {
+                let nok = serde_json::from_slice(part.body()).map_err(|e| {
+                    Error::from(("Failed to deserialize revision error JSON", e))
+                })?;
+                Ok(OpenRevision::Failed(nok))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use AttachmentName;
+
+    fn decode(body: &[u8]) -> Result<Vec<OpenRevision<serde_json::Value>>, Error> {
+        let content_type = "multipart/mixed; boundary=\"outer\"".parse().unwrap();
+        OpenRevision::decode_all(&content_type, body)
+    }
+
+    #[test]
+    fn decode_all_handles_open_revs_response() {
+
+        let body = b"--outer\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_id\":\"alpha\",\"_rev\":\"1-917fa2381192822767f010b95b45325b\",\"_deleted\":true}\r\n\
+            --outer\r\n\
+            Content-Type: multipart/related; boundary=\"inner\"\r\n\
+            \r\n\
+            --inner\r\n\
+            Content-Type: application/json\r\n\
+            \r\n\
+            {\"_id\":\"alpha\",\"_rev\":\"2-c1c6c44c4bc3c9344b037c8690468605\",\"_attachments\":{\
+            \"bravo\":{\"content_type\":\"text/plain\",\"revpos\":2,\
+            \"digest\":\"md5-Ids41vtv725jyrN7iUvMcQ==\",\"length\":5,\"follows\":true}}}\r\n\
+            --inner\r\n\
+            Content-Disposition: attachment; filename=\"bravo\"\r\n\
+            \r\n\
+            hello\r\n\
+            --inner--\r\n\
+            --outer\r\n\
+            Content-Type: application/json; error=\"true\"\r\n\
+            \r\n\
+            {\"missing\":\"3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb\"}\r\n\
+            --outer--";
+
+        let got = decode(body).unwrap();
+        assert_eq!(got.len(), 3);
+
+        match got[0] {
+            OpenRevision::Found(ref doc) => {
+                assert_eq!(doc.document()["_deleted"], true);
+                assert!(doc.attachments().is_empty());
+            }
+            ref x => panic!("Got unexpected result {:?}", x),
+        }
+
+        match got[1] {
+            OpenRevision::Found(ref doc) => {
+                assert_eq!(
+                    doc.document()["_rev"],
+                    "2-c1c6c44c4bc3c9344b037c8690468605"
+                );
+                assert_eq!(
+                    doc.attachments()[&AttachmentName::new("bravo")].content(),
+                    Some(b"hello".as_ref())
+                );
+            }
+            ref x => panic!("Got unexpected result {:?}", x),
+        }
+
+        assert_eq!(
+            got[2],
+            OpenRevision::Missing {
+                id: None,
+                rev: Revision::parse("3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb").unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_all_handles_bulk_get_errors() {
+
+        let body = b"--outer\r\n\
+            Content-Type: application/json; error=\"true\"\r\n\
+            \r\n\
+            {\"error\":\"not_found\",\"id\":\"alpha\",\
+            \"rev\":\"3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb\",\"reason\":\"missing\"}\r\n\
+            --outer\r\n\
+            Content-Type: application/json; error=\"true\"\r\n\
+            \r\n\
+            {\"error\":\"forbidden\",\"id\":\"bravo\",\"reason\":\"go away\"}\r\n\
+            --outer\r\n\
+            Content-Type: application/json; error=\"true\"\r\n\
+            X-Doc-Id: charlie\r\n\
+            \r\n\
+            {\"missing\":\"1-917fa2381192822767f010b95b45325b\"}\r\n\
+            --outer--";
+
+        let got = decode(body).unwrap();
+        assert_eq!(got.len(), 3);
+
+        assert_eq!(
+            got[0],
+            OpenRevision::Missing {
+                id: Some(DocumentId::from("alpha")),
+                rev: Revision::parse("3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb").unwrap(),
+            }
+        );
+
+        match got[1] {
+            OpenRevision::Failed(ref nok) => {
+                assert_eq!(nok.error, "forbidden");
+                assert_eq!(nok.reason, "go away");
+            }
+            ref x => panic!("Got unexpected result {:?}", x),
+        }
+
+        assert_eq!(
+            got[2],
+            OpenRevision::Missing {
+                id: Some(DocumentId::from("charlie")),
+                rev: Revision::parse("1-917fa2381192822767f010b95b45325b").unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn decode_all_rejects_related_content_type() {
+        let content_type = "multipart/related; boundary=\"outer\"".parse().unwrap();
+        match OpenRevision::<serde_json::Value>::decode_all(&content_type, b"--outer--") {
+            Err(Error::BadMultipart { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! The `multipart` module provides types for working with CouchDB's
+//! multipart-encoded documents.
+//!
+//! # Summary
+//!
+//! * CouchDB allows an application to send and receive a document together with
+//!   its attachments as a single `multipart/related` HTTP body. The first part
+//!   contains the document as JSON, and each subsequent part contains the raw
+//!   content of one attachment.
+//!
+//! * Multipart-encoding avoids the overhead of base64-encoding attachment
+//!   content within the document's JSON, which inflates the content's size by a
+//!   third.
+//!
+//! * `RelatedEncoder` constructs a `multipart/related` request body for
+//!   uploading a document and its attachments—e.g., via `PUT /{db}/{doc}`.
+//!
+//! * `RelatedDocument` decodes a `multipart/related` response body containing a
+//!   document and its attachments—e.g., from `GET
+//!   /{db}/{doc}?attachments=true`.
+//!
+//! * `OpenRevision` decodes a `multipart/mixed` response body containing
+//!   multiple revisions of documents—e.g., from `GET /{db}/{doc}?open_revs=all`
+//!   or `POST /{db}/_bulk_get`.
+//!
+//! # Remarks
+//!
+//! The CouchDB server matches each attachment part with an attachment object in
+//! the document's `_attachments` field according to the order in which the
+//! attachment objects appear in the JSON. The `multipart` module takes care of
+//! keeping these in order.
+//!
+//! See the [CouchDB
+//! documentation](http://docs.couchdb.org/en/2.0.0/api/document/common.html#creating-multiple-attachments)
+//! for details.
+
+mod decoder;
+mod encoder;
+mod mixed;
+pub(crate) mod parser;
+
+pub use self::decoder::RelatedDocument;
+pub use self::encoder::{RelatedBody, RelatedEncoder};
+pub use self::mixed::OpenRevision;
+
+const CRLF: &str = "\r\n";
@@ -0,0 +1,211 @@
+use {Error, std};
+use mime::{self, Mime};
+use super::CRLF;
+
+const E_BAD_CONTENT_TYPE: &str = "Content type is not multipart or has no boundary";
+const E_BAD_HEADER: &str = "Part has a malformed header";
+const E_NO_CLOSING_DELIMITER: &str = "Body has no closing delimiter";
+const E_NO_OPENING_DELIMITER: &str = "Body has no opening delimiter";
+const E_UNTERMINATED_HEADERS: &str = "Part headers are not terminated";
+
+// Headers are a part's headers, as name-value pairs in order of appearance.
+type Headers<'a> = Vec<(&'a str, &'a str)>;
+
+// Part is one body part of a multipart body, borrowed from the body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Part<'a> {
+    headers: Headers<'a>,
+    body: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    // Returns the value of the first header having the given name, matched
+    // case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|&&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, v)| v)
+    }
+
+    pub fn content_type(&self) -> Result<Option<Mime>, Error> {
+        match self.header("Content-Type") {
+            None => Ok(None),
+            Some(s) => s.parse().map(Some).map_err(|_| {
+                Error::bad_multipart("Part has a malformed Content-Type header")
+            }),
+        }
+    }
+
+    // Returns the filename parameter of the part's Content-Disposition header,
+    // if any.
+    pub fn filename(&self) -> Option<String> {
+        let value = self.header("Content-Disposition")?;
+        let i = value.find("filename=")?;
+        let value = &value[i + "filename=".len()..];
+
+        if !value.starts_with('"') {
+            return Some(String::from(value.split(';').next().unwrap().trim()));
+        }
+
+        let mut name = String::new();
+        let mut chars = value[1..].chars();
+        loop {
+            match chars.next()? {
+                '"' => return Some(name),
+                '\\' => name.push(chars.next()?),
+                c => name.push(c),
+            }
+        }
+    }
+
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+}
+
+// Returns the boundary of a multipart content type having the given subtype.
+pub fn boundary<'a>(content_type: &'a Mime, subtype: &str) -> Result<&'a str, Error> {
+    if content_type.type_() != mime::MULTIPART || content_type.subtype() != subtype {
+        return Err(Error::bad_multipart(E_BAD_CONTENT_TYPE));
+    }
+    content_type
+        .get_param(mime::BOUNDARY)
+        .map(|x| x.as_str())
+        .ok_or(Error::bad_multipart(E_BAD_CONTENT_TYPE))
+}
+
+// Splits a multipart body into its parts, as specified in RFC 2046.
+pub fn parse<'a>(boundary: &str, body: &'a [u8]) -> Result<Vec<Part<'a>>, Error> {
+
+    let delimiter = format!("{}--{}", CRLF, boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // The first delimiter need not be preceded by a CRLF.
+    let mut cursor = if body.starts_with(&delimiter[CRLF.len()..]) {
+        &body[delimiter.len() - CRLF.len()..]
+    } else {
+        let i = find(body, delimiter).ok_or(Error::bad_multipart(E_NO_OPENING_DELIMITER))?;
+        &body[i + delimiter.len()..]
+    };
+
+    let mut parts = Vec::new();
+
+    loop {
+        if cursor.starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        // Skip transport padding and the CRLF that ends the delimiter line.
+        let i = find(cursor, CRLF.as_bytes()).ok_or(Error::bad_multipart(E_NO_CLOSING_DELIMITER))?;
+        cursor = &cursor[i + CRLF.len()..];
+
+        let (headers, rest) = parse_headers(cursor)?;
+        let i = find(rest, delimiter).ok_or(Error::bad_multipart(E_NO_CLOSING_DELIMITER))?;
+        parts.push(Part {
+            headers: headers,
+            body: &rest[..i],
+        });
+
+        cursor = &rest[i + delimiter.len()..];
+    }
+}
+
+fn parse_headers(mut cursor: &[u8]) -> Result<(Headers<'_>, &[u8]), Error> {
+    let mut headers = Vec::new();
+    loop {
+        let i = find(cursor, CRLF.as_bytes()).ok_or(Error::bad_multipart(E_UNTERMINATED_HEADERS))?;
+        let line = &cursor[..i];
+        cursor = &cursor[i + CRLF.len()..];
+
+        if line.is_empty() {
+            return Ok((headers, cursor));
+        }
+
+        let line = std::str::from_utf8(line).map_err(|_| Error::bad_multipart(E_BAD_HEADER))?;
+        let colon = line.find(':').ok_or(Error::bad_multipart(E_BAD_HEADER))?;
+        headers.push((line[..colon].trim(), line[colon + 1..].trim()));
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|x| x == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundary_is_extracted_from_content_type() {
+        let content_type = "multipart/related; boundary=\"abc123\"".parse().unwrap();
+        assert_eq!(boundary(&content_type, "related").unwrap(), "abc123");
+
+        let content_type = "multipart/mixed; boundary=abc123".parse().unwrap();
+        assert_eq!(boundary(&content_type, "mixed").unwrap(), "abc123");
+        boundary(&content_type, "related").unwrap_err();
+
+        let content_type = "multipart/related".parse().unwrap();
+        boundary(&content_type, "related").unwrap_err();
+    }
+
+    #[test]
+    fn parse_splits_parts() {
+        let body = b"preamble\r\n\
+                     --abc123\r\n\
+                     Content-Type: application/json\r\n\
+                     \r\n\
+                     {}\r\n\
+                     --abc123  \r\n\
+                     \r\n\
+                     alpha\r\nbravo\r\n\
+                     --abc123--\r\n\
+                     epilogue";
+
+        let got = parse("abc123", body).unwrap();
+        assert_eq!(
+            got,
+            vec![
+                Part {
+                    headers: vec![("Content-Type", "application/json")],
+                    body: b"{}",
+                },
+                Part {
+                    headers: vec![],
+                    body: b"alpha\r\nbravo",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_fails_without_closing_delimiter() {
+        let body = b"--abc123\r\n\r\nalpha";
+        parse("abc123", body).unwrap_err();
+    }
+
+    #[test]
+    fn part_header_is_case_insensitive() {
+        let part = Part {
+            headers: vec![("content-TYPE", "text/plain")],
+            body: b"",
+        };
+        assert_eq!(part.header("Content-Type"), Some("text/plain"));
+        assert_eq!(part.header("Content-Length"), None);
+    }
+
+    #[test]
+    fn part_filename_is_unquoted() {
+        let part = Part {
+            headers: vec![("Content-Disposition", r#"attachment; filename="alpha \"bravo\"""#)],
+            body: b"",
+        };
+        assert_eq!(part.filename(), Some(String::from(r#"alpha "bravo""#)));
+
+        let part = Part {
+            headers: vec![("Content-Disposition", "attachment; filename=alpha")],
+            body: b"",
+        };
+        assert_eq!(part.filename(), Some(String::from("alpha")));
+    }
+}
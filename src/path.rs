@@ -64,6 +64,9 @@ use std::str::FromStr;
 
 const DESIGN_PREFIX: &str = "_design";
 const LOCAL_PREFIX: &str = "_local";
+const SCHEDULER_PREFIX: &str = "_scheduler";
+const SCHEDULER_DOCS: &str = "docs";
+const SCHEDULER_JOBS: &str = "jobs";
 const VIEW_PREFIX: &str = "_view";
 
 static DOCUMENT_PREFIXES: &[&str] = &[DESIGN_PREFIX, LOCAL_PREFIX];
@@ -1046,6 +1049,301 @@ impl Display for ViewPath {
         Ok(())
     }
 }
+
+/// `SchedulerPath` is the full URL path of a replication scheduler resource,
+/// which CouchDB 2.1 and later provide.
+///
+/// # Summary
+///
+/// * `/_scheduler/jobs` lists the replication jobs, and
+///   `/_scheduler/jobs/{job_id}` is one job.
+///
+/// * `/_scheduler/docs` lists the replication documents of all replicator
+///   databases, `/_scheduler/docs/{db}` lists those of one replicator
+///   database, and `/_scheduler/docs/{db}/{doc}` is one replication document.
+///
+/// For more information about path-related types, see the [module-level
+/// documentation](index.html).
+///
+/// # Example
+///
+/// ```rust
+/// extern crate couchdb;
+///
+/// let path = couchdb::SchedulerPath::document(couchdb::DatabaseName::new("_replicator"), "alpha");
+/// assert_eq!(path.to_string(), "/_scheduler/docs/_replicator/alpha");
+///
+/// let path = couchdb::SchedulerPath::parse("/_scheduler/jobs").unwrap();
+/// assert_eq!(path, couchdb::SchedulerPath::jobs());
+/// ```
+///
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SchedulerPath {
+    inner: SchedulerPathInner,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+enum SchedulerPathInner {
+    Jobs,
+    Job(String),
+    Docs,
+    DatabaseDocs(DatabaseName),
+    Doc(DatabaseName, DocumentId),
+}
+
+impl SchedulerPath {
+    /// Tries to construct a scheduler path from a string.
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        SchedulerPath::from_str(s)
+    }
+
+    /// Constructs the path `/_scheduler/jobs`.
+    pub fn jobs() -> Self {
+        SchedulerPath { inner: SchedulerPathInner::Jobs }
+    }
+
+    /// Constructs the path `/_scheduler/jobs/{job_id}`.
+    pub fn job<T: Into<String>>(job_id: T) -> Self {
+        SchedulerPath { inner: SchedulerPathInner::Job(job_id.into()) }
+    }
+
+    /// Constructs the path `/_scheduler/docs`.
+    pub fn documents() -> Self {
+        SchedulerPath { inner: SchedulerPathInner::Docs }
+    }
+
+    /// Constructs the path `/_scheduler/docs/{db}`.
+    pub fn database_documents(db_name: DatabaseName) -> Self {
+        SchedulerPath { inner: SchedulerPathInner::DatabaseDocs(db_name) }
+    }
+
+    /// Constructs the path `/_scheduler/docs/{db}/{doc}`.
+    pub fn document<T: Into<DocumentId>>(db_name: DatabaseName, doc_id: T) -> Self {
+        SchedulerPath { inner: SchedulerPathInner::Doc(db_name, doc_id.into()) }
+    }
+
+    /// Borrows the path's replicator database name, if any.
+    pub fn database_name(&self) -> Option<&DatabaseName> {
+        match self.inner {
+            SchedulerPathInner::DatabaseDocs(ref db_name) |
+            SchedulerPathInner::Doc(ref db_name, _) => Some(db_name),
+            _ => None,
+        }
+    }
+
+    /// Borrows the path's replication document id, if any.
+    pub fn document_id(&self) -> Option<&DocumentId> {
+        match self.inner {
+            SchedulerPathInner::Doc(_, ref doc_id) => Some(doc_id),
+            _ => None,
+        }
+    }
+
+    /// Borrows the path's job id, if any.
+    pub fn job_id(&self) -> Option<&str> {
+        match self.inner {
+            SchedulerPathInner::Job(ref job_id) => Some(job_id),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for SchedulerPath {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut p = PathDecoder::begin(s)?;
+        p.decode_exact(SCHEDULER_PREFIX)?;
+        let kind: String = p.decode_segment()?;
+        let inner = match kind.as_str() {
+            SCHEDULER_JOBS if p.cursor.is_empty() => SchedulerPathInner::Jobs,
+            SCHEDULER_JOBS => SchedulerPathInner::Job(p.decode_segment()?),
+            SCHEDULER_DOCS if p.cursor.is_empty() => SchedulerPathInner::Docs,
+            SCHEDULER_DOCS => {
+                let db_name = p.decode_segment()?;
+                if p.cursor.is_empty() {
+                    SchedulerPathInner::DatabaseDocs(db_name)
+                } else {
+                    SchedulerPathInner::Doc(db_name, p.decode_with_optional_prefix(DOCUMENT_PREFIXES)?)
+                }
+            }
+            _ => return Err(Error::bad_path(E_UNEXPECTED_SEGMENT)),
+        };
+        p.end()?;
+        Ok(SchedulerPath { inner: inner })
+    }
+}
+
+impl Display for SchedulerPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        percent_encode_segment(SCHEDULER_PREFIX, f)?;
+        match self.inner {
+            SchedulerPathInner::Jobs => percent_encode_segment(SCHEDULER_JOBS, f)?,
+            SchedulerPathInner::Job(ref job_id) => {
+                percent_encode_segment(SCHEDULER_JOBS, f)?;
+                percent_encode_segment(job_id, f)?;
+            }
+            SchedulerPathInner::Docs => percent_encode_segment(SCHEDULER_DOCS, f)?,
+            SchedulerPathInner::DatabaseDocs(ref db_name) => {
+                percent_encode_segment(SCHEDULER_DOCS, f)?;
+                db_name.encode_path_to(f)?;
+            }
+            SchedulerPathInner::Doc(ref db_name, ref doc_id) => {
+                percent_encode_segment(SCHEDULER_DOCS, f)?;
+                db_name.encode_path_to(f)?;
+                doc_id.encode_path_to(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "proptest")]
+mod arbitrary {
+    use super::*;
+    use proptest::arbitrary::Arbitrary;
+    use proptest::prelude::*;
+    use proptest::strategy::BoxedStrategy;
+
+    // Any non-empty string is a valid path segment, owing to percent-encoding.
+    const ANY_NAME: &str = ".+";
+
+    // CouchDB restricts database names to this pattern.
+    const DATABASE_NAME: &str = "[a-z][a-z0-9_$()+/-]*";
+
+    // Normal document names may not begin with an underscore, which CouchDB
+    // reserves for special documents.
+    const NORMAL_DOCUMENT_NAME: &str = "[^_\\n].*";
+
+    macro_rules! impl_arbitrary_for_name_type {
+        ($type_name:ident, $pattern:expr) => {
+            impl Arbitrary for $type_name {
+                type Parameters = ();
+                type Strategy = BoxedStrategy<Self>;
+                fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+                    $pattern.prop_map($type_name::from).boxed()
+                }
+            }
+        };
+    }
+
+    impl_arbitrary_for_name_type!(DatabaseName, DATABASE_NAME);
+    impl_arbitrary_for_name_type!(NormalDocumentName, NORMAL_DOCUMENT_NAME);
+    impl_arbitrary_for_name_type!(DesignDocumentName, ANY_NAME);
+    impl_arbitrary_for_name_type!(LocalDocumentName, ANY_NAME);
+    impl_arbitrary_for_name_type!(AttachmentName, ANY_NAME);
+    impl_arbitrary_for_name_type!(ViewName, ANY_NAME);
+
+    impl Arbitrary for DocumentId {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            prop_oneof![
+                any::<NormalDocumentName>().prop_map(DocumentId::from),
+                any::<DesignDocumentName>().prop_map(DocumentId::from),
+                any::<LocalDocumentName>().prop_map(DocumentId::from),
+            ].boxed()
+        }
+    }
+
+    impl Arbitrary for DesignDocumentId {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            any::<DesignDocumentName>()
+                .prop_map(DesignDocumentId::from)
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for ViewId {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (any::<DesignDocumentName>(), any::<ViewName>())
+                .prop_map(|(ddoc_name, view_name)| ViewId::new(ddoc_name, view_name))
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for DatabasePath {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            any::<DatabaseName>()
+                .prop_map(|db_name| DatabasePath { db_name: db_name })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for DocumentPath {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (any::<DatabaseName>(), any::<DocumentId>())
+                .prop_map(|(db_name, doc_id)| {
+                    DocumentPath {
+                        db_name: db_name,
+                        doc_id: doc_id,
+                    }
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for DesignDocumentPath {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (any::<DatabaseName>(), any::<DesignDocumentId>())
+                .prop_map(|(db_name, ddoc_id)| {
+                    DesignDocumentPath {
+                        db_name: db_name,
+                        ddoc_id: ddoc_id,
+                    }
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for AttachmentPath {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (
+                any::<DatabaseName>(),
+                any::<DocumentId>(),
+                any::<AttachmentName>(),
+            ).prop_map(|(db_name, doc_id, att_name)| {
+                    AttachmentPath {
+                        db_name: db_name,
+                        doc_id: doc_id,
+                        att_name: att_name,
+                    }
+                })
+                .boxed()
+        }
+    }
+
+    impl Arbitrary for ViewPath {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (
+                any::<DatabaseName>(),
+                any::<DesignDocumentId>(),
+                any::<ViewName>(),
+            ).prop_map(|(db_name, ddoc_id, view_name)| {
+                    ViewPath {
+                        db_name: db_name,
+                        ddoc_id: ddoc_id,
+                        view_name: view_name,
+                    }
+                })
+                .boxed()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1556,4 +1854,113 @@ mod tests {
             .with_view_name("golf");
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn scheduler_path_percent_encodes_itself() {
+        assert_eq!(SchedulerPath::jobs().to_string(), "/_scheduler/jobs");
+        assert_eq!(
+            SchedulerPath::job("alpha+continuous").to_string(),
+            "/_scheduler/jobs/alpha+continuous"
+        );
+        assert_eq!(SchedulerPath::documents().to_string(), "/_scheduler/docs");
+        assert_eq!(
+            SchedulerPath::database_documents(DatabaseName::new("alpha/_replicator")).to_string(),
+            "/_scheduler/docs/alpha%2F_replicator"
+        );
+        assert_eq!(
+            SchedulerPath::document(DatabaseName::new("alpha/_replicator"), "bravo/charlie").to_string(),
+            "/_scheduler/docs/alpha%2F_replicator/bravo%2Fcharlie"
+        );
+    }
+
+    #[test]
+    fn scheduler_path_decodes_str() {
+        let cases = vec![
+            SchedulerPath::jobs(),
+            SchedulerPath::job("alpha+continuous"),
+            SchedulerPath::documents(),
+            SchedulerPath::database_documents(DatabaseName::new("alpha/_replicator")),
+            SchedulerPath::document(DatabaseName::new("alpha/_replicator"), "bravo/charlie"),
+        ];
+        for expected in cases {
+            assert_eq!(SchedulerPath::from_str(&expected.to_string()).unwrap(), expected);
+        }
+
+        let got = SchedulerPath::from_str("/_scheduler/docs/_replicator/bravo").unwrap();
+        assert_eq!(got.database_name(), Some(&DatabaseName::new("_replicator")));
+        assert_eq!(got.document_id(), Some(&DocumentId::from("bravo")));
+
+        match SchedulerPath::from_str("/_scheduler/tasks") {
+            Err(Error::BadPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        match SchedulerPath::from_str("/_scheduler/jobs/alpha/bravo") {
+            Err(Error::BadPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    mod round_trip {
+        use super::super::*;
+        use proptest::prelude::*;
+        use serde_json;
+
+        proptest! {
+            #[test]
+            fn database_name_round_trips(x in any::<DatabaseName>()) {
+                let s = serde_json::to_string(&x).unwrap();
+                prop_assert_eq!(serde_json::from_str::<DatabaseName>(&s).unwrap(), x);
+            }
+
+            #[test]
+            fn document_id_round_trips(x in any::<DocumentId>()) {
+                let s = serde_json::to_string(&x).unwrap();
+                prop_assert_eq!(serde_json::from_str::<DocumentId>(&s).unwrap(), x);
+            }
+
+            #[test]
+            fn design_document_id_round_trips(x in any::<DesignDocumentId>()) {
+                prop_assert_eq!(DesignDocumentId::parse(x.as_ref()).unwrap(), x);
+            }
+
+            #[test]
+            fn attachment_name_round_trips(x in any::<AttachmentName>()) {
+                let s = serde_json::to_string(&x).unwrap();
+                prop_assert_eq!(serde_json::from_str::<AttachmentName>(&s).unwrap(), x);
+            }
+
+            #[test]
+            fn view_name_round_trips(x in any::<ViewName>()) {
+                let s = serde_json::to_string(&x).unwrap();
+                prop_assert_eq!(serde_json::from_str::<ViewName>(&s).unwrap(), x);
+            }
+
+            #[test]
+            fn database_path_round_trips(x in any::<DatabasePath>()) {
+                prop_assert_eq!(DatabasePath::parse(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn document_path_round_trips(x in any::<DocumentPath>()) {
+                prop_assert_eq!(DocumentPath::parse(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn design_document_path_round_trips(x in any::<DesignDocumentPath>()) {
+                prop_assert_eq!(DesignDocumentPath::parse(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn attachment_path_round_trips(x in any::<AttachmentPath>()) {
+                prop_assert_eq!(AttachmentPath::parse(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn view_path_round_trips(x in any::<ViewPath>()) {
+                prop_assert_eq!(ViewPath::parse(&x.to_string()).unwrap(), x);
+            }
+        }
+    }
 }
@@ -0,0 +1,623 @@
+//! The `range` module provides types for requesting and reassembling partial
+//! attachment content via HTTP range requests.
+//!
+//! # Summary
+//!
+//! * The CouchDB server honors the HTTP `Range` header when getting an
+//!   attachment—i.e., `GET /{db}/{doc}/{attachment}`—provided the server stores
+//!   the attachment's content unencoded. See the
+//!   [`Compression`](../attachment/struct.Compression.html) type for predicting
+//!   whether the server compresses an attachment.
+//!
+//! * `RangeRequest` constructs the `Range` header for an attachment from one or
+//!   more `ByteRange` values.
+//!
+//! * The server responds to a satisfiable range request with `206 Partial
+//!   Content`. `PartialAttachment` decodes such a response—either a single
+//!   range described by the `Content-Range` header or multiple ranges in a
+//!   `multipart/byteranges` body.
+//!
+//! * `PartialAttachment` accumulates content across responses and, once
+//!   complete, checks the reassembled content against the attachment's digest.
+//!
+//! # Remarks
+//!
+//! The server ignores the `Range` header for an attachment it stores
+//! compressed and instead responds with `200 OK` and the full content.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate couchdb;
+//!
+//! use couchdb::range::{ByteRange, PartialAttachment, RangeRequest};
+//!
+//! let path = couchdb::AttachmentPath::parse("/db/doc/video.mp4").unwrap();
+//! let request = RangeRequest::new(path, vec![ByteRange::Bounded { first: 0, last: 4 }]);
+//!
+//! // Send this as the request's `Range` header.
+//! assert_eq!(request.range_header(), "bytes=0-4");
+//!
+//! // Decode the server's `206 Partial Content` response.
+//! let content_type = "video/mp4".parse().unwrap();
+//! let mut partial = PartialAttachment::decode(&content_type, Some("bytes 0-4/11"), b"Hello")
+//!     .unwrap();
+//!
+//! // Request the rest.
+//! assert_eq!(partial.missing_ranges(), vec![ByteRange::Bounded { first: 5, last: 10 }]);
+//! let rest = PartialAttachment::decode(&content_type, Some("bytes 5-10/11"), b" world")
+//!     .unwrap();
+//! partial.merge(rest).unwrap();
+//!
+//! let digest = couchdb::Digest::compute_md5(b"Hello world");
+//! assert_eq!(partial.into_content(&digest).unwrap(), b"Hello world");
+//! ```
+
+use {AttachmentPath, Digest, Error, std};
+use mime::Mime;
+use multipart::parser;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const E_INCOMPLETE: &str = "Partial attachment content is incomplete";
+const E_LENGTH_MISMATCH: &str = "Partial attachment content does not match its content range";
+const E_NO_CONTENT_RANGE: &str = "Partial attachment content has no content range";
+const E_TOTAL_LENGTH_MISMATCH: &str = "Partial attachment content ranges disagree on the total length";
+const E_UNKNOWN_TOTAL_LENGTH: &str = "Partial attachment content has an unknown total length";
+const E_DIGEST_MISMATCH: &str = "Attachment content does not match its digest";
+
+/// `ByteRange` is a range of bytes within an attachment's content, as used in
+/// the HTTP `Range` header.
+///
+/// Byte offsets are zero-based, and bounded ranges are inclusive, as in HTTP.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ByteRange {
+    /// The bytes from `first` to `last`, inclusive—e.g., `0-499`.
+    Bounded { first: u64, last: u64 },
+
+    /// The bytes from `first` to the end of the content—e.g., `500-`.
+    From { first: u64 },
+
+    /// The final `length` bytes of the content—e.g., `-500`.
+    Suffix { length: u64 },
+}
+
+/// `RangeRequest` describes an HTTP range request for an attachment's content.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RangeRequest {
+    path: AttachmentPath,
+    ranges: Vec<ByteRange>,
+}
+
+/// `ContentRange` is the value of an HTTP `Content-Range` header in a `206
+/// Partial Content` response—e.g., `bytes 0-499/1234`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ContentRange {
+    first: u64,
+    last: u64,
+    complete_length: Option<u64>,
+}
+
+/// `PartialAttachment` accumulates partial attachment content obtained via
+/// HTTP range requests.
+///
+/// See the [module documentation](index.html) for an example.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartialAttachment {
+    content_length: u64,
+
+    // Chunks are keyed by byte offset and never overlap.
+    chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl ByteRange {
+    /// Resolves the range against the given content length, returning the
+    /// offsets of the first and last bytes, inclusive.
+    ///
+    /// This method returns `None` if the range is unsatisfiable—i.e., it
+    /// selects no bytes of the content.
+    ///
+    pub fn resolve(&self, content_length: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::Bounded { first, last } if first <= last && first < content_length => {
+                Some((first, std::cmp::min(last, content_length - 1)))
+            }
+            ByteRange::From { first } if first < content_length => Some((first, content_length - 1)),
+            ByteRange::Suffix { length } if 0 < length && 0 < content_length => {
+                Some((content_length.saturating_sub(length), content_length - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            ByteRange::Bounded { first, last } => write!(f, "{}-{}", first, last),
+            ByteRange::From { first } => write!(f, "{}-", first),
+            ByteRange::Suffix { length } => write!(f, "-{}", length),
+        }
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        let i = s.find('-').ok_or(Error::bad_range("Byte range has no hyphen"))?;
+        let (first, last) = (s[..i].trim(), s[i + 1..].trim());
+
+        match (first.is_empty(), last.is_empty()) {
+            (false, false) => {
+                let first = parse_offset(first)?;
+                let last = parse_offset(last)?;
+                if last < first {
+                    return Err(Error::bad_range("Byte range ends before it begins"));
+                }
+                Ok(ByteRange::Bounded {
+                    first: first,
+                    last: last,
+                })
+            }
+            (false, true) => Ok(ByteRange::From { first: parse_offset(first)? }),
+            (true, false) => Ok(ByteRange::Suffix { length: parse_offset(last)? }),
+            (true, true) => Err(Error::bad_range("Byte range has no offsets")),
+        }
+    }
+}
+
+impl RangeRequest {
+    /// Constructs a new range request for the given attachment and byte
+    /// ranges.
+    pub fn new<R: IntoIterator<Item = ByteRange>>(path: AttachmentPath, ranges: R) -> Self {
+        RangeRequest {
+            path: path,
+            ranges: ranges.into_iter().collect(),
+        }
+    }
+
+    /// Borrows the path of the attachment to get.
+    pub fn path(&self) -> &AttachmentPath {
+        &self.path
+    }
+
+    /// Borrows the requested byte ranges.
+    pub fn ranges(&self) -> &[ByteRange] {
+        &self.ranges
+    }
+
+    /// Formats the value of the request's `Range` header—e.g.,
+    /// `bytes=0-499,1000-`.
+    pub fn range_header(&self) -> String {
+        let ranges = self.ranges
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        format!("bytes={}", ranges.join(","))
+    }
+}
+
+impl ContentRange {
+    /// Returns the offset of the range's first byte.
+    pub fn first(&self) -> u64 {
+        self.first
+    }
+
+    /// Returns the offset of the range's last byte, inclusive.
+    pub fn last(&self) -> u64 {
+        self.last
+    }
+
+    /// Returns the number of bytes in the range.
+    pub fn length(&self) -> u64 {
+        self.last - self.first + 1
+    }
+
+    /// Returns the length of the complete content, if known.
+    pub fn complete_length(&self) -> Option<u64> {
+        self.complete_length
+    }
+}
+
+impl std::fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "bytes {}-{}/", self.first, self.last)?;
+        match self.complete_length {
+            Some(x) => write!(f, "{}", x),
+            None => f.write_str("*"),
+        }
+    }
+}
+
+impl FromStr for ContentRange {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+
+        let s = s.trim();
+        if !s.starts_with("bytes ") {
+            return Err(Error::bad_range("Content range unit is not bytes"));
+        }
+
+        let mut parts = s["bytes ".len()..].splitn(2, '/');
+        let range = parts.next().unwrap();
+        let complete_length = parts.next().ok_or(Error::bad_range("Content range has no complete length"))?;
+
+        let (first, last) = match ByteRange::from_str(range)? {
+            ByteRange::Bounded { first, last } => (first, last),
+            _ => return Err(Error::bad_range("Content range is not bounded")),
+        };
+
+        let complete_length = match complete_length.trim() {
+            "*" => None,
+            x => {
+                let x = parse_offset(x)?;
+                if x <= last {
+                    return Err(Error::bad_range("Content range ends beyond the complete length"));
+                }
+                Some(x)
+            }
+        };
+
+        Ok(ContentRange {
+            first: first,
+            last: last,
+            complete_length: complete_length,
+        })
+    }
+}
+
+impl PartialAttachment {
+    /// Constructs a new partial attachment that has no content.
+    ///
+    /// The content length is the size of the attachment's complete content, in
+    /// bytes.
+    ///
+    pub fn new(content_length: u64) -> Self {
+        PartialAttachment {
+            content_length: content_length,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    /// Decodes the body of a `206 Partial Content` response.
+    ///
+    /// The content type must be the MIME type the server sent in the response's
+    /// `Content-Type` header, and the content range must be the value of the
+    /// response's `Content-Range` header, if any. A response containing
+    /// multiple ranges has a `multipart/byteranges` content type and no
+    /// `Content-Range` header.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if any range is malformed, has no known
+    /// complete length, or disagrees with the size of its content.
+    ///
+    pub fn decode(content_type: &Mime, content_range: Option<&str>, body: &[u8]) -> Result<Self, Error> {
+
+        if let Ok(boundary) = parser::boundary(content_type, "byteranges") {
+            let mut partial: Option<PartialAttachment> = None;
+            for part in parser::parse(boundary, body)? {
+                let content_range = part.header("Content-Range").ok_or(
+                    Error::bad_attachment(E_NO_CONTENT_RANGE),
+                )?;
+                let other = PartialAttachment::decode_range(content_range, part.body())?;
+                match partial {
+                    None => partial = Some(other),
+                    Some(ref mut x) => x.merge(other)?,
+                }
+            }
+            return partial.ok_or(Error::bad_attachment(E_NO_CONTENT_RANGE));
+        }
+
+        let content_range = content_range.ok_or(Error::bad_attachment(E_NO_CONTENT_RANGE))?;
+        PartialAttachment::decode_range(content_range, body)
+    }
+
+    fn decode_range(content_range: &str, body: &[u8]) -> Result<Self, Error> {
+        let range = ContentRange::from_str(content_range)?;
+        let complete_length = range.complete_length().ok_or(Error::bad_attachment(E_UNKNOWN_TOTAL_LENGTH))?;
+        let mut partial = PartialAttachment::new(complete_length);
+        partial.insert(&range, body)?;
+        Ok(partial)
+    }
+
+    /// Returns the size of the attachment's complete content, in bytes.
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+
+    /// Adds content for the given range.
+    ///
+    /// Content overlapping content already added is ignored.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the content's size differs from the
+    /// range's size or if the range's complete length, if known, differs from
+    /// the partial attachment's content length.
+    ///
+    pub fn insert(&mut self, range: &ContentRange, content: &[u8]) -> Result<(), Error> {
+
+        if range.length() != content.len() as u64 || self.content_length <= range.last() {
+            return Err(Error::bad_attachment(E_LENGTH_MISMATCH));
+        }
+
+        if range.complete_length().map(|x| x != self.content_length).unwrap_or(false) {
+            return Err(Error::bad_attachment(E_TOTAL_LENGTH_MISMATCH));
+        }
+
+        // Split the content into the pieces not yet covered by existing chunks.
+
+        let (first, end) = (range.first(), range.last() + 1);
+        let mut cursor = first;
+        let mut pieces = Vec::new();
+
+        for (&offset, chunk) in self.chunks.range(..end) {
+            let chunk_end = offset + chunk.len() as u64;
+            if chunk_end <= cursor {
+                continue;
+            }
+            if cursor < offset {
+                pieces.push((cursor, offset));
+            }
+            cursor = std::cmp::max(cursor, chunk_end);
+        }
+
+        if cursor < end {
+            pieces.push((cursor, end));
+        }
+
+        for (a, b) in pieces {
+            let bytes = &content[(a - first) as usize..(b - first) as usize];
+            self.chunks.insert(a, Vec::from(bytes));
+        }
+
+        Ok(())
+    }
+
+    /// Adds all content from another partial attachment of the same
+    /// attachment.
+    pub fn merge(&mut self, other: PartialAttachment) -> Result<(), Error> {
+
+        if other.content_length != self.content_length {
+            return Err(Error::bad_attachment(E_TOTAL_LENGTH_MISMATCH));
+        }
+
+        for (offset, chunk) in other.chunks {
+            let range = ContentRange {
+                first: offset,
+                last: offset + chunk.len() as u64 - 1,
+                complete_length: Some(self.content_length),
+            };
+            self.insert(&range, &chunk)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ranges of content not yet added, in order.
+    ///
+    /// The application may request these ranges to complete the attachment.
+    ///
+    pub fn missing_ranges(&self) -> Vec<ByteRange> {
+
+        let mut ranges = Vec::new();
+        let mut cursor = 0;
+
+        for (&offset, chunk) in &self.chunks {
+            if cursor < offset {
+                ranges.push(ByteRange::Bounded {
+                    first: cursor,
+                    last: offset - 1,
+                });
+            }
+            cursor = offset + chunk.len() as u64;
+        }
+
+        if cursor < self.content_length {
+            ranges.push(ByteRange::Bounded {
+                first: cursor,
+                last: self.content_length - 1,
+            });
+        }
+
+        ranges
+    }
+
+    /// Returns whether all content has been added.
+    pub fn is_complete(&self) -> bool {
+        self.missing_ranges().is_empty()
+    }
+
+    /// Reassembles the complete content and checks it against the given
+    /// digest.
+    ///
+    /// The digest should be the attachment's digest as reported by the server.
+    /// Only MD5 digests are supported.
+    ///
+    /// # Errors
+    ///
+    /// This method returns an error if the content is incomplete or does not
+    /// match the digest.
+    ///
+    pub fn into_content(self, digest: &Digest) -> Result<Vec<u8>, Error> {
+
+        if !self.is_complete() {
+            return Err(Error::bad_attachment(E_INCOMPLETE));
+        }
+
+        let mut content = Vec::with_capacity(self.content_length as usize);
+        for (_, chunk) in self.chunks {
+            content.extend(chunk);
+        }
+
+        if !digest.verify(&content) {
+            return Err(Error::bad_attachment(E_DIGEST_MISMATCH));
+        }
+
+        Ok(content)
+    }
+}
+
+fn parse_offset(s: &str) -> Result<u64, Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::bad_range("Byte offset is not a decimal number"));
+    }
+    u64::from_str(s).map_err(|_| Error::bad_range("Byte offset is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_range(first: u64, last: u64, complete_length: u64) -> ContentRange {
+        ContentRange {
+            first: first,
+            last: last,
+            complete_length: Some(complete_length),
+        }
+    }
+
+    #[test]
+    fn byte_range_display_and_parse() {
+        let cases = vec![
+            ("0-499", ByteRange::Bounded { first: 0, last: 499 }),
+            ("500-", ByteRange::From { first: 500 }),
+            ("-500", ByteRange::Suffix { length: 500 }),
+        ];
+        for (s, range) in cases {
+            assert_eq!(range.to_string(), s);
+            assert_eq!(ByteRange::from_str(s).unwrap(), range);
+        }
+
+        for s in &["", "-", "5", "10-5", "a-b", "+1-2"] {
+            match ByteRange::from_str(s) {
+                Err(Error::BadRange { .. }) => {}
+                x => panic!("Got unexpected result {:?} for {:?}", x, s),
+            }
+        }
+    }
+
+    #[test]
+    fn byte_range_resolves_against_content_length() {
+        assert_eq!(ByteRange::Bounded { first: 2, last: 99 }.resolve(10), Some((2, 9)));
+        assert_eq!(ByteRange::Bounded { first: 10, last: 99 }.resolve(10), None);
+        assert_eq!(ByteRange::From { first: 3 }.resolve(10), Some((3, 9)));
+        assert_eq!(ByteRange::Suffix { length: 4 }.resolve(10), Some((6, 9)));
+        assert_eq!(ByteRange::Suffix { length: 40 }.resolve(10), Some((0, 9)));
+        assert_eq!(ByteRange::Suffix { length: 0 }.resolve(10), None);
+    }
+
+    #[test]
+    fn range_request_formats_range_header() {
+        let path = AttachmentPath::parse("/db/doc/att").unwrap();
+        let request = RangeRequest::new(
+            path,
+            vec![
+                ByteRange::Bounded { first: 0, last: 99 },
+                ByteRange::Suffix { length: 10 },
+            ],
+        );
+        assert_eq!(request.range_header(), "bytes=0-99,-10");
+    }
+
+    #[test]
+    fn content_range_display_and_parse() {
+        let got = ContentRange::from_str("bytes 0-499/1234").unwrap();
+        assert_eq!(got, content_range(0, 499, 1234));
+        assert_eq!(got.length(), 500);
+        assert_eq!(got.to_string(), "bytes 0-499/1234");
+
+        let got = ContentRange::from_str("bytes 5-9/*").unwrap();
+        assert_eq!(got.complete_length(), None);
+        assert_eq!(got.to_string(), "bytes 5-9/*");
+
+        for s in &["bytes */1234", "bytes 0-499/499", "bytes 0-/1234", "0-499/1234"] {
+            match ContentRange::from_str(s) {
+                Err(Error::BadRange { .. }) => {}
+                x => panic!("Got unexpected result {:?} for {:?}", x, s),
+            }
+        }
+    }
+
+    #[test]
+    fn partial_attachment_decodes_multipart_byteranges() {
+
+        let content_type = "multipart/byteranges; boundary=abc123".parse().unwrap();
+        let body = b"--abc123\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Range: bytes 0-4/11\r\n\
+            \r\n\
+            Hello\r\n\
+            --abc123\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Range: bytes 8-10/11\r\n\
+            \r\n\
+            rld\r\n\
+            --abc123--";
+
+        let got = PartialAttachment::decode(&content_type, None, body).unwrap();
+        assert_eq!(got.content_length(), 11);
+        assert_eq!(
+            got.missing_ranges(),
+            vec![ByteRange::Bounded { first: 5, last: 7 }]
+        );
+    }
+
+    #[test]
+    fn partial_attachment_decode_requires_content_range() {
+        let content_type = "text/plain".parse().unwrap();
+        PartialAttachment::decode(&content_type, None, b"Hello").unwrap_err();
+        PartialAttachment::decode(&content_type, Some("bytes 0-4/*"), b"Hello").unwrap_err();
+        PartialAttachment::decode(&content_type, Some("bytes 0-5/11"), b"Hello").unwrap_err();
+    }
+
+    #[test]
+    fn partial_attachment_ignores_overlapping_content() {
+
+        let mut partial = PartialAttachment::new(11);
+        partial.insert(&content_range(2, 4, 11), b"llo").unwrap();
+        partial.insert(&content_range(7, 8, 11), b"or").unwrap();
+        partial.insert(&content_range(0, 9, 11), b"Hello worl").unwrap();
+        assert_eq!(
+            partial.missing_ranges(),
+            vec![ByteRange::Bounded { first: 10, last: 10 }]
+        );
+
+        partial.insert(&content_range(10, 10, 11), b"d").unwrap();
+        assert!(partial.is_complete());
+
+        let digest = Digest::compute_md5(b"Hello world");
+        assert_eq!(partial.into_content(&digest).unwrap(), b"Hello world");
+    }
+
+    #[test]
+    fn partial_attachment_rejects_mismatched_lengths() {
+        let mut partial = PartialAttachment::new(11);
+        partial.insert(&content_range(0, 4, 12), b"Hello").unwrap_err();
+        partial.insert(&content_range(0, 4, 11), b"Hell").unwrap_err();
+        partial.merge(PartialAttachment::new(12)).unwrap_err();
+    }
+
+    #[test]
+    fn partial_attachment_checks_digest() {
+
+        let mut partial = PartialAttachment::new(5);
+        partial.insert(&content_range(0, 2, 5), b"Hel").unwrap();
+
+        let digest = Digest::compute_md5(b"Hello");
+        match partial.clone().into_content(&digest) {
+            Err(Error::BadAttachment { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        partial.insert(&content_range(3, 4, 5), b"lp").unwrap();
+        match partial.into_content(&digest) {
+            Err(Error::BadAttachment { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+}
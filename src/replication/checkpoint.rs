@@ -0,0 +1,273 @@
+use {DocumentId, Sequence};
+use std::marker::PhantomData;
+
+/// `ReplicationCheckpoint` is the `_local` document in which a replicator
+/// records its progress, on both the source and the target.
+///
+/// # Summary
+///
+/// * `ReplicationCheckpoint` has public members instead of accessor methods
+///   because there are no invariants restricting the data.
+///
+/// * `ReplicationCheckpoint` implements `Deserialize` and `Serialize`, in the
+///   same format the CouchDB replicator uses.
+///
+/// * The `resume_seq` method compares the source's and target's checkpoints
+///   to find where a replication should resume.
+///
+/// # Remarks
+///
+/// The document id is `_local/{replication_id}`, where `replication_id` is
+/// the base part of a [`ReplicationId`](struct.ReplicationId.html)—i.e.,
+/// without any `+continuous` or `+create_target` extension.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"{
+///     "_id": "_local/0a81b645497e6270611ec3419767a584",
+///     "_rev": "0-3",
+///     "session_id": "d5a34cbbdafa70e0db5cb57d02a6b955",
+///     "source_last_seq": 42,
+///     "replication_id_version": 3,
+///     "history": [
+///         {
+///             "session_id": "d5a34cbbdafa70e0db5cb57d02a6b955",
+///             "start_time": "Mon, 24 Jul 2017 15:09:41 GMT",
+///             "end_time": "Mon, 24 Jul 2017 15:09:42 GMT",
+///             "start_last_seq": 0,
+///             "end_last_seq": 42,
+///             "recorded_seq": 42,
+///             "missing_checked": 17,
+///             "missing_found": 17,
+///             "docs_read": 17,
+///             "docs_written": 17,
+///             "doc_write_failures": 0
+///         }
+///     ]
+/// }"#;
+///
+/// let checkpoint: couchdb::replication::ReplicationCheckpoint =
+///     serde_json::from_slice(body).unwrap();
+///
+/// assert_eq!(checkpoint.source_last_seq, couchdb::Sequence::from(42));
+/// assert_eq!(checkpoint.history[0].docs_written, 17);
+/// assert_eq!(checkpoint.rev, Some(String::from("0-3")));
+/// ```
+///
+/// # Compatibility
+///
+/// `ReplicationCheckpoint` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicationCheckpoint` instance.
+/// This allows new fields to be added to `ReplicationCheckpoint` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ReplicationCheckpoint {
+    /// The document id, which includes the `_local/` prefix.
+    #[serde(default, rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<DocumentId>,
+
+    /// The document revision, such as `0-3`. Local document revisions are not
+    /// valid [`Revision`](../struct.Revision.html) values.
+    #[serde(default, rename = "_rev", skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+
+    /// The id of the replication session that last recorded the checkpoint.
+    pub session_id: String,
+
+    /// The source sequence up to which the replication has progressed.
+    pub source_last_seq: Sequence,
+
+    /// The version of the algorithm that computed the replication id. CouchDB
+    /// 1.2 and later record version 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replication_id_version: Option<u64>,
+
+    /// The replication sessions that recorded the checkpoint, newest first.
+    #[serde(default)]
+    pub history: Vec<ReplicationHistory>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ReplicationHistory` describes one replication session within a
+/// `ReplicationCheckpoint`.
+///
+/// The statistics are cumulative for the session.
+///
+/// # Compatibility
+///
+/// `ReplicationHistory` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicationHistory` instance.
+/// This allows new fields to be added to `ReplicationHistory` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ReplicationHistory {
+    pub session_id: String,
+
+    /// The session's start time, in RFC 1123 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<String>,
+
+    /// The time of the session's latest checkpoint, in RFC 1123 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<String>,
+
+    #[serde(default)]
+    pub start_last_seq: Sequence,
+
+    #[serde(default)]
+    pub end_last_seq: Sequence,
+
+    /// The source sequence the session recorded.
+    pub recorded_seq: Sequence,
+
+    #[serde(default)]
+    pub missing_checked: u64,
+
+    #[serde(default)]
+    pub missing_found: u64,
+
+    #[serde(default)]
+    pub docs_read: u64,
+
+    #[serde(default)]
+    pub docs_written: u64,
+
+    #[serde(default)]
+    pub doc_write_failures: u64,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+impl ReplicationCheckpoint {
+    /// Constructs a checkpoint without an id, revision, or history.
+    pub fn new<T: Into<String>>(session_id: T, source_last_seq: Sequence) -> Self {
+        ReplicationCheckpoint {
+            session_id: session_id.into(),
+            source_last_seq: source_last_seq,
+            ..ReplicationCheckpoint::default()
+        }
+    }
+
+    /// Finds the source sequence from which a replication should resume,
+    /// given this, the source's checkpoint, and the target's checkpoint.
+    ///
+    /// The comparison is the same as the CouchDB replicator's. If both peers
+    /// recorded the same session then the replication resumes from the
+    /// source's `source_last_seq`. Otherwise, the histories are walked in
+    /// lockstep, newest first: at each step, if the source's entry is in what
+    /// remains of the target's history then the replication resumes from the
+    /// source entry's `recorded_seq`, and, failing that, if the target's entry
+    /// is in what remains of the source's history, after the source's entry,
+    /// then the replication resumes from the target entry's `recorded_seq`.
+    /// If either history runs out first then there is no common sequence, and
+    /// the replication must start over.
+    ///
+    pub fn resume_seq<'a>(&'a self, target: &'a ReplicationCheckpoint) -> Option<&'a Sequence> {
+
+        if self.session_id == target.session_id {
+            return Some(&self.source_last_seq);
+        }
+
+        fn contains(history: &[ReplicationHistory], session_id: &str) -> bool {
+            history.iter().any(|x| x.session_id == session_id)
+        }
+
+        let mut source_history = &self.history[..];
+        let mut target_history = &target.history[..];
+
+        while let (Some((s, source_rest)), Some((t, target_rest))) =
+            (source_history.split_first(), target_history.split_first())
+        {
+            if contains(target_history, &s.session_id) {
+                return Some(&s.recorded_seq);
+            }
+            if contains(source_rest, &t.session_id) {
+                return Some(&t.recorded_seq);
+            }
+            source_history = source_rest;
+            target_history = target_rest;
+        }
+
+        None
+    }
+}
+
+impl ReplicationHistory {
+    /// Constructs a history entry with zeroed statistics.
+    pub fn new<T: Into<String>>(session_id: T, recorded_seq: Sequence) -> Self {
+        ReplicationHistory {
+            session_id: session_id.into(),
+            recorded_seq: recorded_seq,
+            ..ReplicationHistory::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn checkpoint_serializes_without_absent_fields() {
+        let mut checkpoint = ReplicationCheckpoint::new("alpha", Sequence::from(7));
+        checkpoint.history.push(ReplicationHistory::new("alpha", Sequence::from(7)));
+        assert_eq!(
+            serde_json::to_value(&checkpoint).unwrap(),
+            json!({
+                "session_id": "alpha",
+                "source_last_seq": 7,
+                "history": [{
+                    "session_id": "alpha",
+                    "start_last_seq": 0,
+                    "end_last_seq": 0,
+                    "recorded_seq": 7,
+                    "missing_checked": 0,
+                    "missing_found": 0,
+                    "docs_read": 0,
+                    "docs_written": 0,
+                    "doc_write_failures": 0,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn resume_seq_finds_common_session() {
+
+        fn checkpoint(session_id: &str, history: &[(&str, u64)]) -> ReplicationCheckpoint {
+            let mut checkpoint = ReplicationCheckpoint::new(session_id, Sequence::from(history[0].1));
+            checkpoint.history = history
+                .iter()
+                .map(|&(id, seq)| ReplicationHistory::new(id, Sequence::from(seq)))
+                .collect();
+            checkpoint
+        }
+
+        let source = checkpoint("c", &[("c", 30), ("b", 20), ("a", 10)]);
+
+        let same = checkpoint("c", &[("c", 30)]);
+        assert_eq!(source.resume_seq(&same), Some(&Sequence::from(30)));
+
+        let common = checkpoint("d", &[("d", 40), ("b", 20)]);
+        assert_eq!(source.resume_seq(&common), Some(&Sequence::from(20)));
+
+        let later = checkpoint("d", &[("d", 40), ("e", 35), ("a", 12)]);
+        assert_eq!(source.resume_seq(&later), Some(&Sequence::from(10)));
+
+        let target_first = checkpoint("d", &[("b", 22)]);
+        assert_eq!(source.resume_seq(&target_first), Some(&Sequence::from(22)));
+
+        let unrelated = checkpoint("e", &[("e", 50)]);
+        assert_eq!(source.resume_seq(&unrelated), None);
+    }
+}
@@ -0,0 +1,570 @@
+use {DatabaseName, DocumentId, DocumentPath, Revision, Sequence, serde_json};
+use replication::{ReplicationInfo, ReplicationState};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+/// The name of the default replicator database.
+pub const REPLICATOR_DATABASE: &str = "_replicator";
+
+/// Constructs the path of a replication document in the default replicator
+/// database—i.e., `/_replicator/{doc}`.
+pub fn replicator_document_path<T: Into<DocumentId>>(doc_id: T) -> DocumentPath {
+    DatabaseName::new(REPLICATOR_DATABASE).with_document_id(doc_id)
+}
+
+/// `ReplicationDocument` is a document in a replicator database, such as
+/// `_replicator`, that describes a replication for the server to run.
+///
+/// # Summary
+///
+/// * `ReplicationDocument` has public members instead of accessor methods
+///   because there are no invariants restricting the data.
+///
+/// * `ReplicationDocument` implements `Deserialize` and `Serialize`. Absent
+///   options are omitted from the JSON, so that the server applies its
+///   defaults.
+///
+/// * The fields whose names begin with `replication_` are the ones the server
+///   maintains—e.g., `_replication_state`. An application should not set
+///   them.
+///
+/// # Remarks
+///
+/// The server fails a replication document that specifies more than one of
+/// `doc_ids`, `filter`, and `selector`, but such a document still decodes, so
+/// that it does not fail decoding of the whole replicator database. See
+/// [`ReplicationSelection`](enum.ReplicationSelection.html) for the
+/// exclusive form a `_replicate` request uses.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::replication::{RemoteDatabase, ReplicationDocument};
+///
+/// let mut doc = ReplicationDocument::new(
+///     RemoteDatabase::new("http://example.com:5984/alpha").with_basic_auth("bob", "secret"),
+///     couchdb::DatabaseName::new("bravo"),
+/// );
+/// doc.continuous = Some(true);
+///
+/// assert_eq!(
+///     serde_json::to_value(&doc).unwrap(),
+///     serde_json::from_str::<serde_json::Value>(r#"{
+///         "source": {
+///             "url": "http://example.com:5984/alpha",
+///             "auth": {"basic": {"username": "bob", "password": "secret"}}
+///         },
+///         "target": "bravo",
+///         "continuous": true
+///     }"#).unwrap()
+/// );
+/// ```
+///
+/// # Compatibility
+///
+/// `ReplicationDocument` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicationDocument` instance.
+/// This allows new fields to be added to `ReplicationDocument` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ReplicationDocument {
+    #[serde(default, rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<DocumentId>,
+
+    #[serde(default, rename = "_rev", skip_serializing_if = "Option::is_none")]
+    pub rev: Option<Revision>,
+
+    pub source: ReplicationDatabase,
+    pub target: ReplicationDatabase,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuous: Option<bool>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub create_target: Option<bool>,
+
+    /// The name of a filter function, such as `ddoc/filter`, or of a built-in
+    /// filter, such as `_view`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+
+    /// The query parameters the filter function receives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_params: Option<serde_json::Map<String, serde_json::Value>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc_ids: Option<Vec<DocumentId>>,
+
+    /// A Mango selector—CouchDB 2.x and later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<serde_json::Value>,
+
+    /// The source sequence from which to start, overriding any checkpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_seq: Option<Sequence>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_checkpoints: Option<bool>,
+
+    /// The interval between checkpoints, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint_interval: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_processes: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_batch_size: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_connections: Option<u64>,
+
+    /// The HTTP connection timeout, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_timeout: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries_per_request: Option<u64>,
+
+    /// The user context under which a CouchDB 1.x replication runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_ctx: Option<ReplicationUserCtx>,
+
+    /// The user who created the document—CouchDB 2.x and later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    #[serde(default, rename = "_replication_id", skip_serializing_if = "Option::is_none")]
+    pub replication_id: Option<String>,
+
+    #[serde(default, rename = "_replication_state", skip_serializing_if = "Option::is_none")]
+    pub replication_state: Option<ReplicationState>,
+
+    #[serde(default, rename = "_replication_state_time", skip_serializing_if = "Option::is_none")]
+    pub replication_state_time: Option<serde_json::Value>,
+
+    #[serde(default, rename = "_replication_state_reason", skip_serializing_if = "Option::is_none")]
+    pub replication_state_reason: Option<String>,
+
+    #[serde(default, rename = "_replication_stats", skip_serializing_if = "Option::is_none")]
+    pub replication_stats: Option<ReplicationInfo>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ReplicationDatabase` is the source or target database of a replication
+/// document or `_replicate` request.
+///
+/// # Summary
+///
+/// * A local database is one on the replicating server, which the JSON
+///   specifies by name.
+///
+/// * A remote database is one the JSON specifies by URL, optionally with
+///   headers and credentials.
+///
+/// # Remarks
+///
+/// A JSON string containing `://` is a remote database, and any other JSON
+/// string is a local database.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ReplicationDatabase {
+    Local(DatabaseName),
+    Remote(RemoteDatabase),
+}
+
+/// `RemoteDatabase` is a replication source or target that the replicating
+/// server accesses by URL.
+///
+/// # Compatibility
+///
+/// `RemoteDatabase` contains a dummy private member in order to prevent
+/// applications from directly constructing a `RemoteDatabase` instance. This
+/// allows new fields to be added to `RemoteDatabase` in future releases
+/// without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct RemoteDatabase {
+    pub url: String,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<ReplicationAuth>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ReplicationAuth` contains the credentials of a remote database.
+///
+/// # Remarks
+///
+/// An `auth` object this crate does not recognize—e.g., `session` or
+/// Cloudant's `iam`—is kept as is in the `Other` variant so that it does not
+/// fail decoding of the whole document.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ReplicationAuth {
+    /// HTTP basic authentication—CouchDB 2.2 and later.
+    Basic { username: String, password: String },
+
+    /// OAuth 1.0 authentication—CouchDB 1.x only.
+    OAuth {
+        consumer_key: String,
+        consumer_secret: String,
+        token: String,
+        token_secret: String,
+    },
+
+    /// Any other `auth` object.
+    Other(serde_json::Value),
+}
+
+// KnownAuth is the JSON form of the ReplicationAuth variants this crate
+// recognizes.
+#[derive(Deserialize, Serialize)]
+enum KnownAuth {
+    #[serde(rename = "basic")]
+    Basic { username: String, password: String },
+
+    #[serde(rename = "oauth")]
+    OAuth {
+        consumer_key: String,
+        consumer_secret: String,
+        token: String,
+        token_secret: String,
+    },
+}
+
+/// `ReplicationUserCtx` is the user context of a replication document.
+///
+/// # Compatibility
+///
+/// `ReplicationUserCtx` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicationUserCtx` instance.
+/// This allows new fields to be added to `ReplicationUserCtx` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct ReplicationUserCtx {
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub roles: Vec<String>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+impl ReplicationDocument {
+    /// Constructs a replication document with no options.
+    pub fn new<S, T>(source: S, target: T) -> Self
+    where
+        S: Into<ReplicationDatabase>,
+        T: Into<ReplicationDatabase>,
+    {
+        ReplicationDocument {
+            id: None,
+            rev: None,
+            source: source.into(),
+            target: target.into(),
+            continuous: None,
+            create_target: None,
+            filter: None,
+            query_params: None,
+            doc_ids: None,
+            selector: None,
+            since_seq: None,
+            use_checkpoints: None,
+            checkpoint_interval: None,
+            worker_processes: None,
+            worker_batch_size: None,
+            http_connections: None,
+            connection_timeout: None,
+            retries_per_request: None,
+            user_ctx: None,
+            owner: None,
+            replication_id: None,
+            replication_state: None,
+            replication_state_time: None,
+            replication_state_reason: None,
+            replication_stats: None,
+            _private_guard: PhantomData,
+        }
+    }
+}
+
+impl RemoteDatabase {
+    /// Constructs a remote database from its URL.
+    pub fn new<T: Into<String>>(url: T) -> Self {
+        RemoteDatabase {
+            url: url.into(),
+            ..RemoteDatabase::default()
+        }
+    }
+
+    /// Adds an HTTP header the replicating server sends to the database.
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets HTTP basic authentication credentials.
+    pub fn with_basic_auth<U: Into<String>, P: Into<String>>(mut self, username: U, password: P) -> Self {
+        self.auth = Some(ReplicationAuth::Basic {
+            username: username.into(),
+            password: password.into(),
+        });
+        self
+    }
+}
+
+impl ReplicationUserCtx {
+    /// Constructs a user context.
+    pub fn new(name: Option<String>, roles: Vec<String>) -> Self {
+        ReplicationUserCtx {
+            name: name,
+            roles: roles,
+            _private_guard: PhantomData,
+        }
+    }
+}
+
+impl From<DatabaseName> for ReplicationDatabase {
+    fn from(db_name: DatabaseName) -> Self {
+        ReplicationDatabase::Local(db_name)
+    }
+}
+
+impl From<RemoteDatabase> for ReplicationDatabase {
+    fn from(remote: RemoteDatabase) -> Self {
+        ReplicationDatabase::Remote(remote)
+    }
+}
+
+impl Serialize for ReplicationDatabase {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            ReplicationDatabase::Local(ref db_name) => db_name.serialize(serializer),
+            ReplicationDatabase::Remote(ref remote) if remote.headers.is_empty() && remote.auth.is_none() => {
+                remote.url.serialize(serializer)
+            }
+            ReplicationDatabase::Remote(ref remote) => remote.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReplicationDatabase {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            String(String),
+            Remote(RemoteDatabase),
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::String(ref s) if s.contains("://") => ReplicationDatabase::Remote(RemoteDatabase::new(s.clone())),
+            Raw::String(s) => ReplicationDatabase::Local(DatabaseName::new(s)),
+            Raw::Remote(remote) => ReplicationDatabase::Remote(remote),
+        })
+    }
+}
+
+impl Serialize for ReplicationAuth {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            ReplicationAuth::Basic {
+                ref username,
+                ref password,
+            } => {
+                KnownAuth::Basic {
+                    username: username.clone(),
+                    password: password.clone(),
+                }.serialize(serializer)
+            }
+            ReplicationAuth::OAuth {
+                ref consumer_key,
+                ref consumer_secret,
+                ref token,
+                ref token_secret,
+            } => {
+                KnownAuth::OAuth {
+                    consumer_key: consumer_key.clone(),
+                    consumer_secret: consumer_secret.clone(),
+                    token: token.clone(),
+                    token_secret: token_secret.clone(),
+                }.serialize(serializer)
+            }
+            ReplicationAuth::Other(ref value) => value.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ReplicationAuth {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        Ok(match serde_json::from_value(value.clone()) {
+            Ok(KnownAuth::Basic { username, password }) => ReplicationAuth::Basic {
+                username: username,
+                password: password,
+            },
+            Ok(KnownAuth::OAuth {
+                consumer_key,
+                consumer_secret,
+                token,
+                token_secret,
+            }) => ReplicationAuth::OAuth {
+                consumer_key: consumer_key,
+                consumer_secret: consumer_secret,
+                token: token,
+                token_secret: token_secret,
+            },
+            Err(..) => ReplicationAuth::Other(value),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn replicator_document_path_is_in_replicator_database() {
+        assert_eq!(replicator_document_path("alpha").to_string(), "/_replicator/alpha");
+    }
+
+    #[test]
+    fn replication_database_deserializes_all_forms() {
+        let got: ReplicationDatabase = serde_json::from_str(r#""alpha""#).unwrap();
+        assert_eq!(got, ReplicationDatabase::Local(DatabaseName::new("alpha")));
+
+        let got: ReplicationDatabase = serde_json::from_str(r#""http://example.com/alpha""#).unwrap();
+        assert_eq!(got, ReplicationDatabase::Remote(RemoteDatabase::new("http://example.com/alpha")));
+
+        let got: ReplicationDatabase = serde_json::from_str(
+            r#"{
+                "url": "http://example.com/alpha",
+                "headers": {"Authorization": "Basic Ym9iOnNlY3JldA=="}
+            }"#,
+        ).unwrap();
+        let expected = RemoteDatabase::new("http://example.com/alpha").with_header("Authorization", "Basic Ym9iOnNlY3JldA==");
+        assert_eq!(got, ReplicationDatabase::Remote(expected));
+    }
+
+    #[test]
+    fn replication_document_deserializes_server_fields() {
+        let source = r#"{
+            "_id": "alpha",
+            "_rev": "3-7051cbe5c8faecd085a3fa619e6e6337",
+            "source": "http://example.com:5984/bravo",
+            "target": {"url": "http://example.com:5984/charlie", "auth": {"basic": {"username": "bob", "password": "secret"}}},
+            "create_target": true,
+            "filter": "ddoc/by_type",
+            "query_params": {"type": "user"},
+            "since_seq": "10-g1AAAAB",
+            "worker_processes": 4,
+            "owner": "bob",
+            "_replication_id": "c0ebe9256695ff083347cbf95f93e280",
+            "_replication_state": "completed",
+            "_replication_state_time": "2017-08-01T12:00:00+00:00",
+            "_replication_stats": {
+                "revisions_checked": 17,
+                "missing_revisions_found": 17,
+                "docs_read": 17,
+                "docs_written": 17,
+                "doc_write_failures": 0,
+                "checkpointed_source_seq": "10-g1AAAAB"
+            }
+        }"#;
+
+        let got: ReplicationDocument = serde_json::from_str(source).unwrap();
+        assert_eq!(got.id, Some(DocumentId::from("alpha")));
+        assert_eq!(
+            got.source,
+            ReplicationDatabase::Remote(RemoteDatabase::new("http://example.com:5984/bravo"))
+        );
+        match got.target {
+            ReplicationDatabase::Remote(ref x) => {
+                assert_eq!(
+                    x.auth,
+                    Some(ReplicationAuth::Basic {
+                        username: String::from("bob"),
+                        password: String::from("secret"),
+                    })
+                )
+            }
+            ref x => panic!("Got unexpected target {:?}", x),
+        }
+        assert_eq!(got.create_target, Some(true));
+        assert_eq!(got.filter, Some(String::from("ddoc/by_type")));
+        assert_eq!(got.query_params.unwrap()["type"], "user");
+        assert_eq!(got.since_seq, Some(Sequence::from("10-g1AAAAB")));
+        assert_eq!(got.worker_processes, Some(4));
+        assert_eq!(got.replication_state, Some(ReplicationState::Completed));
+        assert_eq!(got.replication_stats.unwrap().docs_written, Some(17));
+    }
+
+    #[test]
+    fn replication_document_serializes_only_given_options() {
+        let mut doc = ReplicationDocument::new(DatabaseName::new("alpha"), DatabaseName::new("bravo"));
+        doc.doc_ids = Some(vec![DocumentId::from("charlie")]);
+        doc.use_checkpoints = Some(false);
+        assert_eq!(
+            serde_json::to_value(&doc).unwrap(),
+            json!({
+                "source": "alpha",
+                "target": "bravo",
+                "doc_ids": ["charlie"],
+                "use_checkpoints": false,
+            })
+        );
+    }
+
+    #[test]
+    fn replication_auth_keeps_unknown_forms() {
+        let source = json!({
+            "source": {"url": "https://example.cloudant.com/alpha", "auth": {"iam": {"api_key": "xyz"}}},
+            "target": {"url": "http://example.com:5984/bravo", "auth": {"session": {}}},
+        });
+
+        let got: ReplicationDocument = serde_json::from_value(source.clone()).unwrap();
+        match got.source {
+            ReplicationDatabase::Remote(ref x) => {
+                assert_eq!(
+                    x.auth,
+                    Some(ReplicationAuth::Other(json!({"iam": {"api_key": "xyz"}})))
+                )
+            }
+            ref x => panic!("Got unexpected source {:?}", x),
+        }
+        assert_eq!(serde_json::to_value(&got).unwrap(), source);
+    }
+
+    #[test]
+    fn replication_document_keeps_conflicting_selections() {
+        let source = json!({
+            "source": "alpha",
+            "target": "bravo",
+            "doc_ids": ["charlie"],
+            "selector": {"delta": "echo"},
+            "_replication_state": "failed",
+        });
+        let got: ReplicationDocument = serde_json::from_value(source.clone()).unwrap();
+        assert_eq!(got.doc_ids, Some(vec![DocumentId::from("charlie")]));
+        assert_eq!(got.selector, Some(json!({"delta": "echo"})));
+        assert_eq!(serde_json::to_value(&got).unwrap(), source);
+    }
+}
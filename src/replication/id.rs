@@ -0,0 +1,581 @@
+use {DatabaseName, DocumentId, Error, LocalDocumentName, md5, serde_json, std};
+use etf::{self, Ejson, Term};
+use uuid::Uuid;
+
+/// `ReplicationId` identifies a replication the same way the CouchDB
+/// replicator does.
+///
+/// # Summary
+///
+/// * `ReplicationId` has a base part and an extension part, such as
+///   `+continuous`. The base part names the replication's checkpoint
+///   documents, and the `checkpoint_id` method returns their document id.
+///
+/// * `ReplicationId` implements `Display`, producing the full id, as shown in
+///   the `_active_tasks` and `_scheduler/jobs` resources.
+///
+/// * `ReplicationIdBuilder` computes a `ReplicationId`.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ReplicationId {
+    base: String,
+    extension: String,
+}
+
+/// `ReplicationIdVersion` specifies the algorithm with which to compute a
+/// replication id.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ReplicationIdVersion {
+    /// The algorithm of CouchDB 1.2 through 1.x, whose local endpoints
+    /// include the user context.
+    CouchDb1,
+
+    /// The algorithm of CouchDB 2.x.
+    CouchDb2,
+}
+
+/// `ReplicationEndpoint` is the source or target of a replication, as the
+/// replication id computation sees it.
+///
+/// # Remarks
+///
+/// A remote endpoint's URL is as the replication document or `_replicate`
+/// request specifies it, including any credentials. As with CouchDB, the
+/// computation appends a trailing slash if the URL has neither one nor a query
+/// string.
+///
+/// A remote endpoint's headers are those the replication document or
+/// `_replicate` request specifies—not the replicator's default `Accept` and
+/// `User-Agent` headers.
+///
+/// OAuth credentials, which CouchDB 1.x supports, are unsupported.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ReplicationEndpoint {
+    inner: EndpointInner,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum EndpointInner {
+    Remote {
+        url: String,
+        headers: Vec<(String, String)>,
+    },
+    Local {
+        db_name: DatabaseName,
+        user_name: Option<String>,
+        roles: Vec<String>,
+    },
+}
+
+/// `ReplicationFilter` selects the documents a replication replicates, as the
+/// replication id computation sees it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationFilter {
+    /// Replicate only the documents having the given ids, as given in the
+    /// `doc_ids` field, though not percent-encoded.
+    DocIds(Vec<DocumentId>),
+
+    /// Replicate the documents a filter function selects. The `code` is the
+    /// source code of the function, as in its design document, and the
+    /// `query_params` is the JSON text of the `query_params` field, if any.
+    Function {
+        code: String,
+        query_params: Option<String>,
+    },
+
+    /// Replicate the documents a built-in filter, such as `_view`, selects.
+    /// The `query_params` is the JSON text of the `query_params` field, if
+    /// any.
+    BuiltIn {
+        name: String,
+        query_params: Option<String>,
+    },
+
+    /// Replicate the documents a Mango selector selects—CouchDB 2.x only. The
+    /// selector must be in the normalized form the server produces, e.g.,
+    /// with explicit `$eq` and `$and` operators.
+    Selector(serde_json::Value),
+}
+
+/// `ReplicationIdBuilder` computes a replication id the same way the CouchDB
+/// replicator does.
+///
+/// # Summary
+///
+/// * The computation hashes an Erlang term of the replicating server's UUID,
+///   the source and target endpoints, and the replication's filter.
+///
+/// * The `continuous` and `create_target` options add an extension to the
+///   replication id but do not affect its base part.
+///
+/// # Remarks
+///
+/// The replicating server's UUID is the `uuid` field of its
+/// [`Root`](../struct.Root.html)—i.e., the server running the replication,
+/// which need not be the source or the target.
+///
+/// An application may use the base part of the replication id to read the
+/// checkpoints of a server-side replication or to share them with a
+/// [`Replicator`](struct.Replicator.html).
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate uuid;
+///
+/// use couchdb::replication::{ReplicationEndpoint, ReplicationIdBuilder, ReplicationIdVersion};
+///
+/// let host_uuid = uuid::Uuid::parse_str("0762dcce5f0d7f6f79157f852186f149").unwrap();
+///
+/// let id = ReplicationIdBuilder::new(
+///     &host_uuid,
+///     ReplicationEndpoint::remote("http://example.com:5984/alpha"),
+///     ReplicationEndpoint::local("bravo"),
+/// ).with_continuous(true)
+///     .build(ReplicationIdVersion::CouchDb2)
+///     .unwrap();
+///
+/// assert_eq!(id.base().len(), 32);
+/// assert_eq!(id.extension(), "+continuous");
+/// assert_eq!(id.checkpoint_id().to_string(), format!("_local/{}", id.base()));
+/// ```
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicationIdBuilder {
+    host_uuid: Uuid,
+    source: ReplicationEndpoint,
+    target: ReplicationEndpoint,
+    filter: Option<ReplicationFilter>,
+    continuous: bool,
+    create_target: bool,
+}
+
+impl ReplicationId {
+    /// Borrows the base part of the replication id—i.e., 32 hex digits.
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Borrows the extension part of the replication id, such as
+    /// `+continuous+create_target`, which may be empty.
+    pub fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    /// Returns the id of the replication's checkpoint documents—i.e.,
+    /// `_local/{base}`.
+    pub fn checkpoint_id(&self) -> DocumentId {
+        DocumentId::from(LocalDocumentName::new(self.base.clone()))
+    }
+}
+
+impl std::fmt::Display for ReplicationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}{}", self.base, self.extension)
+    }
+}
+
+impl ReplicationEndpoint {
+    /// Constructs a remote endpoint from a database URL.
+    pub fn remote<T: Into<String>>(url: T) -> Self {
+        ReplicationEndpoint {
+            inner: EndpointInner::Remote {
+                url: url.into(),
+                headers: Vec::new(),
+            },
+        }
+    }
+
+    /// Constructs a local endpoint from the name of a database on the
+    /// replicating server.
+    pub fn local<T: Into<String>>(db_name: T) -> Self {
+        ReplicationEndpoint {
+            inner: EndpointInner::Local {
+                db_name: DatabaseName::new(db_name),
+                user_name: None,
+                roles: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a header to a remote endpoint. A local endpoint ignores headers.
+    ///
+    /// As with CouchDB, only the first header having a given name counts.
+    ///
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        if let EndpointInner::Remote { ref mut headers, .. } = self.inner {
+            headers.push((name.into(), value.into()));
+        }
+        self
+    }
+
+    /// Sets the user context of a local endpoint—i.e., the `user_ctx` field of
+    /// the replication document. Only the CouchDB 1.x algorithm uses it, and a
+    /// remote endpoint ignores it.
+    pub fn with_user_ctx<T: Into<String>>(mut self, user_name: Option<T>, roles: Vec<String>) -> Self {
+        if let EndpointInner::Local {
+            user_name: ref mut x,
+            roles: ref mut y,
+            ..
+        } = self.inner
+        {
+            *x = user_name.map(|x| x.into());
+            *y = roles;
+        }
+        self
+    }
+
+    fn to_term(&self, version: ReplicationIdVersion) -> Term {
+        match self.inner {
+            EndpointInner::Remote { ref url, ref headers } => {
+                let mut url = url.clone();
+                if !url.contains('?') && !url.ends_with('/') {
+                    url.push('/');
+                }
+                let mut headers = headers.clone();
+                headers.sort_by(|a, b| a.0.cmp(&b.0));
+                headers.dedup_by(|a, b| a.0 == b.0);
+                Term::Tuple(vec![
+                    Term::Atom("remote"),
+                    Term::String(url.into_bytes()),
+                    Term::List(
+                        headers
+                            .into_iter()
+                            .map(|(name, value)| {
+                                Term::Tuple(vec![
+                                    Term::String(name.into_bytes()),
+                                    Term::String(value.into_bytes()),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ])
+            }
+            EndpointInner::Local {
+                ref db_name,
+                ref user_name,
+                ref roles,
+            } => {
+                let db_name = Term::Binary(db_name.as_ref().as_bytes().to_vec());
+                match version {
+                    ReplicationIdVersion::CouchDb1 => {
+                        let user_ctx = Term::Tuple(vec![
+                            Term::Atom("user_ctx"),
+                            match *user_name {
+                                Some(ref x) => Term::Binary(x.as_bytes().to_vec()),
+                                None => Term::Atom("null"),
+                            },
+                            Term::List(
+                                roles
+                                    .iter()
+                                    .map(|x| Term::Binary(x.as_bytes().to_vec()))
+                                    .collect(),
+                            ),
+                            Term::Atom("undefined"),
+                        ]);
+                        Term::Tuple(vec![Term::Atom("local"), db_name, user_ctx])
+                    }
+                    ReplicationIdVersion::CouchDb2 => Term::Tuple(vec![Term::Atom("local"), db_name]),
+                }
+            }
+        }
+    }
+}
+
+impl ReplicationIdBuilder {
+    /// Constructs a builder for a replication without a filter or options.
+    pub fn new(host_uuid: &Uuid, source: ReplicationEndpoint, target: ReplicationEndpoint) -> Self {
+        ReplicationIdBuilder {
+            host_uuid: *host_uuid,
+            source: source,
+            target: target,
+            filter: None,
+            continuous: false,
+            create_target: false,
+        }
+    }
+
+    /// Sets the replication's filter.
+    pub fn with_filter(mut self, filter: ReplicationFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sets whether the replication is continuous.
+    pub fn with_continuous(mut self, continuous: bool) -> Self {
+        self.continuous = continuous;
+        self
+    }
+
+    /// Sets whether the replication creates the target database.
+    pub fn with_create_target(mut self, create_target: bool) -> Self {
+        self.create_target = create_target;
+        self
+    }
+
+    /// Computes the replication id.
+    ///
+    /// The computation fails if a filter's query parameters are not valid
+    /// JSON.
+    ///
+    pub fn build(&self, version: ReplicationIdVersion) -> Result<ReplicationId, Error> {
+
+        let mut terms = vec![
+            Term::Binary(self.host_uuid.simple().to_string().into_bytes()),
+            self.source.to_term(version),
+            self.target.to_term(version),
+        ];
+
+        match self.filter {
+            None => {}
+            Some(ReplicationFilter::DocIds(ref doc_ids)) => {
+                let mut doc_ids = doc_ids
+                    .iter()
+                    .map(|x| x.as_ref().as_bytes().to_vec())
+                    .collect::<Vec<_>>();
+                if version == ReplicationIdVersion::CouchDb2 {
+                    doc_ids.sort();
+                    doc_ids.dedup();
+                }
+                terms.push(Term::List(doc_ids.into_iter().map(Term::Binary).collect()));
+            }
+            Some(ReplicationFilter::Function {
+                     ref code,
+                     ref query_params,
+                 }) => {
+                terms.push(Term::Binary(code.as_bytes().to_vec()));
+                terms.push(query_params_term(query_params.as_ref())?);
+            }
+            Some(ReplicationFilter::BuiltIn {
+                     ref name,
+                     ref query_params,
+                 }) => {
+                terms.push(Term::Binary(name.as_bytes().to_vec()));
+                terms.push(query_params_term(query_params.as_ref())?);
+            }
+            Some(ReplicationFilter::Selector(ref selector)) => {
+                // A serde_json object sorts its members by key, as CouchDB
+                // sorts the selector's members before hashing it.
+                let Ejson(term) = serde_json::from_value(selector.clone()).map_err(|e| {
+                    Error::from(("The replication selector is not valid", e))
+                })?;
+                terms.push(term);
+            }
+        }
+
+        let base = format!("{:x}", md5::compute(Term::List(terms).to_binary()));
+
+        let mut extension = String::new();
+        if self.continuous {
+            extension.push_str("+continuous");
+        }
+        if self.create_target {
+            extension.push_str("+create_target");
+        }
+
+        Ok(ReplicationId {
+            base: base,
+            extension: extension,
+        })
+    }
+}
+
+fn query_params_term(query_params: Option<&String>) -> Result<Term, Error> {
+    match query_params {
+        None => Ok(Term::Tuple(vec![Term::List(Vec::new())])),
+        Some(text) => {
+            let Ejson(term) = etf::from_slice(text.as_bytes()).map_err(|e| {
+                Error::from(("The replication query parameters are not valid JSON", e))
+            })?;
+            Ok(term)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_uuid() -> Uuid {
+        Uuid::parse_str("0762dcce5f0d7f6f79157f852186f149").unwrap()
+    }
+
+    fn md5_hex(term: Term) -> String {
+        format!("{:x}", md5::compute(term.to_binary()))
+    }
+
+    #[test]
+    fn replication_id_hashes_uuid_and_endpoints() {
+
+        let got = ReplicationIdBuilder::new(
+            &host_uuid(),
+            ReplicationEndpoint::remote("http://example.com:5984/alpha"),
+            ReplicationEndpoint::local("bravo"),
+        ).build(ReplicationIdVersion::CouchDb2)
+            .unwrap();
+
+        let expected = md5_hex(Term::List(vec![
+            Term::Binary(b"0762dcce5f0d7f6f79157f852186f149".to_vec()),
+            Term::Tuple(vec![
+                Term::Atom("remote"),
+                Term::String(b"http://example.com:5984/alpha/".to_vec()),
+                Term::List(vec![]),
+            ]),
+            Term::Tuple(vec![Term::Atom("local"), Term::Binary(b"bravo".to_vec())]),
+        ]));
+
+        assert_eq!(got.base(), expected);
+        assert_eq!(got.extension(), "");
+        assert_eq!(got.to_string(), expected);
+    }
+
+    #[test]
+    fn replication_id_trailing_slash_and_headers_are_normalized() {
+
+        let build = |url: &str, headers: &[(&str, &str)]| {
+            let source = headers.iter().fold(
+                ReplicationEndpoint::remote(url),
+                |e, &(k, v)| e.with_header(k, v),
+            );
+            ReplicationIdBuilder::new(&host_uuid(), source, ReplicationEndpoint::local("bravo"))
+                .build(ReplicationIdVersion::CouchDb2)
+                .unwrap()
+        };
+
+        assert_eq!(
+            build("http://example.com/alpha", &[]),
+            build("http://example.com/alpha/", &[])
+        );
+        assert_ne!(
+            build("http://example.com/alpha?x=1", &[]),
+            build("http://example.com/alpha?x=1/", &[])
+        );
+        assert_eq!(
+            build("http://example.com/alpha", &[("b", "2"), ("a", "1")]),
+            build("http://example.com/alpha", &[("a", "1"), ("b", "2"), ("a", "1")])
+        );
+        assert_eq!(
+            build("http://example.com/alpha", &[("a", "1")]),
+            build("http://example.com/alpha", &[("a", "1"), ("a", "2")])
+        );
+        assert_ne!(
+            build("http://example.com/alpha", &[("a", "2")]),
+            build("http://example.com/alpha", &[("a", "1"), ("a", "2")])
+        );
+        assert_ne!(
+            build("http://example.com/alpha", &[("a", "1")]),
+            build("http://example.com/alpha", &[])
+        );
+    }
+
+    #[test]
+    fn replication_id_versions_differ_for_local_endpoints() {
+
+        let builder = ReplicationIdBuilder::new(
+            &host_uuid(),
+            ReplicationEndpoint::local("alpha").with_user_ctx(Some("bob"), vec![String::from("_admin")]),
+            ReplicationEndpoint::remote("http://example.com/bravo/"),
+        );
+
+        let expected = md5_hex(Term::List(vec![
+            Term::Binary(b"0762dcce5f0d7f6f79157f852186f149".to_vec()),
+            Term::Tuple(vec![
+                Term::Atom("local"),
+                Term::Binary(b"alpha".to_vec()),
+                Term::Tuple(vec![
+                    Term::Atom("user_ctx"),
+                    Term::Binary(b"bob".to_vec()),
+                    Term::List(vec![Term::Binary(b"_admin".to_vec())]),
+                    Term::Atom("undefined"),
+                ]),
+            ]),
+            Term::Tuple(vec![
+                Term::Atom("remote"),
+                Term::String(b"http://example.com/bravo/".to_vec()),
+                Term::List(vec![]),
+            ]),
+        ]));
+
+        let v1 = builder.build(ReplicationIdVersion::CouchDb1).unwrap();
+        let v2 = builder.build(ReplicationIdVersion::CouchDb2).unwrap();
+        assert_eq!(v1.base(), expected);
+        assert_ne!(v1, v2);
+    }
+
+    #[test]
+    fn replication_id_includes_filter_and_options() {
+
+        let builder = ReplicationIdBuilder::new(
+            &host_uuid(),
+            ReplicationEndpoint::local("alpha"),
+            ReplicationEndpoint::local("bravo"),
+        );
+
+        let plain = builder.build(ReplicationIdVersion::CouchDb2).unwrap();
+
+        let options = builder
+            .clone()
+            .with_continuous(true)
+            .with_create_target(true)
+            .build(ReplicationIdVersion::CouchDb2)
+            .unwrap();
+        assert_eq!(options.base(), plain.base());
+        assert_eq!(options.extension(), "+continuous+create_target");
+        assert_eq!(options.to_string(), format!("{}+continuous+create_target", plain.base()));
+
+        let doc_ids = |ids: &[&str]| {
+            builder
+                .clone()
+                .with_filter(ReplicationFilter::DocIds(
+                    ids.iter().map(|&x| DocumentId::from(x)).collect(),
+                ))
+                .build(ReplicationIdVersion::CouchDb2)
+                .unwrap()
+        };
+        assert_ne!(doc_ids(&["a", "b"]), plain);
+        assert_eq!(doc_ids(&["a", "b"]), doc_ids(&["b", "a", "b"]));
+
+        let function = builder
+            .clone()
+            .with_filter(ReplicationFilter::Function {
+                code: String::from("function(doc, req) { return true; }"),
+                query_params: Some(String::from(r#"{"b":"1","a":"2"}"#)),
+            })
+            .build(ReplicationIdVersion::CouchDb2)
+            .unwrap();
+
+        let expected = md5_hex(Term::List(vec![
+            Term::Binary(b"0762dcce5f0d7f6f79157f852186f149".to_vec()),
+            Term::Tuple(vec![Term::Atom("local"), Term::Binary(b"alpha".to_vec())]),
+            Term::Tuple(vec![Term::Atom("local"), Term::Binary(b"bravo".to_vec())]),
+            Term::Binary(b"function(doc, req) { return true; }".to_vec()),
+            Term::Tuple(vec![
+                Term::List(vec![
+                    Term::Tuple(vec![Term::Binary(b"b".to_vec()), Term::Binary(b"1".to_vec())]),
+                    Term::Tuple(vec![Term::Binary(b"a".to_vec()), Term::Binary(b"2".to_vec())]),
+                ]),
+            ]),
+        ]));
+        assert_eq!(function.base(), expected);
+
+        match builder
+            .clone()
+            .with_filter(ReplicationFilter::BuiltIn {
+                name: String::from("_view"),
+                query_params: Some(String::from("{")),
+            })
+            .build(ReplicationIdVersion::CouchDb2) {
+            Err(Error::Json { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        let selector = builder
+            .clone()
+            .with_filter(ReplicationFilter::Selector(json!({"type": {"$eq": "user"}})))
+            .build(ReplicationIdVersion::CouchDb2)
+            .unwrap();
+        assert_ne!(selector, plain);
+    }
+}
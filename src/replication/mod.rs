@@ -0,0 +1,52 @@
+//! The `replication` module provides types for replicating documents between
+//! databases.
+//!
+//! # Summary
+//!
+//! * `Replicator` drives the CouchDB replication protocol between a source and
+//!   a target `Peer`. It does no I/O of its own, so either peer may be a
+//!   CouchDB server or something else entirely, such as local storage.
+//!
+//! * `ReplicationIdBuilder` computes a replication id the same way the CouchDB
+//!   replicator does, and `ReplicationCheckpoint` is the `_local` document in
+//!   which a replication records its progress.
+//!
+//! * `RevsDiffRequest` constructs the body of a `POST /{db}/_revs_diff` or
+//!   `POST /{db}/_missing_revs` request, and `RevsDiffResponse` and
+//!   `MissingRevsResponse` decode their responses.
+//!
+//! * The `revs_diff` function computes the answer to a `_revs_diff` request
+//!   locally, from revision trees.
+//!
+//! * `ReplicationDocument` is a document in the `_replicator` database, and
+//!   `SchedulerJobs` and `SchedulerDocs` decode the responses of the
+//!   `/_scheduler` endpoints, which report the state of each replication.
+//!
+//! * `ReplicateRequest` constructs the body of a `POST /_replicate` request,
+//!   and `ReplicateResponse` decodes its response.
+//!
+//! # Remarks
+//!
+//! See the [CouchDB replication
+//! protocol](http://docs.couchdb.org/en/2.0.0/replication/protocol.html) for
+//! details.
+
+mod checkpoint;
+mod document;
+mod id;
+mod peer;
+mod replicate;
+mod replicator;
+mod revs_diff;
+mod scheduler;
+
+pub use self::checkpoint::{ReplicationCheckpoint, ReplicationHistory};
+pub use self::document::{REPLICATOR_DATABASE, RemoteDatabase, ReplicationAuth, ReplicationDatabase, ReplicationDocument,
+                         ReplicationUserCtx, replicator_document_path};
+pub use self::id::{ReplicationEndpoint, ReplicationFilter, ReplicationId, ReplicationIdBuilder, ReplicationIdVersion};
+pub use self::peer::Peer;
+pub use self::replicate::{ReplicateRequest, ReplicateResponse, ReplicationSelection};
+pub use self::replicator::{ReplicationError, ReplicationEvent, ReplicationStats, Replicator};
+pub use self::revs_diff::{MissingRevsResponse, RevsDiffEntry, RevsDiffRequest, RevsDiffResponse, revs_diff};
+pub use self::scheduler::{ReplicationInfo, ReplicationState, SchedulerDoc, SchedulerDocs, SchedulerJob, SchedulerJobEvent,
+                          SchedulerJobs};
@@ -0,0 +1,93 @@
+use {Changes, DocumentId, Revision, Root, Sequence, serde_json};
+use bulk::{BulkDocsRequest, BulkDocsResult, BulkGetDoc, BulkGetRequest, BulkGetResponse};
+use replication::{RevsDiffRequest, RevsDiffResponse};
+
+/// `Peer` is one database taking part in a replication—either the source or
+/// the target.
+///
+/// # Summary
+///
+/// * `Peer` abstracts the transport. Each method corresponds to one HTTP
+///   request of the CouchDB replication protocol, and an implementation may
+///   instead read and write local storage.
+///
+/// * A [`Replicator`](struct.Replicator.html) calls the methods of its source
+///   and target peers to drive a replication.
+///
+/// # Remarks
+///
+/// Documents are JSON objects containing their `_id`, `_rev`, and
+/// `_revisions` fields, and `_deleted` if they are deleted—i.e., the
+/// documents a CouchDB server returns with the `revs=true` query parameter.
+/// Attachments, if any, are inline, as with the `attachments=true` query
+/// parameter.
+///
+/// A replicator calls only some methods on each peer. It calls `root`,
+/// `changes`, `bulk_get`, and `open_revs` on the source, and `revs_diff`,
+/// `bulk_docs`, and `ensure_full_commit` on the target. It calls `exists`,
+/// `url`, `get_local`, and `put_local` on both.
+///
+pub trait Peer {
+    /// The error type of the transport.
+    type Error;
+
+    /// Returns a string identifying the peer, such as the database's URL.
+    fn url(&self) -> &str;
+
+    /// Gets the server's root resource—i.e., `GET /`.
+    fn root(&mut self) -> Result<Root, Self::Error>;
+
+    /// Returns whether the database exists—i.e., `HEAD /{db}`.
+    fn exists(&mut self) -> Result<bool, Self::Error>;
+
+    /// Gets a local document, or `None` if it does not exist—i.e., `GET
+    /// /{db}/_local/{doc}`.
+    fn get_local(&mut self, id: &DocumentId) -> Result<Option<serde_json::Value>, Self::Error>;
+
+    /// Writes a local document and returns its new revision—i.e., `PUT
+    /// /{db}/_local/{doc}`.
+    ///
+    /// The document contains a `_rev` field if, and only if, the replicator
+    /// has read or written the document earlier. Local document revisions,
+    /// such as `0-1`, are not valid [`Revision`](../struct.Revision.html)
+    /// values, hence the `String` type.
+    fn put_local(&mut self, id: &DocumentId, doc: &serde_json::Value) -> Result<String, Self::Error>;
+
+    /// Gets at most `limit` changes after the `since` sequence—i.e., `GET
+    /// /{db}/_changes?style=all_docs&since={since}&limit={limit}`.
+    ///
+    /// Each change must list all of the document's leaf revisions.
+    fn changes(&mut self, since: &Sequence, limit: u64) -> Result<Changes, Self::Error>;
+
+    /// Gets the revisions the database lacks—i.e., `POST /{db}/_revs_diff`.
+    fn revs_diff(&mut self, request: &RevsDiffRequest) -> Result<RevsDiffResponse, Self::Error>;
+
+    /// Gets many document revisions—i.e., `POST
+    /// /{db}/_bulk_get?revs=true&attachments=true`.
+    fn bulk_get(&mut self, request: &BulkGetRequest) -> Result<BulkGetResponse<serde_json::Value>, Self::Error>;
+
+    /// Gets revisions of one document—i.e., `GET
+    /// /{db}/{doc}?revs=true&attachments=true&open_revs=[…]&atts_since=[…]`.
+    fn open_revs(
+        &mut self,
+        id: &DocumentId,
+        revs: &[Revision],
+        atts_since: &[Revision],
+    ) -> Result<Vec<BulkGetDoc<serde_json::Value>>, Self::Error>;
+
+    /// Writes documents as they are, with `new_edits=false`—i.e., `POST
+    /// /{db}/_bulk_docs`.
+    ///
+    /// The returned rows need only include documents the database failed to
+    /// write, as is the case with CouchDB.
+    fn bulk_docs(&mut self, request: &BulkDocsRequest<serde_json::Value>) -> Result<Vec<BulkDocsResult>, Self::Error>;
+
+    /// Commits all writes to durable storage—i.e., `POST
+    /// /{db}/_ensure_full_commit`.
+    ///
+    /// The default implementation does nothing, which suits CouchDB 2.x and
+    /// later.
+    fn ensure_full_commit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
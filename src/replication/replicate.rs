@@ -0,0 +1,355 @@
+use {DocumentId, Sequence, serde_json};
+use replication::{ReplicationDatabase, ReplicationHistory};
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeMap;
+use std::marker::PhantomData;
+
+/// `ReplicateRequest` is the body of a `POST /_replicate` request.
+///
+/// # Summary
+///
+/// * `ReplicateRequest` implements `Serialize`. Options the application does
+///   not set are omitted from the JSON, so that the server applies its
+///   defaults.
+///
+/// * The source and target are each a local database name or a remote URL,
+///   optionally with headers and credentials—see
+///   [`ReplicationDatabase`](enum.ReplicationDatabase.html).
+///
+/// # Remarks
+///
+/// The server silently ignores an option whose name it does not recognize,
+/// which is why `ReplicateRequest` has builder methods instead of a free-form
+/// JSON object.
+///
+/// To cancel a continuous replication, send a request with the same options
+/// as the one that started it, plus the `cancel` option.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::replication::{RemoteDatabase, ReplicateRequest, ReplicationSelection};
+///
+/// let request = ReplicateRequest::new(
+///     couchdb::DatabaseName::new("alpha"),
+///     RemoteDatabase::new("http://example.com:5984/bravo").with_basic_auth("bob", "secret"),
+/// ).with_continuous()
+///     .with_create_target()
+///     .with_selection(ReplicationSelection::DocIds(vec![couchdb::DocumentId::from("charlie")]));
+///
+/// assert_eq!(
+///     serde_json::to_value(&request).unwrap(),
+///     serde_json::from_str::<serde_json::Value>(r#"{
+///         "source": "alpha",
+///         "target": {
+///             "url": "http://example.com:5984/bravo",
+///             "auth": {"basic": {"username": "bob", "password": "secret"}}
+///         },
+///         "continuous": true,
+///         "create_target": true,
+///         "doc_ids": ["charlie"]
+///     }"#).unwrap()
+/// );
+/// ```
+///
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReplicateRequest {
+    source: ReplicationDatabase,
+    target: ReplicationDatabase,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuous: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cancel: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    create_target: Option<bool>,
+
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    selection: Option<ReplicationSelection>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since_seq: Option<Sequence>,
+}
+
+/// `ReplicateResponse` contains the JSON response to a `POST /_replicate`
+/// request.
+///
+/// # Summary
+///
+/// * `ReplicateResponse` has public members instead of accessor methods
+///   because there are no invariants restricting the data.
+///
+/// * `ReplicateResponse` implements `Deserialize`.
+///
+/// # Remarks
+///
+/// Only a one-off replication's response has a `history`, which is the same
+/// as that of the replication's checkpoint document. A continuous
+/// replication's response has a `local_id` instead, and a replication that
+/// had nothing to do has `no_changes` set.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"{
+///     "ok": true,
+///     "session_id": "142a35854a08e205c47174d91b1f9628",
+///     "source_last_seq": 2,
+///     "replication_id_version": 3,
+///     "history": [
+///         {
+///             "session_id": "142a35854a08e205c47174d91b1f9628",
+///             "start_time": "Mon, 24 Jul 2017 15:09:41 GMT",
+///             "end_time": "Mon, 24 Jul 2017 15:09:42 GMT",
+///             "start_last_seq": 0,
+///             "end_last_seq": 2,
+///             "recorded_seq": 2,
+///             "missing_checked": 2,
+///             "missing_found": 2,
+///             "docs_read": 2,
+///             "docs_written": 2,
+///             "doc_write_failures": 0
+///         }
+///     ]
+/// }"#;
+///
+/// let response: couchdb::replication::ReplicateResponse =
+///     serde_json::from_slice(body).unwrap();
+///
+/// assert!(response.ok);
+/// assert_eq!(response.source_last_seq, Some(couchdb::Sequence::from(2)));
+/// assert_eq!(response.history[0].docs_written, 2);
+/// assert_eq!(response.history[0].end_last_seq, couchdb::Sequence::from(2));
+/// ```
+///
+/// # Compatibility
+///
+/// `ReplicateResponse` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicateResponse` instance.
+/// This allows new fields to be added to `ReplicateResponse` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ReplicateResponse {
+    #[serde(default)]
+    pub ok: bool,
+
+    /// Whether the source had no changes to replicate.
+    #[serde(default)]
+    pub no_changes: bool,
+
+    #[serde(default)]
+    pub session_id: Option<String>,
+
+    #[serde(default)]
+    pub source_last_seq: Option<Sequence>,
+
+    #[serde(default)]
+    pub replication_id_version: Option<u64>,
+
+    /// The replication sessions, newest first.
+    #[serde(default)]
+    pub history: Vec<ReplicationHistory>,
+
+    /// The replication id of a continuous replication.
+    #[serde(default, rename = "_local_id")]
+    pub local_id: Option<String>,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `ReplicationSelection` restricts a replication to some of the source's
+/// documents.
+///
+/// # Summary
+///
+/// * `ReplicationSelection` implements `Serialize`, as the fields it
+///   comprises—e.g., `{"doc_ids": ["alpha"]}`—so that a `_replicate` request
+///   can flatten it into its own JSON object.
+///
+/// # Remarks
+///
+/// The server rejects a replication that specifies more than one of
+/// `doc_ids`, `filter`, and `selector`, which is why they are variants of one
+/// enum.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationSelection {
+    /// Replicate only the documents having the given ids.
+    DocIds(Vec<DocumentId>),
+
+    /// Replicate the documents a filter function accepts. The `name` is that
+    /// of a filter function, such as `ddoc/filter`, or of a built-in filter,
+    /// such as `_view`, and the `query_params` are the query parameters the
+    /// function receives, if any.
+    Filter {
+        name: String,
+        query_params: Option<serde_json::Map<String, serde_json::Value>>,
+    },
+
+    /// Replicate the documents a Mango selector matches—CouchDB 2.x and
+    /// later.
+    Selector(serde_json::Value),
+}
+
+impl ReplicateRequest {
+    /// Constructs a request to replicate from the source to the target, with
+    /// all options unset.
+    pub fn new<S, T>(source: S, target: T) -> Self
+    where
+        S: Into<ReplicationDatabase>,
+        T: Into<ReplicationDatabase>,
+    {
+        ReplicateRequest {
+            source: source.into(),
+            target: target.into(),
+            continuous: None,
+            cancel: None,
+            create_target: None,
+            selection: None,
+            since_seq: None,
+        }
+    }
+
+    /// Borrows the request's source.
+    pub fn source(&self) -> &ReplicationDatabase {
+        &self.source
+    }
+
+    /// Borrows the request's target.
+    pub fn target(&self) -> &ReplicationDatabase {
+        &self.target
+    }
+
+    /// Sets the `continuous` option, which keeps the replication running
+    /// after it catches up with the source.
+    pub fn with_continuous(mut self) -> Self {
+        self.continuous = Some(true);
+        self
+    }
+
+    /// Sets the `cancel` option, which stops a running replication with the
+    /// same source, target, and options.
+    pub fn with_cancel(mut self) -> Self {
+        self.cancel = Some(true);
+        self
+    }
+
+    /// Sets the `create_target` option, which creates the target database if
+    /// it does not exist.
+    pub fn with_create_target(mut self) -> Self {
+        self.create_target = Some(true);
+        self
+    }
+
+    /// Restricts the replication to some of the source's documents—i.e.,
+    /// sets the `doc_ids`, `filter` and `query_params`, or `selector` option.
+    pub fn with_selection(mut self, selection: ReplicationSelection) -> Self {
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Starts the replication from the given source sequence, overriding any
+    /// checkpoint—CouchDB 2.x and later.
+    pub fn with_since_seq(mut self, since_seq: Sequence) -> Self {
+        self.since_seq = Some(since_seq);
+        self
+    }
+}
+
+impl Serialize for ReplicationSelection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match *self {
+            ReplicationSelection::DocIds(ref doc_ids) => map.serialize_entry("doc_ids", doc_ids)?,
+            ReplicationSelection::Filter {
+                ref name,
+                ref query_params,
+            } => {
+                map.serialize_entry("filter", name)?;
+                if let Some(ref query_params) = *query_params {
+                    map.serialize_entry("query_params", query_params)?;
+                }
+            }
+            ReplicationSelection::Selector(ref selector) => map.serialize_entry("selector", selector)?,
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DatabaseName;
+    use serde_json;
+
+    #[test]
+    fn request_serializes_only_set_options() {
+        let request = ReplicateRequest::new(DatabaseName::new("alpha"), DatabaseName::new("bravo"));
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({"source": "alpha", "target": "bravo"})
+        );
+
+        let mut query_params = serde_json::Map::new();
+        query_params.insert(String::from("charlie"), json!("delta"));
+        let request = request
+            .with_continuous()
+            .with_cancel()
+            .with_selection(ReplicationSelection::Filter {
+                name: String::from("echo/foxtrot"),
+                query_params: Some(query_params),
+            })
+            .with_since_seq(Sequence::from(7));
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "source": "alpha",
+                "target": "bravo",
+                "continuous": true,
+                "cancel": true,
+                "filter": "echo/foxtrot",
+                "query_params": {"charlie": "delta"},
+                "since_seq": 7,
+            })
+        );
+
+        let request = request.with_selection(ReplicationSelection::Selector(json!({"golf": {"$gt": 1}})));
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            json!({
+                "source": "alpha",
+                "target": "bravo",
+                "continuous": true,
+                "cancel": true,
+                "selector": {"golf": {"$gt": 1}},
+                "since_seq": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn response_deserializes_without_history() {
+        let got: ReplicateResponse = serde_json::from_str(r#"{"ok": true, "no_changes": true}"#).unwrap();
+        assert!(got.ok);
+        assert!(got.no_changes);
+        assert!(got.history.is_empty());
+
+        let got: ReplicateResponse =
+            serde_json::from_str(r#"{"ok": true, "_local_id": "0a81b645497e6270611ec3419767a584+continuous"}"#)
+                .unwrap();
+        assert_eq!(
+            got.local_id,
+            Some(String::from("0a81b645497e6270611ec3419767a584+continuous"))
+        );
+    }
+}
@@ -0,0 +1,972 @@
+use {DocumentId, Error, LocalDocumentName, Root, Sequence, md5, serde_json, std};
+use bulk::{BulkDocsRequest, BulkGetDoc, BulkGetError, BulkGetItem, BulkGetRequest, BulkGetStrategy};
+use replication::{Peer, ReplicationCheckpoint, ReplicationHistory, RevsDiffRequest, RevsDiffResponse};
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+const DEFAULT_BATCH_SIZE: u64 = 100;
+const MAX_HISTORY: usize = 50;
+
+/// `Replicator` replicates documents from a source database to a target
+/// database, following the CouchDB replication protocol.
+///
+/// # Summary
+///
+/// * `Replicator` is a state machine that does no I/O of its own. It calls the
+///   methods of a source and a target [`Peer`](trait.Peer.html), which may be
+///   CouchDB servers, local storage, or anything else.
+///
+/// * The `step` method advances the replication by one phase and returns a
+///   `ReplicationEvent` describing what happened. The `run` method steps until
+///   the replication completes.
+///
+/// * `Replicator` records a checkpoint on both peers after each batch of
+///   changes, as a `_local` document, so that a later replication between the
+///   same peers resumes where this one stopped.
+///
+/// # Remarks
+///
+/// The replication proceeds as follows:
+///
+/// 1. Verify that both databases exist, read the source's root resource to
+///    choose between `_bulk_get` and `open_revs` requests, and determine the
+///    replication id.
+///
+/// 2. Read the checkpoints of both peers and compare them to find the source
+///    sequence from which to resume.
+///
+/// 3. Read a batch of changes from the source, ask the target which of the
+///    changed revisions it lacks, fetch those revisions from the source, write
+///    them to the target with `new_edits=false`, and record a checkpoint.
+///    Repeat until the source has no more changes.
+///
+/// The replication is one-shot—i.e., it completes when it reaches the end of
+/// the source's changes feed. As with CouchDB, revisions the source reports as
+/// `not_found`—e.g., because of compaction after the source reported the
+/// change—are skipped. Any other error for a revision fails the replication
+/// before it records a checkpoint past the revision's change.
+///
+/// The default replication id is specific to this crate. To share checkpoints
+/// with a CouchDB replicator, compute the replication id with a
+/// [`ReplicationIdBuilder`](struct.ReplicationIdBuilder.html) and pass its
+/// base part to the `with_replication_id` method.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// # use couchdb::{Changes, DocumentId, Revision, Root, Sequence};
+/// # use couchdb::bulk::{BulkDocsRequest, BulkDocsResult, BulkGetDoc, BulkGetRequest, BulkGetResponse};
+/// # use couchdb::replication::{Peer, RevsDiffRequest, RevsDiffResponse};
+/// # use serde_json::Value;
+/// #
+/// # struct HttpPeer;
+/// #
+/// # impl Peer for HttpPeer {
+/// #     type Error = String;
+/// #     fn url(&self) -> &str { unimplemented!() }
+/// #     fn root(&mut self) -> Result<Root, String> { unimplemented!() }
+/// #     fn exists(&mut self) -> Result<bool, String> { unimplemented!() }
+/// #     fn get_local(&mut self, _: &DocumentId) -> Result<Option<Value>, String> { unimplemented!() }
+/// #     fn put_local(&mut self, _: &DocumentId, _: &Value) -> Result<String, String> { unimplemented!() }
+/// #     fn changes(&mut self, _: &Sequence, _: u64) -> Result<Changes, String> { unimplemented!() }
+/// #     fn revs_diff(&mut self, _: &RevsDiffRequest) -> Result<RevsDiffResponse, String> { unimplemented!() }
+/// #     fn bulk_get(&mut self, _: &BulkGetRequest) -> Result<BulkGetResponse<Value>, String> { unimplemented!() }
+/// #     fn open_revs(&mut self, _: &DocumentId, _: &[Revision], _: &[Revision])
+/// #         -> Result<Vec<BulkGetDoc<Value>>, String> { unimplemented!() }
+/// #     fn bulk_docs(&mut self, _: &BulkDocsRequest<Value>) -> Result<Vec<BulkDocsResult>, String> { unimplemented!() }
+/// # }
+/// #
+/// # fn main() {
+/// # }
+/// #
+/// # #[allow(dead_code)]
+/// fn replicate(source: &mut HttpPeer, target: &mut HttpPeer) -> Result<(), couchdb::replication::ReplicationError<String>> {
+///     let stats = couchdb::replication::Replicator::new()
+///         .with_batch_size(500)
+///         .run(source, target)?;
+///     println!("Wrote {} documents", stats.docs_written);
+///     Ok(())
+/// }
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct Replicator {
+    batch_size: u64,
+    replication_id: Option<String>,
+    session_id: String,
+    phase: Phase,
+    strategy: BulkGetStrategy,
+    start_seq: Sequence,
+    since: Sequence,
+    history: Vec<ReplicationHistory>,
+    source_log_rev: Option<String>,
+    target_log_rev: Option<String>,
+    stats: ReplicationStats,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Start,
+    PeersVerified,
+    Replicating,
+    Complete,
+}
+
+/// `ReplicationEvent` describes one step of a replication.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationEvent {
+    /// Both databases exist, and the replication has the given id.
+    PeersVerified { replication_id: String },
+
+    /// The replication resumes after the given source sequence, according to
+    /// the peers' checkpoints.
+    CheckpointsCompared { since: Sequence },
+
+    /// The replication processed a batch of changes and recorded a
+    /// checkpoint at the given source sequence.
+    BatchReplicated {
+        changes: usize,
+        docs_written: u64,
+        last_seq: Sequence,
+    },
+
+    /// The replication reached the end of the source's changes feed.
+    Completed { last_seq: Sequence },
+}
+
+/// `ReplicationStats` counts the work a replication has done.
+///
+/// # Compatibility
+///
+/// `ReplicationStats` contains a dummy private member in order to prevent
+/// applications from directly constructing a `ReplicationStats` instance. This
+/// allows new fields to be added to `ReplicationStats` in future releases
+/// without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ReplicationStats {
+    /// The number of revisions the replication asked the target about.
+    pub missing_checked: u64,
+
+    /// The number of revisions the target lacked.
+    pub missing_found: u64,
+
+    /// The number of revisions the replication read from the source.
+    pub docs_read: u64,
+
+    /// The number of revisions the replication wrote to the target.
+    pub docs_written: u64,
+
+    /// The number of revisions the target failed to write.
+    pub doc_write_failures: u64,
+
+    _private_guard: PhantomData<()>,
+}
+
+/// `ReplicationError` is the error type of a replication.
+#[derive(Debug)]
+pub enum ReplicationError<E> {
+    /// A request to the source failed.
+    Source(E),
+
+    /// A request to the target failed.
+    Target(E),
+
+    /// The source database does not exist.
+    SourceNotFound,
+
+    /// The target database does not exist.
+    TargetNotFound,
+
+    /// The source failed to return a document revision for a reason other
+    /// than the revision not being found.
+    SourceDocument(BulkGetError),
+
+    /// A peer's response violates the replication protocol.
+    Protocol(Error),
+}
+
+impl Default for Replicator {
+    fn default() -> Self {
+        Replicator {
+            batch_size: DEFAULT_BATCH_SIZE,
+            replication_id: None,
+            session_id: Uuid::new_v4().simple().to_string(),
+            phase: Phase::Start,
+            strategy: BulkGetStrategy::BulkGet,
+            start_seq: Sequence::default(),
+            since: Sequence::default(),
+            history: Vec::new(),
+            source_log_rev: None,
+            target_log_rev: None,
+            stats: ReplicationStats::default(),
+        }
+    }
+}
+
+impl Replicator {
+    /// Constructs a new replicator, with a new session id.
+    pub fn new() -> Self {
+        Replicator::default()
+    }
+
+    /// Sets the maximum number of changes per batch.
+    ///
+    /// By default, the batch size is 100.
+    pub fn with_batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = std::cmp::max(batch_size, 1);
+        self
+    }
+
+    /// Sets the replication id, which names the peers' checkpoint documents.
+    pub fn with_replication_id<T: Into<String>>(mut self, replication_id: T) -> Self {
+        self.replication_id = Some(replication_id.into());
+        self
+    }
+
+    /// Borrows the replication id, if the replication has determined it.
+    pub fn replication_id(&self) -> Option<&str> {
+        self.replication_id.as_deref()
+    }
+
+    /// Borrows the session id, which is unique to this replicator.
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Borrows the source sequence up to which the replication has progressed.
+    pub fn last_seq(&self) -> &Sequence {
+        &self.since
+    }
+
+    /// Borrows the replication's statistics.
+    pub fn stats(&self) -> &ReplicationStats {
+        &self.stats
+    }
+
+    /// Returns whether the replication has completed.
+    pub fn is_complete(&self) -> bool {
+        self.phase == Phase::Complete
+    }
+
+    /// Advances the replication by one phase.
+    ///
+    /// After the replication completes, each call returns
+    /// `ReplicationEvent::Completed` without doing any work.
+    ///
+    pub fn step<S, T, E>(&mut self, source: &mut S, target: &mut T) -> Result<ReplicationEvent, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        match self.phase {
+            Phase::Start => self.verify_peers(source, target),
+            Phase::PeersVerified => self.compare_checkpoints(source, target),
+            Phase::Replicating => self.replicate_batch(source, target),
+            Phase::Complete => Ok(ReplicationEvent::Completed { last_seq: self.since.clone() }),
+        }
+    }
+
+    /// Steps the replication until it completes.
+    pub fn run<S, T, E>(&mut self, source: &mut S, target: &mut T) -> Result<ReplicationStats, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        while !self.is_complete() {
+            self.step(source, target)?;
+        }
+        Ok(self.stats.clone())
+    }
+
+    fn verify_peers<S, T, E>(&mut self, source: &mut S, target: &mut T) -> Result<ReplicationEvent, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        if !source.exists().map_err(ReplicationError::Source)? {
+            return Err(ReplicationError::SourceNotFound);
+        }
+
+        if !target.exists().map_err(ReplicationError::Target)? {
+            return Err(ReplicationError::TargetNotFound);
+        }
+
+        let root = source.root().map_err(ReplicationError::Source)?;
+        self.strategy = BulkGetStrategy::from_root(&root);
+
+        let replication_id = match self.replication_id {
+            Some(ref x) => x.clone(),
+            None => default_replication_id(&root, source.url(), target.url()),
+        };
+
+        self.replication_id = Some(replication_id.clone());
+        self.phase = Phase::PeersVerified;
+        Ok(ReplicationEvent::PeersVerified { replication_id: replication_id })
+    }
+
+    fn compare_checkpoints<S, T, E>(
+        &mut self,
+        source: &mut S,
+        target: &mut T,
+    ) -> Result<ReplicationEvent, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        let id = self.checkpoint_id();
+
+        let (source_log, source_log_rev) = read_checkpoint(source, &id).map_err(ReplicationError::Source)?;
+        let (target_log, target_log_rev) = read_checkpoint(target, &id).map_err(ReplicationError::Target)?;
+
+        let (since, history) = match (source_log, target_log) {
+            (Some(source_log), Some(target_log)) => {
+                match source_log.resume_seq(&target_log).cloned() {
+                    Some(seq) => (seq, source_log.history),
+                    None => (Sequence::default(), Vec::new()),
+                }
+            }
+            _ => (Sequence::default(), Vec::new()),
+        };
+
+        self.source_log_rev = source_log_rev;
+        self.target_log_rev = target_log_rev;
+        self.start_seq = since.clone();
+        self.since = since.clone();
+        self.history = history;
+        self.phase = Phase::Replicating;
+        Ok(ReplicationEvent::CheckpointsCompared { since: since })
+    }
+
+    fn replicate_batch<S, T, E>(&mut self, source: &mut S, target: &mut T) -> Result<ReplicationEvent, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        let changes = source
+            .changes(&self.since, self.batch_size)
+            .map_err(ReplicationError::Source)?;
+
+        if changes.results.is_empty() {
+            self.phase = Phase::Complete;
+            return Ok(ReplicationEvent::Completed { last_seq: self.since.clone() });
+        }
+
+        let mut request = RevsDiffRequest::new();
+        for result in changes.results.iter() {
+            let revs = result.changes.iter().map(|x| x.rev.clone()).collect::<Vec<_>>();
+            self.stats.missing_checked += revs.len() as u64;
+            request.insert(result.id.clone(), revs);
+        }
+
+        let diff = target.revs_diff(&request).map_err(ReplicationError::Target)?;
+        self.stats.missing_found += diff.iter().map(|(_, x)| x.missing.len() as u64).sum::<u64>();
+
+        let docs = self.fetch(source, &diff)?;
+        self.stats.docs_read += docs.len() as u64;
+
+        let docs_written = if docs.is_empty() {
+            0
+        } else {
+            let count = docs.len() as u64;
+            let request = BulkDocsRequest::new(docs).with_new_edits(false);
+            let results = target.bulk_docs(&request).map_err(ReplicationError::Target)?;
+            let failures = results.iter().filter(|x| !x.is_ok()).count() as u64;
+            self.stats.doc_write_failures += failures;
+            count.saturating_sub(failures)
+        };
+
+        self.stats.docs_written += docs_written;
+        target.ensure_full_commit().map_err(
+            ReplicationError::Target,
+        )?;
+
+        self.since = changes.last_seq;
+        self.record_checkpoints(source, target)?;
+
+        Ok(ReplicationEvent::BatchReplicated {
+            changes: changes.results.len(),
+            docs_written: docs_written,
+            last_seq: self.since.clone(),
+        })
+    }
+
+    fn fetch<S, E>(&self, source: &mut S, diff: &RevsDiffResponse) -> Result<Vec<serde_json::Value>, ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+    {
+        let mut docs = Vec::new();
+
+        if diff.is_empty() {
+            return Ok(docs);
+        }
+
+        match self.strategy {
+            BulkGetStrategy::BulkGet => {
+                let mut request = BulkGetRequest::default();
+                for (id, entry) in diff.iter() {
+                    for rev in entry.missing.iter() {
+                        request.push(
+                            BulkGetItem::new(id.clone())
+                                .with_revision(rev.clone())
+                                .with_atts_since(entry.possible_ancestors.clone()),
+                        );
+                    }
+                }
+                let response = source.bulk_get(&request).map_err(ReplicationError::Source)?;
+                for result in response.results {
+                    for doc in result.docs {
+                        docs.extend(ok_doc(doc)?);
+                    }
+                }
+            }
+            BulkGetStrategy::OpenRevs => {
+                for (id, entry) in diff.iter() {
+                    let revs = source
+                        .open_revs(id, &entry.missing, &entry.possible_ancestors)
+                        .map_err(ReplicationError::Source)?;
+                    for doc in revs {
+                        docs.extend(ok_doc(doc)?);
+                    }
+                }
+            }
+        }
+
+        Ok(docs)
+    }
+
+    fn record_checkpoints<S, T, E>(&mut self, source: &mut S, target: &mut T) -> Result<(), ReplicationError<E>>
+    where
+        S: Peer<Error = E>,
+        T: Peer<Error = E>,
+    {
+        let mut entry = ReplicationHistory::new(self.session_id.clone(), self.since.clone());
+        entry.start_last_seq = self.start_seq.clone();
+        entry.end_last_seq = self.since.clone();
+        entry.missing_checked = self.stats.missing_checked;
+        entry.missing_found = self.stats.missing_found;
+        entry.docs_read = self.stats.docs_read;
+        entry.docs_written = self.stats.docs_written;
+        entry.doc_write_failures = self.stats.doc_write_failures;
+
+        let mut history = vec![entry];
+        history.extend(
+            self.history
+                .iter()
+                .filter(|x| x.session_id != self.session_id)
+                .take(MAX_HISTORY - 1)
+                .cloned(),
+        );
+
+        let mut checkpoint = ReplicationCheckpoint::new(self.session_id.clone(), self.since.clone());
+        checkpoint.id = Some(self.checkpoint_id());
+        checkpoint.history = history;
+
+        checkpoint.rev = self.source_log_rev.clone();
+        let rev = write_checkpoint(source, &checkpoint, ReplicationError::Source)?;
+        self.source_log_rev = Some(rev);
+
+        checkpoint.rev = self.target_log_rev.clone();
+        let rev = write_checkpoint(target, &checkpoint, ReplicationError::Target)?;
+        self.target_log_rev = Some(rev);
+
+        self.history = checkpoint.history;
+        Ok(())
+    }
+
+    fn checkpoint_id(&self) -> DocumentId {
+        let replication_id = self.replication_id.as_deref().unwrap_or("");
+        DocumentId::from(LocalDocumentName::new(replication_id))
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for ReplicationError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            ReplicationError::Source(ref e) => write!(f, "The source request failed: {}", e),
+            ReplicationError::Target(ref e) => write!(f, "The target request failed: {}", e),
+            ReplicationError::SourceNotFound => f.write_str("The source database does not exist"),
+            ReplicationError::TargetNotFound => f.write_str("The target database does not exist"),
+            ReplicationError::SourceDocument(ref e) => write!(
+                f,
+                "The source failed to return document {}: {}: {}",
+                e.id,
+                e.error,
+                e.reason
+            ),
+            ReplicationError::Protocol(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for ReplicationError<E> {
+    fn description(&self) -> &str {
+        match *self {
+            ReplicationError::Source(..) => "The source request failed",
+            ReplicationError::Target(..) => "The target request failed",
+            ReplicationError::SourceNotFound => "The source database does not exist",
+            ReplicationError::TargetNotFound => "The target database does not exist",
+            ReplicationError::SourceDocument(..) => "The source failed to return a document revision",
+            ReplicationError::Protocol(..) => "The replication protocol failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match *self {
+            ReplicationError::Source(ref e) |
+            ReplicationError::Target(ref e) => Some(e),
+            ReplicationError::Protocol(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn default_replication_id(root: &Root, source_url: &str, target_url: &str) -> String {
+    let key = format!("{}\n{}\n{}", root.uuid.simple(), source_url, target_url);
+    format!("{:x}", md5::compute(key.as_bytes()))
+}
+
+// Skips a revision the source reports as missing, the same as CouchDB does,
+// but fails for any other error so that the replicator does not checkpoint past
+// a revision it has yet to replicate.
+fn ok_doc<E>(doc: BulkGetDoc<serde_json::Value>) -> Result<Option<serde_json::Value>, ReplicationError<E>> {
+    match doc {
+        BulkGetDoc::Ok(x) => Ok(Some(x)),
+        BulkGetDoc::Error(ref e) if e.error == "not_found" => Ok(None),
+        BulkGetDoc::Error(e) => Err(ReplicationError::SourceDocument(e)),
+    }
+}
+
+// Reads a peer's checkpoint. A checkpoint that fails to deserialize is as good
+// as no checkpoint—i.e., the replication starts over—though its revision is
+// kept so that the replicator can overwrite it.
+fn read_checkpoint<P: Peer>(
+    peer: &mut P,
+    id: &DocumentId,
+) -> Result<(Option<ReplicationCheckpoint>, Option<String>), P::Error> {
+    let doc = match peer.get_local(id)? {
+        None => return Ok((None, None)),
+        Some(x) => x,
+    };
+
+    let rev = doc.get("_rev").and_then(|x| x.as_str()).map(String::from);
+    Ok((serde_json::from_value(doc).ok(), rev))
+}
+
+fn write_checkpoint<P: Peer>(
+    peer: &mut P,
+    checkpoint: &ReplicationCheckpoint,
+    wrap: fn(P::Error) -> ReplicationError<P::Error>,
+) -> Result<String, ReplicationError<P::Error>> {
+    let doc = serde_json::to_value(checkpoint).map_err(|e| {
+        ReplicationError::Protocol(Error::from(("Failed to serialize the replication checkpoint", e)))
+    })?;
+    peer.put_local(checkpoint.id.as_ref().unwrap(), &doc).map_err(wrap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Changes, Revision, RevisionTree, Revisions, new_revision};
+    use bulk::{BulkDocsResult, BulkGetResponse};
+    use replication::revs_diff;
+    use std::collections::BTreeMap;
+
+    struct MemoryDoc {
+        seq: u64,
+        tree: RevisionTree,
+        bodies: BTreeMap<Revision, serde_json::Value>,
+    }
+
+    // MemoryPeer is a database in memory that implements just enough of
+    // CouchDB's semantics to take part in a replication.
+    struct MemoryPeer {
+        url: String,
+        version: &'static str,
+        exists: bool,
+        seq: u64,
+        docs: BTreeMap<DocumentId, MemoryDoc>,
+        locals: BTreeMap<DocumentId, (u64, serde_json::Value)>,
+        calls: Vec<&'static str>,
+        failures: BTreeMap<DocumentId, &'static str>,
+    }
+
+    impl MemoryPeer {
+        fn new(url: &str, version: &'static str) -> Self {
+            MemoryPeer {
+                url: String::from(url),
+                version: version,
+                exists: true,
+                seq: 0,
+                docs: BTreeMap::new(),
+                locals: BTreeMap::new(),
+                calls: Vec::new(),
+                failures: BTreeMap::new(),
+            }
+        }
+
+        // Makes a new edit, the same way CouchDB does with new_edits=true.
+        fn edit(&mut self, id: &str, parent: Option<&Revision>, body: serde_json::Value, deleted: bool) -> Revision {
+            let content = serde_json::to_vec(&body).unwrap();
+            let rev = new_revision(parent, deleted, &content, Vec::new()).unwrap();
+            let mut path = vec![rev.clone()];
+            if let Some(parent) = parent {
+                let doc = &self.docs[&DocumentId::from(id)];
+                path.extend(doc.tree.path(parent).unwrap().into_iter().cloned());
+            }
+            self.write_path(DocumentId::from(id), &path, deleted, body);
+            rev
+        }
+
+        fn write_path(&mut self, id: DocumentId, path: &[Revision], deleted: bool, body: serde_json::Value) {
+            self.seq += 1;
+            let doc = self.docs.entry(id).or_insert_with(|| {
+                MemoryDoc {
+                    seq: 0,
+                    tree: RevisionTree::new(),
+                    bodies: BTreeMap::new(),
+                }
+            });
+            doc.seq = self.seq;
+            doc.tree.merge_path(path, deleted).unwrap();
+            doc.bodies.insert(path[0].clone(), body);
+        }
+
+        fn doc_json(&self, id: &DocumentId, rev: &Revision) -> Option<serde_json::Value> {
+            let doc = self.docs.get(id)?;
+            let mut body = doc.bodies.get(rev)?.clone();
+            let path = doc.tree.path(rev)?.into_iter().cloned().collect::<Vec<_>>();
+            let deleted = doc.tree.leafs().into_iter().any(|(x, deleted)| x == rev && deleted);
+            let fields = body.as_object_mut().unwrap();
+            fields.insert(String::from("_id"), json!(id));
+            fields.insert(String::from("_rev"), json!(rev));
+            fields.insert(
+                String::from("_revisions"),
+                serde_json::to_value(Revisions::from_revisions(&path).unwrap()).unwrap(),
+            );
+            if deleted {
+                fields.insert(String::from("_deleted"), json!(true));
+            }
+            Some(body.clone())
+        }
+
+        fn open_rev(&self, id: &DocumentId, rev: &Revision) -> serde_json::Value {
+            if let Some(error) = self.failures.get(id) {
+                return json!({"error": {"id": id, "rev": rev, "error": error, "reason": "injected"}});
+            }
+            match self.doc_json(id, rev) {
+                Some(doc) => json!({"ok": doc}),
+                None => json!({"error": {"id": id, "rev": rev, "error": "not_found", "reason": "missing"}}),
+            }
+        }
+
+        fn leafs(&self) -> BTreeMap<DocumentId, Vec<(Revision, bool)>> {
+            self.docs
+                .iter()
+                .map(|(id, doc)| {
+                    let leafs = doc.tree
+                        .leafs()
+                        .into_iter()
+                        .map(|(rev, deleted)| (rev.clone(), deleted))
+                        .collect();
+                    (id.clone(), leafs)
+                })
+                .collect()
+        }
+    }
+
+    impl Peer for MemoryPeer {
+        type Error = String;
+
+        fn url(&self) -> &str {
+            &self.url
+        }
+
+        fn root(&mut self) -> Result<Root, Self::Error> {
+            self.calls.push("root");
+            Ok(
+                serde_json::from_value(json!({
+                    "couchdb": "Welcome",
+                    "uuid": "0762dcce5f0d7f6f79157f852186f149",
+                    "version": self.version,
+                    "vendor": {"name": "The Apache Software Foundation"},
+                })).unwrap(),
+            )
+        }
+
+        fn exists(&mut self) -> Result<bool, Self::Error> {
+            self.calls.push("exists");
+            Ok(self.exists)
+        }
+
+        fn get_local(&mut self, id: &DocumentId) -> Result<Option<serde_json::Value>, Self::Error> {
+            self.calls.push("get_local");
+            Ok(self.locals.get(id).map(|x| x.1.clone()))
+        }
+
+        fn put_local(&mut self, id: &DocumentId, doc: &serde_json::Value) -> Result<String, Self::Error> {
+            self.calls.push("put_local");
+            let n = self.locals.get(id).map(|&(n, _)| n).unwrap_or(0);
+            let expected = if n == 0 { None } else { Some(format!("0-{}", n)) };
+            if doc.get("_rev").and_then(|x| x.as_str()).map(String::from) != expected {
+                return Err(String::from("conflict"));
+            }
+            let rev = format!("0-{}", n + 1);
+            let mut doc = doc.clone();
+            doc.as_object_mut().unwrap().insert(String::from("_rev"), json!(rev));
+            self.locals.insert(id.clone(), (n + 1, doc));
+            Ok(rev)
+        }
+
+        fn changes(&mut self, since: &Sequence, limit: u64) -> Result<Changes, Self::Error> {
+            self.calls.push("changes");
+            let since = since.to_string().parse::<u64>().unwrap();
+            let mut docs = self.docs.iter().filter(|&(_, doc)| doc.seq > since).collect::<Vec<_>>();
+            docs.sort_by_key(|&(_, doc)| doc.seq);
+            docs.truncate(limit as usize);
+            let last_seq = docs.last().map(|&(_, doc)| doc.seq).unwrap_or(since);
+            let results = docs.into_iter()
+                .map(|(id, doc)| {
+                    let changes = doc.tree
+                        .leafs()
+                        .into_iter()
+                        .map(|(rev, _)| json!({"rev": rev}))
+                        .collect::<Vec<_>>();
+                    json!({"seq": doc.seq, "id": id, "changes": changes, "deleted": doc.tree.is_deleted()})
+                })
+                .collect::<Vec<_>>();
+            Ok(
+                serde_json::from_value(json!({"results": results, "last_seq": last_seq}))
+                    .unwrap(),
+            )
+        }
+
+        fn revs_diff(&mut self, request: &RevsDiffRequest) -> Result<RevsDiffResponse, Self::Error> {
+            self.calls.push("revs_diff");
+            let docs = &self.docs;
+            Ok(revs_diff(request, move |id| docs.get(id).map(|x| &x.tree)))
+        }
+
+        fn bulk_get(&mut self, request: &BulkGetRequest) -> Result<BulkGetResponse<serde_json::Value>, Self::Error> {
+            self.calls.push("bulk_get");
+            let results = request
+                .docs()
+                .iter()
+                .map(|item| {
+                    let rev = item.revision().unwrap();
+                    json!({"id": item.id(), "docs": [self.open_rev(item.id(), rev)]})
+                })
+                .collect::<Vec<_>>();
+            Ok(serde_json::from_value(json!({"results": results})).unwrap())
+        }
+
+        fn open_revs(
+            &mut self,
+            id: &DocumentId,
+            revs: &[Revision],
+            _atts_since: &[Revision],
+        ) -> Result<Vec<BulkGetDoc<serde_json::Value>>, Self::Error> {
+            self.calls.push("open_revs");
+            Ok(
+                revs.iter()
+                    .map(|rev| serde_json::from_value(self.open_rev(id, rev)).unwrap())
+                    .collect(),
+            )
+        }
+
+        fn bulk_docs(&mut self, request: &BulkDocsRequest<serde_json::Value>) -> Result<Vec<BulkDocsResult>, Self::Error> {
+            self.calls.push("bulk_docs");
+            assert!(!request.new_edits());
+            for doc in request.docs() {
+                let id = DocumentId::from(doc["_id"].as_str().unwrap());
+                let revisions: Revisions = serde_json::from_value(doc["_revisions"].clone()).unwrap();
+                let deleted = doc.get("_deleted") == Some(&json!(true));
+                let body = doc.as_object()
+                    .unwrap()
+                    .iter()
+                    .filter(|&(k, _)| !k.starts_with('_'))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<serde_json::Map<_, _>>();
+                self.write_path(
+                    id,
+                    &revisions.to_revisions().unwrap(),
+                    deleted,
+                    serde_json::Value::Object(body),
+                );
+            }
+            Ok(Vec::new())
+        }
+    }
+
+    // Returns a source having an updated document, a deleted document, and a
+    // conflicted document.
+    fn make_source(version: &'static str) -> MemoryPeer {
+        let mut source = MemoryPeer::new("memory://source", version);
+
+        let rev = source.edit("alpha", None, json!({"value": 1}), false);
+        source.edit("alpha", Some(&rev), json!({"value": 2}), false);
+
+        let rev = source.edit("bravo", None, json!({"value": 1}), false);
+        source.edit("bravo", Some(&rev), json!({}), true);
+
+        let rev = source.edit("charlie", None, json!({"value": 1}), false);
+        source.edit("charlie", Some(&rev), json!({"value": 2}), false);
+        source.edit("charlie", Some(&rev), json!({"value": 3}), false);
+
+        source
+    }
+
+    #[test]
+    fn replicator_steps_through_phases() {
+        let mut source = make_source("2.1.1");
+        let mut target = MemoryPeer::new("memory://target", "2.1.1");
+        let mut replicator = Replicator::new().with_batch_size(2);
+
+        let replication_id = match replicator.step(&mut source, &mut target).unwrap() {
+            ReplicationEvent::PeersVerified { replication_id } => replication_id,
+            x => panic!("Got unexpected event {:?}", x),
+        };
+        assert_eq!(replicator.replication_id(), Some(replication_id.as_str()));
+
+        assert_eq!(
+            replicator.step(&mut source, &mut target).unwrap(),
+            ReplicationEvent::CheckpointsCompared { since: Sequence::from(0) }
+        );
+
+        assert_eq!(
+            replicator.step(&mut source, &mut target).unwrap(),
+            ReplicationEvent::BatchReplicated {
+                changes: 2,
+                docs_written: 2,
+                last_seq: Sequence::from(4),
+            }
+        );
+
+        assert_eq!(
+            replicator.step(&mut source, &mut target).unwrap(),
+            ReplicationEvent::BatchReplicated {
+                changes: 1,
+                docs_written: 2,
+                last_seq: Sequence::from(7),
+            }
+        );
+
+        assert_eq!(
+            replicator.step(&mut source, &mut target).unwrap(),
+            ReplicationEvent::Completed { last_seq: Sequence::from(7) }
+        );
+
+        assert!(replicator.is_complete());
+        assert_eq!(target.leafs(), source.leafs());
+        assert!(target.docs[&DocumentId::from("bravo")].tree.is_deleted());
+        assert_eq!(target.docs[&DocumentId::from("charlie")].tree.conflicts().len(), 1);
+
+        let stats = replicator.stats();
+        assert_eq!(stats.missing_checked, 4);
+        assert_eq!(stats.missing_found, 4);
+        assert_eq!(stats.docs_read, 4);
+        assert_eq!(stats.docs_written, 4);
+        assert_eq!(stats.doc_write_failures, 0);
+
+        assert!(source.calls.contains(&"bulk_get"));
+        assert!(!source.calls.contains(&"open_revs"));
+
+        let id = DocumentId::from(LocalDocumentName::new(replication_id));
+        for peer in [&source, &target].iter() {
+            let &(n, ref doc) = &peer.locals[&id];
+            assert_eq!(n, 2);
+            assert_eq!(doc["session_id"], json!(replicator.session_id()));
+            assert_eq!(doc["source_last_seq"], json!(7));
+            assert_eq!(doc["history"].as_array().unwrap().len(), 1);
+        }
+    }
+
+    #[test]
+    fn replicator_falls_back_to_open_revs() {
+        let mut source = make_source("1.6.1");
+        let mut target = MemoryPeer::new("memory://target", "1.6.1");
+
+        let stats = Replicator::new().run(&mut source, &mut target).unwrap();
+        assert_eq!(stats.docs_written, 4);
+        assert_eq!(target.leafs(), source.leafs());
+
+        assert!(source.calls.contains(&"open_revs"));
+        assert!(!source.calls.contains(&"bulk_get"));
+    }
+
+    #[test]
+    fn replicator_resumes_from_checkpoint() {
+        let mut source = make_source("2.1.1");
+        let mut target = MemoryPeer::new("memory://target", "2.1.1");
+
+        let first = {
+            let mut replicator = Replicator::new();
+            replicator.run(&mut source, &mut target).unwrap();
+            String::from(replicator.session_id())
+        };
+
+        let rev = source.docs[&DocumentId::from("alpha")].tree.winner().unwrap().clone();
+        source.edit("alpha", Some(&rev), json!({"value": 3}), false);
+
+        let mut replicator = Replicator::new();
+        replicator.step(&mut source, &mut target).unwrap();
+        assert_eq!(
+            replicator.step(&mut source, &mut target).unwrap(),
+            ReplicationEvent::CheckpointsCompared { since: Sequence::from(7) }
+        );
+
+        let stats = replicator.run(&mut source, &mut target).unwrap();
+        assert_eq!(stats.missing_checked, 1);
+        assert_eq!(stats.docs_written, 1);
+        assert_eq!(target.leafs(), source.leafs());
+
+        let id = DocumentId::from(LocalDocumentName::new(replicator.replication_id().unwrap()));
+        let history = target.locals[&id].1["history"].as_array().unwrap().clone();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["session_id"], json!(replicator.session_id()));
+        assert_eq!(history[1]["session_id"], json!(first));
+    }
+
+    #[test]
+    fn replicator_fails_if_database_is_missing() {
+        let mut source = make_source("2.1.1");
+        let mut target = MemoryPeer::new("memory://target", "2.1.1");
+        target.exists = false;
+
+        match Replicator::new().run(&mut source, &mut target) {
+            Err(ReplicationError::TargetNotFound) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        source.exists = false;
+
+        match Replicator::new().run(&mut source, &mut target) {
+            Err(ReplicationError::SourceNotFound) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn replicator_skips_only_missing_revisions() {
+        let mut source = make_source("2.1.1");
+        let mut target = MemoryPeer::new("memory://target", "2.1.1");
+        source.failures.insert(DocumentId::from("bravo"), "forbidden");
+
+        match Replicator::new().run(&mut source, &mut target) {
+            Err(ReplicationError::SourceDocument(ref e)) if e.error == "forbidden" => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        assert!(target.locals.is_empty());
+
+        source.failures.insert(DocumentId::from("bravo"), "not_found");
+
+        let stats = Replicator::new().run(&mut source, &mut target).unwrap();
+        assert_eq!(stats.missing_found, 4);
+        assert_eq!(stats.docs_read, 3);
+        assert!(!target.docs.contains_key(&DocumentId::from("bravo")));
+        assert!(!target.locals.is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+use {DocumentId, Revision, RevisionTree};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::marker::PhantomData;
+
+/// `RevsDiffRequest` is the body of a `POST /{db}/_revs_diff` or `POST
+/// /{db}/_missing_revs` request.
+///
+/// # Summary
+///
+/// * `RevsDiffRequest` maps document ids to the revisions the application
+///   asks about.
+///
+/// * `RevsDiffRequest` implements `Deserialize` and `Serialize`, as a JSON
+///   object.
+///
+/// # Remarks
+///
+/// Both endpoints take the same request body. CouchDB 1.x and later support
+/// `_revs_diff`. The older `_missing_revs` endpoint is gone from CouchDB 3.x.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// use couchdb::replication::RevsDiffRequest;
+///
+/// let mut request = RevsDiffRequest::new();
+/// request.insert(
+///     "alpha",
+///     vec![couchdb::Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap()],
+/// );
+///
+/// assert_eq!(
+///     serde_json::to_string(&request).unwrap(),
+///     r#"{"alpha":["2-7051cbe5c8faecd085a3fa619e6e6337"]}"#
+/// );
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RevsDiffRequest {
+    docs: BTreeMap<DocumentId, Vec<Revision>>,
+}
+
+/// `RevsDiffResponse` is the response to a `POST /{db}/_revs_diff` request.
+///
+/// # Summary
+///
+/// * `RevsDiffResponse` maps document ids to the revisions the server lacks
+///   and the server's revisions that may be ancestors of them.
+///
+/// * `RevsDiffResponse` implements `Deserialize` and `Serialize`, as a JSON
+///   object.
+///
+/// * The `revs_diff` function computes a `RevsDiffResponse` locally.
+///
+/// # Remarks
+///
+/// The response includes only documents having at least one missing revision.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"{
+///     "alpha": {
+///         "missing": ["3-6bcedf1f2e7f1b2cd45e5ea4ff8a1bdb"],
+///         "possible_ancestors": ["2-7051cbe5c8faecd085a3fa619e6e6337"]
+///     }
+/// }"#;
+///
+/// let response: couchdb::replication::RevsDiffResponse = serde_json::from_slice(body).unwrap();
+/// let entry = response.get(&couchdb::DocumentId::from("alpha")).unwrap();
+/// assert_eq!(entry.missing[0].sequence_number(), 3);
+/// assert_eq!(entry.possible_ancestors[0].sequence_number(), 2);
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RevsDiffResponse {
+    docs: BTreeMap<DocumentId, RevsDiffEntry>,
+}
+
+/// `RevsDiffEntry` contains the missing revisions of one document within a
+/// `_revs_diff` response.
+///
+/// # Compatibility
+///
+/// `RevsDiffEntry` contains a dummy private member in order to prevent
+/// applications from directly constructing a `RevsDiffEntry` instance. This
+/// allows new fields to be added to `RevsDiffEntry` in future releases without
+/// it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct RevsDiffEntry {
+    /// The requested revisions the server lacks.
+    pub missing: Vec<Revision>,
+
+    /// The server's leaf revisions that may be ancestors of a missing
+    /// revision—i.e., that have a lower sequence number than some missing
+    /// revision.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub possible_ancestors: Vec<Revision>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+/// `MissingRevsResponse` is the response to a `POST /{db}/_missing_revs`
+/// request, which CouchDB 1.x and 2.x support.
+///
+/// # Compatibility
+///
+/// `MissingRevsResponse` contains a dummy private member in order to prevent
+/// applications from directly constructing a `MissingRevsResponse` instance.
+/// This allows new fields to be added to `MissingRevsResponse` in future
+/// releases without it being a breaking change.
+///
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct MissingRevsResponse {
+    pub missing_revs: BTreeMap<DocumentId, Vec<Revision>>,
+
+    #[serde(default = "PhantomData::default", skip_serializing)]
+    _private_guard: PhantomData<()>,
+}
+
+impl RevsDiffRequest {
+    /// Constructs an empty request.
+    pub fn new() -> Self {
+        RevsDiffRequest::default()
+    }
+
+    /// Adds revisions of a document to the request.
+    pub fn insert<T: Into<DocumentId>>(&mut self, id: T, revs: Vec<Revision>) {
+        self.docs.entry(id.into()).or_default().extend(revs);
+    }
+
+    /// Borrows the requested revisions of a document.
+    pub fn get(&self, id: &DocumentId) -> Option<&[Revision]> {
+        self.docs.get(id).map(|x| x.as_slice())
+    }
+
+    /// Returns the number of documents in the request.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Returns whether the request has no documents.
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Returns an iterator over the request's documents and their revisions.
+    pub fn iter(&self) -> btree_map::Iter<'_, DocumentId, Vec<Revision>> {
+        self.docs.iter()
+    }
+}
+
+impl From<BTreeMap<DocumentId, Vec<Revision>>> for RevsDiffRequest {
+    fn from(docs: BTreeMap<DocumentId, Vec<Revision>>) -> Self {
+        RevsDiffRequest { docs: docs }
+    }
+}
+
+impl RevsDiffResponse {
+    /// Borrows the entry of a document, if the document has missing
+    /// revisions.
+    pub fn get(&self, id: &DocumentId) -> Option<&RevsDiffEntry> {
+        self.docs.get(id)
+    }
+
+    /// Returns the number of documents having missing revisions.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Returns whether no document has missing revisions.
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Returns an iterator over the documents having missing revisions.
+    pub fn iter(&self) -> btree_map::Iter<'_, DocumentId, RevsDiffEntry> {
+        self.docs.iter()
+    }
+}
+
+impl IntoIterator for RevsDiffResponse {
+    type Item = (DocumentId, RevsDiffEntry);
+    type IntoIter = btree_map::IntoIter<DocumentId, RevsDiffEntry>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.docs.into_iter()
+    }
+}
+
+impl From<RevsDiffResponse> for MissingRevsResponse {
+    fn from(response: RevsDiffResponse) -> Self {
+        MissingRevsResponse {
+            missing_revs: response
+                .docs
+                .into_iter()
+                .map(|(id, entry)| (id, entry.missing))
+                .collect(),
+            _private_guard: PhantomData,
+        }
+    }
+}
+
+impl Serialize for RevsDiffRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.docs.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RevsDiffRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::deserialize(deserializer).map(RevsDiffRequest::from)
+    }
+}
+
+impl Serialize for RevsDiffResponse {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.docs.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RevsDiffResponse {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::deserialize(deserializer).map(|docs| RevsDiffResponse { docs: docs })
+    }
+}
+
+/// Computes the response to a `_revs_diff` request locally, the same way the
+/// CouchDB server does.
+///
+/// The `lookup` function returns the revision tree of a given document, or
+/// `None` if the document does not exist, in which case all of the document's
+/// requested revisions are missing.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+///
+/// use couchdb::{DocumentId, Revision, RevisionTree};
+/// use couchdb::replication::{RevsDiffRequest, revs_diff};
+///
+/// let r1 = Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap();
+/// let r2 = Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap();
+///
+/// let mut tree = RevisionTree::new();
+/// tree.merge_path(&[r1.clone()], false).unwrap();
+///
+/// let mut request = RevsDiffRequest::new();
+/// request.insert("alpha", vec![r1.clone(), r2.clone()]);
+///
+/// let response = revs_diff(&request, |_| Some(&tree));
+/// let entry = response.get(&DocumentId::from("alpha")).unwrap();
+/// assert_eq!(entry.missing, vec![r2]);
+/// assert_eq!(entry.possible_ancestors, vec![r1]);
+/// ```
+///
+pub fn revs_diff<'a, F>(request: &RevsDiffRequest, mut lookup: F) -> RevsDiffResponse
+where
+    F: FnMut(&DocumentId) -> Option<&'a RevisionTree>,
+{
+    let mut docs = BTreeMap::new();
+
+    for (id, revs) in request.iter() {
+
+        let tree = match lookup(id) {
+            None => {
+                docs.insert(
+                    id.clone(),
+                    RevsDiffEntry {
+                        missing: revs.clone(),
+                        possible_ancestors: Vec::new(),
+                        _private_guard: PhantomData,
+                    },
+                );
+                continue;
+            }
+            Some(x) => x,
+        };
+
+        let missing = revs.iter()
+            .filter(|x| !tree.contains(x))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            continue;
+        }
+
+        let possible_ancestors = tree.leafs()
+            .into_iter()
+            .rev()
+            .map(|(leaf, _)| leaf)
+            .filter(|leaf| {
+                missing.iter().any(|x| {
+                    leaf.sequence_number() < x.sequence_number()
+                })
+            })
+            .cloned()
+            .collect();
+
+        docs.insert(
+            id.clone(),
+            RevsDiffEntry {
+                missing: missing,
+                possible_ancestors: possible_ancestors,
+                _private_guard: PhantomData,
+            },
+        );
+    }
+
+    RevsDiffResponse { docs: docs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn rev(s: &str) -> Revision {
+        Revision::parse(s).unwrap()
+    }
+
+    const R1: &str = "1-11111111111111111111111111111111";
+    const R2A: &str = "2-2aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const R2B: &str = "2-2bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const R3A: &str = "3-3aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    #[test]
+    fn request_round_trips() {
+        let source = r#"{"alpha":["1-11111111111111111111111111111111"],"bravo":[]}"#;
+        let got: RevsDiffRequest = serde_json::from_str(source).unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got.get(&DocumentId::from("alpha")), Some(&[rev(R1)][..]));
+        assert_eq!(serde_json::to_string(&got).unwrap(), source);
+    }
+
+    #[test]
+    fn response_round_trips() {
+        let source = r#"{"alpha":{"missing":["3-3aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"],"possible_ancestors":["2-2aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"]},"bravo":{"missing":["1-11111111111111111111111111111111"]}}"#;
+        let got: RevsDiffResponse = serde_json::from_str(source).unwrap();
+        assert_eq!(got.len(), 2);
+        assert!(got.get(&DocumentId::from("bravo")).unwrap().possible_ancestors.is_empty());
+        assert_eq!(serde_json::to_string(&got).unwrap(), source);
+
+        let missing_revs = MissingRevsResponse::from(got);
+        assert_eq!(
+            serde_json::to_string(&missing_revs).unwrap(),
+            r#"{"missing_revs":{"alpha":["3-3aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"],"bravo":["1-11111111111111111111111111111111"]}}"#
+        );
+    }
+
+    #[test]
+    fn revs_diff_computes_missing_and_possible_ancestors() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&[rev(R2A), rev(R1)], false).unwrap();
+        tree.merge_path(&[rev(R2B), rev(R1)], true).unwrap();
+
+        let mut request = RevsDiffRequest::new();
+        request.insert("alpha", vec![rev(R1), rev(R2A)]);
+        request.insert("bravo", vec![rev(R3A), rev(R2B)]);
+        request.insert("charlie", vec![rev(R1)]);
+        request.insert("delta", vec![rev("2-2ccccccccccccccccccccccccccccccc")]);
+
+        let response = revs_diff(&request, |id| match id.as_ref() {
+            "alpha" | "bravo" | "delta" => Some(&tree),
+            _ => None,
+        });
+
+        assert_eq!(response.len(), 3);
+        assert!(response.get(&DocumentId::from("alpha")).is_none());
+
+        let entry = response.get(&DocumentId::from("bravo")).unwrap();
+        assert_eq!(entry.missing, vec![rev(R3A)]);
+        assert_eq!(entry.possible_ancestors, vec![rev(R2B), rev(R2A)]);
+
+        let entry = response.get(&DocumentId::from("charlie")).unwrap();
+        assert_eq!(entry.missing, vec![rev(R1)]);
+        assert!(entry.possible_ancestors.is_empty());
+
+        let entry = response.get(&DocumentId::from("delta")).unwrap();
+        assert!(entry.possible_ancestors.is_empty());
+    }
+}
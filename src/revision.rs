@@ -1,4 +1,5 @@
-use {Error, serde, std};
+use {Digest, Error, md5, serde, std};
+use etf::{self, EjsonObject, Term};
 use uuid::Uuid;
 
 /// `Revision` contains a document revision.
@@ -9,9 +10,16 @@ use uuid::Uuid;
 ///   `1-9c65296036141e575d32ba9c034dd3ee`.
 ///
 /// * `Revision` can be parsed from a string via `FromStr` or the
-///   `Revision::parse` method.
+///   `Revision::parse` method, both of which require an MD5 digest. The
+///   `Revision::parse_tolerant` method also accepts other digests.
 ///
-/// * `Revision` implements `Deserialize` and `Serialize`.
+/// * `Revision` implements `Deserialize` and `Serialize`. Deserialization is
+///   tolerant, and `Revision::deserialize_strict` deserializes the same as
+///   `Revision::parse`.
+///
+/// * `Revision` implements `Ord`, using the same ordering as the CouchDB
+///   server: first by sequence number and then by digest. MD5 digests compare
+///   as hexadecimal strings.
 ///
 /// # Remarks
 ///
@@ -21,6 +29,13 @@ use uuid::Uuid;
 /// is a hash of the document content, which the CouchDB server uses to detect
 /// conflicts.
 ///
+/// Some replication peers—e.g., PouchDB, Couchbase Lite, and Cloudant—and
+/// older tools write revisions whose digest is not a 32-digit hexadecimal MD5
+/// digest. The CouchDB server stores such a digest as an opaque string, and so
+/// does `Revision`. A tolerant revision with a non-MD5 digest formats exactly
+/// as it was parsed, whereas an MD5 digest formats in lowercase, as the server
+/// formats it.
+///
 /// # Example
 ///
 /// ```
@@ -35,7 +50,13 @@ use uuid::Uuid;
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Revision {
     sequence_number: u64,
-    digest: Uuid,
+    digest: RevisionDigest,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+enum RevisionDigest {
+    Md5(Uuid),
+    Other(String),
 }
 
 impl Revision {
@@ -48,6 +69,46 @@ impl Revision {
         Revision::from_str(s)
     }
 
+    /// Constructs a new `Revision` from the given string, accepting any
+    /// non-empty digest.
+    ///
+    /// The string must be of the form `42-{digest}`. A digest of 32
+    /// hexadecimal digits, of either case, is an MD5 digest, the same as with
+    /// `Revision::parse` and the CouchDB server, and it formats in lowercase.
+    /// Any other digest is stored verbatim so that it formats exactly as it
+    /// was parsed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate couchdb;
+    ///
+    /// let rev = couchdb::Revision::parse_tolerant(
+    ///     "3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e",
+    /// ).unwrap();
+    ///
+    /// assert!(!rev.is_md5());
+    /// assert_eq!(rev.to_string(), "3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e");
+    ///
+    /// couchdb::Revision::parse("3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e")
+    ///     .unwrap_err();
+    /// ```
+    ///
+    pub fn parse_tolerant(s: &str) -> Result<Self, Error> {
+        let (sequence_number, digest_str) = split_revision(s)?;
+        if digest_str.is_empty() {
+            return Err(Error::BadRevision);
+        }
+        let digest = match parse_md5_digest(digest_str) {
+            Some(x) => RevisionDigest::Md5(x),
+            None => RevisionDigest::Other(String::from(digest_str)),
+        };
+        Ok(Revision {
+            sequence_number: sequence_number,
+            digest: digest,
+        })
+    }
+
     /// Returns the sequence number part of the revision.
     ///
     /// The sequence number is the `42` part of the revision
@@ -56,39 +117,130 @@ impl Revision {
     pub fn sequence_number(&self) -> u64 {
         self.sequence_number
     }
-}
 
-impl std::fmt::Display for Revision {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}-{}", self.sequence_number, self.digest.simple())
+    /// Returns whether the revision's digest is an MD5 digest.
+    ///
+    /// Only a revision parsed via `Revision::parse_tolerant` or deserialized
+    /// may have a digest that is not MD5.
+    ///
+    pub fn is_md5(&self) -> bool {
+        match self.digest {
+            RevisionDigest::Md5(_) => true,
+            RevisionDigest::Other(_) => false,
+        }
+    }
+
+    /// Deserializes a revision the same as `Revision::parse`, requiring an MD5
+    /// digest, for use with serde's `deserialize_with` attribute.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// extern crate couchdb;
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// extern crate serde_json;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Doc {
+    ///     #[serde(rename = "_rev", deserialize_with = "couchdb::Revision::deserialize_strict")]
+    ///     rev: couchdb::Revision,
+    /// }
+    ///
+    /// # fn main() {
+    /// let doc: Doc = serde_json::from_str(r#"{"_rev": "1-967a00dff5e02add41819138abb3284d"}"#)
+    ///     .unwrap();
+    /// assert!(doc.rev.is_md5());
+    ///
+    /// assert!(serde_json::from_str::<Doc>(r#"{"_rev": "1-abc"}"#).is_err());
+    /// # }
+    /// ```
+    ///
+    pub fn deserialize_strict<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_str(RevisionVisitor(Revision::parse))
+    }
+
+    // Returns the digest part of the revision—e.g., the
+    // `1234567890abcdef1234567890abcdef` part of the revision
+    // `42-1234567890abcdef1234567890abcdef`.
+    pub(crate) fn digest_string(&self) -> String {
+        match self.digest {
+            RevisionDigest::Md5(ref x) => x.simple().to_string(),
+            RevisionDigest::Other(ref x) => x.clone(),
+        }
+    }
+
+    // Returns the digest as the CouchDB server stores it: the 16 bytes of an
+    // MD5 digest, or else the digest's text.
+    fn digest_bytes(&self) -> &[u8] {
+        match self.digest {
+            RevisionDigest::Md5(ref x) => x.as_bytes(),
+            RevisionDigest::Other(ref x) => x.as_bytes(),
+        }
     }
 }
 
-impl std::str::FromStr for Revision {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+fn split_revision(s: &str) -> Result<(u64, &str), Error> {
 
-        let mut parts = s.splitn(2, '-');
+    let mut parts = s.splitn(2, '-');
 
-        let sequence_number_str = parts.next().ok_or(Error::BadRevision)?;
-        let sequence_number = u64::from_str_radix(sequence_number_str, 10).map_err(|_| {
-            Error::BadRevision
-        })?;
+    let sequence_number_str = parts.next().ok_or(Error::BadRevision)?;
+    let sequence_number = u64::from_str_radix(sequence_number_str, 10).map_err(|_| {
+        Error::BadRevision
+    })?;
 
-        if sequence_number == 0 {
-            return Err(Error::BadRevision);
-        }
+    if sequence_number == 0 {
+        return Err(Error::BadRevision);
+    }
 
-        let digest_str = parts.next().ok_or(Error::BadRevision)?;
-        let digest = Uuid::parse_str(digest_str).map_err(|_| Error::BadRevision)?;
+    let digest_str = parts.next().ok_or(Error::BadRevision)?;
+    Ok((sequence_number, digest_str))
+}
 
-        if digest_str.chars().any(|c| !c.is_digit(16)) {
-            return Err(Error::BadRevision);
+fn parse_md5_digest(s: &str) -> Option<Uuid> {
+    if s.len() != 32 || s.chars().any(|c| !c.is_digit(16)) {
+        return None;
+    }
+    Uuid::parse_str(s).ok()
+}
+
+impl std::fmt::Display for Revision {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.digest {
+            RevisionDigest::Md5(ref x) => write!(f, "{}-{}", self.sequence_number, x.simple()),
+            RevisionDigest::Other(ref x) => write!(f, "{}-{}", self.sequence_number, x),
         }
+    }
+}
+
+impl Ord for Revision {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // The CouchDB server compares digests as Erlang binaries. An MD5
+        // digest's bytes compare in the same order as its lowercase
+        // hexadecimal string.
+        self.sequence_number.cmp(&other.sequence_number).then_with(|| {
+            self.digest_bytes().cmp(other.digest_bytes())
+        })
+    }
+}
 
+impl PartialOrd for Revision {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for Revision {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sequence_number, digest_str) = split_revision(s)?;
+        let digest = parse_md5_digest(digest_str).ok_or(Error::BadRevision)?;
         Ok(Revision {
             sequence_number: sequence_number,
-            digest: digest,
+            digest: RevisionDigest::Md5(digest),
         })
     }
 }
@@ -114,24 +266,177 @@ impl<'de> serde::Deserialize<'de> for Revision {
     where
         D: serde::Deserializer<'de>,
     {
-        struct Visitor;
+        deserializer.deserialize_str(RevisionVisitor(Revision::parse_tolerant))
+    }
+}
 
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = Revision;
+// RevisionVisitor deserializes a revision string using the given parse
+// function.
+struct RevisionVisitor(fn(&str) -> Result<Revision, Error>);
 
-            fn expecting(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-                write!(f, "a string specifying a CouchDB document revision")
-            }
+impl<'de> serde::de::Visitor<'de> for RevisionVisitor {
+    type Value = Revision;
 
-            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
-            where
-                E: serde::de::Error,
-            {
-                Revision::parse(v).map_err(|_e| E::invalid_value(serde::de::Unexpected::Str(v), &self))
-            }
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "a string specifying a CouchDB document revision")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        (self.0)(v).map_err(|_e| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+    }
+}
+
+/// Chooses a document's winning revision from among its leaf revisions.
+///
+/// Each leaf comprises a revision and whether that revision is deleted. The
+/// winner is chosen the same way the CouchDB server chooses it: a non-deleted
+/// leaf beats a deleted leaf, and otherwise the greatest revision wins—i.e.,
+/// the one with the highest sequence number and then the highest digest.
+///
+/// Returns `None` if there are no leafs.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+///
+/// let a = couchdb::Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap();
+/// let b = couchdb::Revision::parse("3-0e1a1e4c8e3ab1b5c2e0a6d1c4ab4d44").unwrap();
+///
+/// assert_eq!(couchdb::winning_revision(vec![(&a, false), (&b, false)]), Some(&b));
+/// assert_eq!(couchdb::winning_revision(vec![(&a, false), (&b, true)]), Some(&a));
+/// ```
+///
+pub fn winning_revision<'a, I>(leafs: I) -> Option<&'a Revision>
+where
+    I: IntoIterator<Item = (&'a Revision, bool)>,
+{
+    leafs
+        .into_iter()
+        .max_by(|&(a, a_deleted), &(b, b_deleted)| {
+            b_deleted.cmp(&a_deleted).then_with(|| a.cmp(b))
+        })
+        .map(|(rev, _)| rev)
+}
+
+/// Computes the revision the CouchDB server assigns to a document when
+/// writing it.
+///
+/// # Summary
+///
+/// * `prev` is the revision the write replaces, or `None` for a new document.
+///
+/// * `deleted` is whether the write deletes the document.
+///
+/// * `body` is the document's JSON object. Top-level fields beginning with an
+///   underscore (e.g., `_id`, `_rev`, and `_attachments`) do not affect the
+///   revision and are ignored.
+///
+/// * `attachments` lists the name, content type, and digest of each of the
+///   document's attachments, in the same order as in the document's
+///   `_attachments` field.
+///
+/// # Remarks
+///
+/// The server computes the digest part of a revision as the MD5 hash of the
+/// Erlang external term format encoding of the document's deleted status,
+/// previous revision, body, and attachment digests. This function does the
+/// same, so it produces the same revision as the server for the same input.
+///
+/// The server decodes the body's numbers and object members as they appear in
+/// the JSON text. Thus the order of object members affects the revision, as
+/// does whether a number has a fractional part or exponent—e.g., `1` versus
+/// `1.0`.
+///
+/// Returns an error if the body is not a JSON object or if an attachment
+/// digest is not an MD5 digest, in which case the server generates a random
+/// revision. Also returns an error if the body contains an integer whose
+/// magnitude exceeds 64 bits, which this function does not support.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+///
+/// let rev = couchdb::new_revision(None, false, br#"{"foo":"bar"}"#, vec![]).unwrap();
+/// assert_eq!(rev.to_string(), "1-4c6114c65e295552ab1019e2b046b10e");
+/// ```
+///
+pub fn new_revision<'a, I>(
+    prev: Option<&Revision>,
+    deleted: bool,
+    body: &[u8],
+    attachments: I,
+) -> Result<Revision, Error>
+where
+    I: IntoIterator<Item = (&'a str, &'a str, &'a Digest)>,
+{
+    let EjsonObject(members) = etf::from_slice(body).map_err(|e| {
+        Error::from(("Failed to decode the document body", e))
+    })?;
+
+    let body = EjsonObject(
+        members
+            .into_iter()
+            .filter(|x| !x.0.starts_with('_'))
+            .collect(),
+    ).into_term();
+
+    // The server lists attachments in reverse order.
+    let mut atts = Vec::new();
+    for (name, content_type, digest) in attachments {
+        if !digest.is_md5() {
+            return Err(Error::BadDigest);
         }
+        atts.insert(
+            0,
+            Term::Tuple(vec![
+                Term::Binary(Vec::from(name.as_bytes())),
+                Term::Binary(Vec::from(content_type.as_bytes())),
+                Term::Binary(Vec::from(digest.bytes())),
+            ]),
+        );
+    }
 
-        deserializer.deserialize_str(Visitor)
+    let (prev_sequence_number, prev_digest) = match prev {
+        None => (0, Term::integer(0)),
+        Some(x) => (x.sequence_number, Term::Binary(Vec::from(x.digest_bytes()))),
+    };
+
+    let term = Term::List(vec![
+        Term::Atom(if deleted { "true" } else { "false" }),
+        Term::Integer {
+            negative: false,
+            magnitude: prev_sequence_number,
+        },
+        prev_digest,
+        body,
+        Term::List(atts),
+    ]);
+
+    Ok(Revision {
+        sequence_number: prev_sequence_number + 1,
+        digest: RevisionDigest::Md5(Uuid::from_bytes(&md5::compute(term.to_binary()).0).unwrap()),
+    })
+}
+
+#[cfg(feature = "proptest")]
+impl proptest::arbitrary::Arbitrary for Revision {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        use proptest::prelude::*;
+        (1u64.., any::<[u8; 16]>())
+            .prop_map(|(sequence_number, digest)| {
+                Revision {
+                    sequence_number: sequence_number,
+                    digest: RevisionDigest::Md5(Uuid::from_bytes(&digest).unwrap()),
+                }
+            })
+            .boxed()
     }
 }
 
@@ -145,7 +450,7 @@ mod tests {
     fn parse_ok() {
         let expected = Revision {
             sequence_number: 42,
-            digest: "1234567890abcdeffedcba0987654321".parse().unwrap(),
+            digest: RevisionDigest::Md5("1234567890abcdeffedcba0987654321".parse().unwrap()),
         };
         let got = Revision::parse("42-1234567890abcdeffedcba0987654321").unwrap();
         assert_eq!(expected, got);
@@ -167,7 +472,7 @@ mod tests {
         let expected = "42-1234567890abcdeffedcba0987654321";
         let source = Revision {
             sequence_number: 42,
-            digest: "1234567890abcdeffedcba0987654321".parse().unwrap(),
+            digest: RevisionDigest::Md5("1234567890abcdeffedcba0987654321".parse().unwrap()),
         };
         let got = format!("{}", source);
         assert_eq!(expected, got);
@@ -178,7 +483,7 @@ mod tests {
         use std::str::FromStr;
         let expected = Revision {
             sequence_number: 42,
-            digest: "1234567890abcdeffedcba0987654321".parse().unwrap(),
+            digest: RevisionDigest::Md5("1234567890abcdeffedcba0987654321".parse().unwrap()),
         };
         let got = Revision::from_str("42-1234567890abcdeffedcba0987654321").unwrap();
         assert_eq!(expected, got);
@@ -214,7 +519,7 @@ mod tests {
         let expected = "42-1234567890abcdeffedcba0987654321";
         let source = Revision {
             sequence_number: 42,
-            digest: "1234567890abcdeffedcba0987654321".parse().unwrap(),
+            digest: RevisionDigest::Md5("1234567890abcdeffedcba0987654321".parse().unwrap()),
         };
         let got = format!("{}", source);
         assert_eq!(expected, got);
@@ -256,6 +561,87 @@ mod tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn parse_tolerant_ok() {
+        let got = Revision::parse_tolerant("42-1234567890abcdeffedcba0987654321").unwrap();
+        assert_eq!(got, Revision::parse("42-1234567890abcdeffedcba0987654321").unwrap());
+        assert!(got.is_md5());
+
+        let got = Revision::parse_tolerant("42-1234567890ABCDEFFEDCBA0987654321").unwrap();
+        assert_eq!(got, Revision::parse("42-1234567890ABCDEFFEDCBA0987654321").unwrap());
+        assert!(got.is_md5());
+        assert_eq!(got.to_string(), "42-1234567890abcdeffedcba0987654321");
+
+        for s in &[
+            "3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e",
+            "1-abc",
+            "2-ABCDEF",
+            "7-a1b2c3-d4e5",
+        ]
+        {
+            let got = Revision::parse_tolerant(s).unwrap();
+            assert!(!got.is_md5());
+            assert_eq!(got.to_string(), *s);
+            Revision::parse(s).unwrap_err();
+        }
+    }
+
+    #[test]
+    fn parse_tolerant_nok() {
+        for s in &["", "1-", "0-abc", "-abc", "abc", "x-abc"] {
+            match Revision::parse_tolerant(s) {
+                Err(Error::BadRevision) => {}
+                x => panic!("Got unexpected result {:?} for {:?}", x, s),
+            }
+        }
+    }
+
+    #[test]
+    fn ord_compares_digests_as_server_does() {
+        let md5 = Revision::parse("2-ffffffffffffffffffffffffffffffff").unwrap();
+        let other = Revision::parse_tolerant("2-abc").unwrap();
+        let lower = Revision::parse_tolerant("2-ABC").unwrap();
+        assert!(md5 > other);
+        assert!(other > lower);
+        assert!(Revision::parse_tolerant("3-abc").unwrap() > md5);
+    }
+
+    #[test]
+    fn deserialization_is_tolerant() {
+        let source = r#""3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e""#;
+        let got: Revision = serde_json::from_str(source).unwrap();
+        assert!(!got.is_md5());
+        assert_eq!(serde_json::to_string(&got).unwrap(), source);
+    }
+
+    #[test]
+    fn strict_deserialization_requires_md5_digest() {
+
+        #[derive(Deserialize)]
+        struct Doc {
+            #[serde(deserialize_with = "Revision::deserialize_strict")]
+            rev: Revision,
+        }
+
+        let got: Doc = serde_json::from_str(r#"{"rev": "42-1234567890ABCDEFFEDCBA0987654321"}"#).unwrap();
+        assert_eq!(got.rev, Revision::parse("42-1234567890abcdeffedcba0987654321").unwrap());
+
+        for source in &[r#"{"rev": "3-6a5d8e2f0b7c4e3a9f1d2b8c7e6a5d4c3b2a1f0e"}"#, r#"{"rev": "1-abc"}"#] {
+            match serde_json::from_str::<Doc>(source) {
+                Err(ref e) if e.is_data() => {}
+                x => panic!("Got unexpected result {:?}", x.map(|x| x.rev)),
+            }
+        }
+    }
+
+    #[test]
+    fn new_revision_with_non_md5_previous_revision() {
+        let prev = Revision::parse_tolerant("1-abc").unwrap();
+        let got = new_revision(Some(&prev), false, b"{}", vec![]).unwrap();
+        assert_eq!(got.sequence_number(), 2);
+        assert!(got.is_md5());
+    }
+
     #[test]
     fn deserialization_enforces_revision_validity() {
         let source = r#""obviously bad revision""#;
@@ -264,4 +650,123 @@ mod tests {
             x => panic!("Got unexpected result {:?}", x),
         }
     }
+
+    #[test]
+    fn ord_by_sequence_number_then_digest() {
+        let revs = vec![
+            "1-ffffffffffffffffffffffffffffffff",
+            "2-00000000000000000000000000000000",
+            "2-0000000000000000000000000000000a",
+            "2-a0000000000000000000000000000000",
+            "10-00000000000000000000000000000000",
+        ].into_iter()
+            .map(|x| Revision::parse(x).unwrap())
+            .collect::<Vec<_>>();
+
+        for (i, a) in revs.iter().enumerate() {
+            for (j, b) in revs.iter().enumerate() {
+                assert_eq!(a.cmp(b), i.cmp(&j), "{} vs {}", a, b);
+            }
+        }
+
+        let a = Revision::parse("2-ABCDEF00000000000000000000000000").unwrap();
+        let b = Revision::parse("2-abcdef00000000000000000000000000").unwrap();
+        assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn winning_revision_prefers_non_deleted_then_highest() {
+        let r1 = Revision::parse("3-00000000000000000000000000000000").unwrap();
+        let r2 = Revision::parse("2-ffffffffffffffffffffffffffffffff").unwrap();
+        let r3 = Revision::parse("2-eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee").unwrap();
+
+        assert_eq!(winning_revision(vec![]), None);
+        assert_eq!(winning_revision(vec![(&r2, false), (&r1, false), (&r3, false)]), Some(&r1));
+        assert_eq!(winning_revision(vec![(&r2, false), (&r1, true), (&r3, false)]), Some(&r2));
+        assert_eq!(winning_revision(vec![(&r2, true), (&r1, true), (&r3, false)]), Some(&r3));
+        assert_eq!(winning_revision(vec![(&r2, true), (&r1, true), (&r3, true)]), Some(&r1));
+    }
+
+    // These revisions were captured from CouchDB 2.x and 3.x servers.
+    #[test]
+    fn new_revision_matches_server() {
+        let got = new_revision(None, false, b"{}", vec![]).unwrap();
+        assert_eq!(got.to_string(), "1-967a00dff5e02add41819138abb3284d");
+
+        let got = new_revision(None, false, br#"{"_id":"foo"}"#, vec![]).unwrap();
+        assert_eq!(got.to_string(), "1-967a00dff5e02add41819138abb3284d");
+
+        let got = new_revision(None, false, br#"{"_id":"foo","foo":"bar"}"#, vec![]).unwrap();
+        assert_eq!(got.to_string(), "1-4c6114c65e295552ab1019e2b046b10e");
+
+        let prev = Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap();
+        let got = new_revision(Some(&prev), true, br#"{"_deleted":true}"#, vec![]).unwrap();
+        assert_eq!(got.to_string(), "2-eec205a9d413992850a6e32678485900");
+    }
+
+    #[test]
+    fn new_revision_depends_on_body_and_attachments() {
+        let a = new_revision(None, false, br#"{"a":1,"b":2}"#, vec![]).unwrap();
+        let b = new_revision(None, false, br#"{"b":2,"a":1}"#, vec![]).unwrap();
+        let c = new_revision(None, false, br#"{"a":1.0,"b":2}"#, vec![]).unwrap();
+        assert!(a != b);
+        assert!(a != c);
+
+        let d1 = Digest::compute_md5(b"hello");
+        let d2 = Digest::compute_md5(b"world");
+        let x = new_revision(None, false, b"{}", vec![("a", "text/plain", &d1), ("b", "text/plain", &d2)]).unwrap();
+        let y = new_revision(None, false, b"{}", vec![("b", "text/plain", &d2), ("a", "text/plain", &d1)]).unwrap();
+        assert_eq!(x.sequence_number(), 1);
+        assert!(x != y);
+        assert!(x != new_revision(None, false, b"{}", vec![]).unwrap());
+    }
+
+    #[test]
+    fn new_revision_nok() {
+        match new_revision(None, false, b"[]", vec![]) {
+            Err(Error::Json { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        match new_revision(None, false, b"{", vec![]) {
+            Err(Error::Json { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        match new_revision(None, false, br#"{"a":123456789012345678901234567890}"#, vec![]) {
+            Err(Error::Json { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        let digest: Digest = "sha-AAAA".parse().unwrap();
+        match new_revision(None, false, b"{}", vec![("a", "text/plain", &digest)]) {
+            Err(Error::BadDigest) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "proptest")]
+    mod round_trip {
+        use super::super::*;
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn revision_round_trips(x in any::<Revision>()) {
+                prop_assert_eq!(Revision::parse(&x.to_string()).unwrap(), x);
+            }
+
+            #[test]
+            fn tolerant_revision_round_trips(s in "[1-9][0-9]{0,5}-[g-z][a-zA-Z0-9]{0,40}") {
+                prop_assert_eq!(Revision::parse_tolerant(&s).unwrap().to_string(), s);
+            }
+
+            #[test]
+            fn revision_ord_matches_hex_string_ord(a in any::<Revision>(), b in any::<Revision>()) {
+                let key = |x: &Revision| {
+                    let s = x.to_string();
+                    let digest = String::from(&s[s.find('-').unwrap() + 1..]);
+                    (x.sequence_number(), digest)
+                };
+                prop_assert_eq!(a.cmp(&b), key(&a).cmp(&key(&b)));
+            }
+        }
+    }
 }
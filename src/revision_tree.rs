@@ -0,0 +1,398 @@
+use {Error, Revision, winning_revision};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// `RevisionTree` is the revision history of a single document, comprising
+/// all known revisions and how they descend from each other.
+///
+/// # Summary
+///
+/// * `RevisionTree` merges revision paths—e.g., as obtained from a document's
+///   `_revisions` field—using the same rules as the CouchDB server's
+///   `couch_key_tree` module.
+///
+/// * `RevisionTree` tracks which leaf revisions are deleted, and it computes
+///   the document's winning revision, conflicts, and deleted conflicts.
+///
+/// * `RevisionTree` stems its history to a `revs_limit`, discarding the same
+///   revisions the CouchDB server would discard.
+///
+/// # Remarks
+///
+/// A revision tree may have more than one root. This happens after stemming
+/// removes a common ancestor, or when merging a path that shares no revision
+/// with the tree.
+///
+/// Only the leaf revision of each merged path carries a deleted status. The
+/// status of an interior revision is irrelevant to the document's state.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+///
+/// use couchdb::{MergeOutcome, Revision, RevisionTree};
+///
+/// let r1 = Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap();
+/// let r2a = Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap();
+/// let r2b = Revision::parse("2-eec205a9d413992850a6e32678485900").unwrap();
+///
+/// let mut tree = RevisionTree::new();
+/// assert_eq!(tree.merge_path(&[r2a.clone(), r1.clone()], false).unwrap(), MergeOutcome::NewLeaf);
+/// assert_eq!(tree.merge_path(&[r2b.clone(), r1.clone()], false).unwrap(), MergeOutcome::NewBranch);
+///
+/// assert_eq!(tree.winner(), Some(&r2b));
+/// assert_eq!(tree.conflicts(), vec![&r2a]);
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RevisionTree {
+    nodes: BTreeMap<Revision, Node>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Node {
+    parent: Option<Revision>,
+
+    // The deleted status is unknown for a revision that has only ever appeared
+    // as an ancestor in a merged path.
+    deleted: Option<bool>,
+}
+
+/// `MergeOutcome` describes how merging a revision path changed a
+/// [`RevisionTree`](struct.RevisionTree.html).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MergeOutcome {
+    /// The path extended an existing leaf revision, or the tree was empty.
+    NewLeaf,
+
+    /// The path added a new branch to the tree, thereby creating a conflict
+    /// or a new root.
+    NewBranch,
+
+    /// The path's leaf revision was already in the tree.
+    InternalNode,
+}
+
+impl RevisionTree {
+    /// Constructs an empty revision tree.
+    pub fn new() -> Self {
+        RevisionTree::default()
+    }
+
+    /// Returns the number of revisions in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns whether the tree has no revisions.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Returns whether the tree contains the given revision.
+    pub fn contains(&self, rev: &Revision) -> bool {
+        self.nodes.contains_key(rev)
+    }
+
+    /// Merges a revision path into the tree.
+    ///
+    /// The path lists revisions newest first, as in a document's `_revisions`
+    /// field, such that each revision is the parent of the revision before it.
+    /// The `deleted` argument is the deleted status of the path's first
+    /// revision.
+    ///
+    /// Returns an error if the path is empty or if its sequence numbers do not
+    /// decrease one by one.
+    ///
+    pub fn merge_path(&mut self, path: &[Revision], deleted: bool) -> Result<MergeOutcome, Error> {
+
+        if path.is_empty() {
+            return Err(Error::bad_revision_path("The path is empty"));
+        }
+
+        for w in path.windows(2) {
+            if w[0].sequence_number() != w[1].sequence_number() + 1 {
+                return Err(Error::bad_revision_path(
+                    "The path's sequence numbers are not consecutive",
+                ));
+            }
+        }
+
+        let outcome = if self.nodes.contains_key(&path[0]) {
+            MergeOutcome::InternalNode
+        } else if self.nodes.is_empty() {
+            MergeOutcome::NewLeaf
+        } else {
+            match path.iter().find(|x| self.nodes.contains_key(x)) {
+                Some(ancestor) if self.is_leaf(ancestor) => MergeOutcome::NewLeaf,
+                _ => MergeOutcome::NewBranch,
+            }
+        };
+
+        for (i, rev) in path.iter().enumerate() {
+            let parent = path.get(i + 1).cloned();
+            let node = self.nodes.entry(rev.clone()).or_insert_with(|| {
+                Node {
+                    parent: None,
+                    deleted: None,
+                }
+            });
+            if node.parent.is_none() {
+                // Merging a longer path grafts history onto a stemmed root.
+                node.parent = parent;
+            }
+            if i == 0 && node.deleted.is_none() {
+                node.deleted = Some(deleted);
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    /// Returns the tree's leaf revisions, each with its deleted status.
+    ///
+    /// The leafs are sorted such that the winning revision is first, followed
+    /// by the remaining leafs in descending order of precedence.
+    ///
+    pub fn leafs(&self) -> Vec<(&Revision, bool)> {
+        let parents = self.nodes
+            .values()
+            .filter_map(|x| x.parent.as_ref())
+            .collect::<HashSet<_>>();
+        let mut leafs = self.nodes
+            .iter()
+            .filter(|&(rev, _)| !parents.contains(rev))
+            .map(|(rev, node)| (rev, node.deleted.unwrap_or(false)))
+            .collect::<Vec<_>>();
+        leafs.sort_by(|&(a, a_deleted), &(b, b_deleted)| {
+            a_deleted.cmp(&b_deleted).then_with(|| b.cmp(a))
+        });
+        leafs
+    }
+
+    /// Returns the document's winning revision, or `None` if the tree is
+    /// empty.
+    pub fn winner(&self) -> Option<&Revision> {
+        winning_revision(self.leafs())
+    }
+
+    /// Returns whether the document is deleted—i.e., whether its winning
+    /// revision is deleted.
+    pub fn is_deleted(&self) -> bool {
+        self.leafs().first().map(|&(_, deleted)| deleted).unwrap_or(
+            false,
+        )
+    }
+
+    /// Returns the document's conflicts—i.e., its non-deleted leaf revisions
+    /// other than the winner—as the server reports them in the `_conflicts`
+    /// field.
+    pub fn conflicts(&self) -> Vec<&Revision> {
+        self.leafs()
+            .into_iter()
+            .skip(1)
+            .filter(|&(_, deleted)| !deleted)
+            .map(|(rev, _)| rev)
+            .collect()
+    }
+
+    /// Returns the document's deleted conflicts—i.e., its deleted leaf
+    /// revisions other than the winner—as the server reports them in the
+    /// `_deleted_conflicts` field.
+    pub fn deleted_conflicts(&self) -> Vec<&Revision> {
+        self.leafs()
+            .into_iter()
+            .skip(1)
+            .filter(|&(_, deleted)| deleted)
+            .map(|(rev, _)| rev)
+            .collect()
+    }
+
+    /// Returns the known ancestry of a revision, newest first and starting with
+    /// the revision itself, or `None` if the tree does not contain the
+    /// revision.
+    pub fn path(&self, rev: &Revision) -> Option<Vec<&Revision>> {
+        let (mut rev, mut node) = self.nodes.get_key_value(rev)?;
+        let mut path = vec![rev];
+        while let Some(x) = node.parent.as_ref().and_then(|x| self.nodes.get_key_value(x)) {
+            rev = x.0;
+            node = x.1;
+            path.push(rev);
+        }
+        Some(path)
+    }
+
+    /// Stems the tree such that each leaf keeps at most `revs_limit`
+    /// revisions of history, including the leaf itself.
+    ///
+    /// A revision survives if it is within `revs_limit` revisions of any leaf.
+    /// A revision whose parent is discarded becomes a root. A `revs_limit` of
+    /// zero, which the server does not allow, is treated as one, so that
+    /// stemming never discards a leaf.
+    ///
+    pub fn stem(&mut self, revs_limit: u64) {
+
+        let revs_limit = revs_limit.max(1);
+
+        let keep = self.leafs()
+            .into_iter()
+            .flat_map(|(leaf, _)| {
+                self.path(leaf).unwrap().into_iter().take(
+                    revs_limit as usize,
+                )
+            })
+            .cloned()
+            .collect::<BTreeSet<_>>();
+
+        self.nodes.retain(|rev, _| keep.contains(rev));
+
+        for node in self.nodes.values_mut() {
+            let is_stemmed = match node.parent {
+                Some(ref x) => !keep.contains(x),
+                None => false,
+            };
+            if is_stemmed {
+                node.parent = None;
+            }
+        }
+    }
+
+    fn is_leaf(&self, rev: &Revision) -> bool {
+        !self.nodes.values().any(
+            |x| x.parent.as_ref() == Some(rev),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rev(s: &str) -> Revision {
+        Revision::parse(s).unwrap()
+    }
+
+    fn path(s: &[&str]) -> Vec<Revision> {
+        s.iter().map(|x| rev(x)).collect()
+    }
+
+    const R1: &str = "1-11111111111111111111111111111111";
+    const R2A: &str = "2-2aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const R2B: &str = "2-2bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const R3A: &str = "3-3aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const R4A: &str = "4-4aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    #[test]
+    fn merge_path_reports_outcome() {
+        let mut tree = RevisionTree::new();
+        assert_eq!(tree.merge_path(&path(&[R2A, R1]), false).unwrap(), MergeOutcome::NewLeaf);
+        assert_eq!(tree.merge_path(&path(&[R3A, R2A]), false).unwrap(), MergeOutcome::NewLeaf);
+        assert_eq!(tree.merge_path(&path(&[R2B, R1]), false).unwrap(), MergeOutcome::NewBranch);
+        assert_eq!(tree.merge_path(&path(&[R2A, R1]), false).unwrap(), MergeOutcome::InternalNode);
+        assert_eq!(
+            tree.merge_path(&path(&["1-99999999999999999999999999999999"]), false)
+                .unwrap(),
+            MergeOutcome::NewBranch
+        );
+        assert_eq!(tree.len(), 5);
+    }
+
+    #[test]
+    fn merge_path_rejects_bad_path() {
+        let mut tree = RevisionTree::new();
+        match tree.merge_path(&[], false) {
+            Err(Error::BadRevisionPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        match tree.merge_path(&path(&[R3A, R1]), false) {
+            Err(Error::BadRevisionPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn winner_and_conflicts() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R3A, R2A, R1]), true).unwrap();
+        tree.merge_path(&path(&[R2B, R1]), false).unwrap();
+        tree.merge_path(&path(&["2-2ccccccccccccccccccccccccccccccc", R1]), false)
+            .unwrap();
+
+        assert_eq!(tree.winner(), Some(&rev("2-2ccccccccccccccccccccccccccccccc")));
+        assert!(!tree.is_deleted());
+        assert_eq!(tree.conflicts(), vec![&rev(R2B)]);
+        assert_eq!(tree.deleted_conflicts(), vec![&rev(R3A)]);
+
+        tree.merge_path(&path(&[R4A, R3A]), true).unwrap();
+        assert_eq!(tree.deleted_conflicts(), vec![&rev(R4A)]);
+    }
+
+    #[test]
+    fn all_leafs_deleted() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R2A, R1]), true).unwrap();
+        tree.merge_path(&path(&[R2B, R1]), true).unwrap();
+        assert_eq!(tree.winner(), Some(&rev(R2B)));
+        assert!(tree.is_deleted());
+        assert!(tree.conflicts().is_empty());
+        assert_eq!(tree.deleted_conflicts(), vec![&rev(R2A)]);
+    }
+
+    #[test]
+    fn path_lists_ancestry() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R3A, R2A, R1]), false).unwrap();
+        tree.merge_path(&path(&[R2B, R1]), false).unwrap();
+        assert_eq!(
+            tree.path(&rev(R3A)),
+            Some(vec![&rev(R3A), &rev(R2A), &rev(R1)])
+        );
+        assert_eq!(tree.path(&rev(R4A)), None);
+    }
+
+    #[test]
+    fn stem_keeps_recent_history_of_each_leaf() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R4A, R3A, R2A, R1]), false).unwrap();
+        tree.merge_path(&path(&[R2B, R1]), false).unwrap();
+
+        tree.stem(2);
+        assert_eq!(tree.len(), 4);
+        assert!(!tree.contains(&rev(R2A)));
+        assert_eq!(tree.path(&rev(R4A)), Some(vec![&rev(R4A), &rev(R3A)]));
+        assert_eq!(tree.path(&rev(R2B)), Some(vec![&rev(R2B), &rev(R1)]));
+
+        tree.stem(1);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.winner(), Some(&rev(R4A)));
+        assert_eq!(tree.conflicts(), vec![&rev(R2B)]);
+    }
+
+    #[test]
+    fn stem_to_zero_keeps_leafs() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R3A, R2A, R1]), false).unwrap();
+        tree.merge_path(&path(&[R2B, R1]), false).unwrap();
+
+        tree.stem(0);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.winner(), Some(&rev(R3A)));
+        assert_eq!(tree.conflicts(), vec![&rev(R2B)]);
+    }
+
+    #[test]
+    fn merge_grafts_history_onto_stemmed_root() {
+        let mut tree = RevisionTree::new();
+        tree.merge_path(&path(&[R4A, R3A]), false).unwrap();
+        assert_eq!(
+            tree.merge_path(&path(&[R3A, R2A, R1]), false).unwrap(),
+            MergeOutcome::InternalNode
+        );
+        assert_eq!(
+            tree.path(&rev(R4A)),
+            Some(vec![&rev(R4A), &rev(R3A), &rev(R2A), &rev(R1)])
+        );
+        assert_eq!(tree.leafs(), vec![(&rev(R4A), false)]);
+    }
+}
@@ -0,0 +1,261 @@
+use {Error, Revision};
+use std::marker::PhantomData;
+
+/// `Revisions` contains a document's revision history as the CouchDB server
+/// formats it in the `_revisions` field.
+///
+/// # Summary
+///
+/// * `Revisions` implements `Deserialize` and `Serialize`.
+///
+/// * `Revisions` converts to and from a list of `Revision` values, ordered
+///   newest first.
+///
+/// # Remarks
+///
+/// The server includes the `_revisions` field when getting a document with
+/// the `revs=true` query parameter. The field comprises the sequence number of
+/// the newest revision (`start`) and the digests of the revision and its
+/// ancestors (`ids`), newest first. Thus the *n*th digest, counting from zero,
+/// belongs to the revision having sequence number `start - n`.
+///
+/// An application must send the `_revisions` field when writing a document
+/// with the `new_edits=false` query parameter—e.g., when replicating.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"{
+///     "start": 3,
+///     "ids": [
+///         "7051cbe5c8faecd085a3fa619e6e6337",
+///         "eec205a9d413992850a6e32678485900",
+///         "967a00dff5e02add41819138abb3284d"
+///     ]
+/// }"#;
+///
+/// let revisions: couchdb::Revisions = serde_json::from_slice(body).unwrap();
+/// let revs = revisions.to_revisions().unwrap();
+///
+/// assert_eq!(revs[0].to_string(), "3-7051cbe5c8faecd085a3fa619e6e6337");
+/// assert_eq!(revs[2].to_string(), "1-967a00dff5e02add41819138abb3284d");
+///
+/// assert_eq!(couchdb::Revisions::from_revisions(&revs).unwrap(), revisions);
+/// ```
+///
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Revisions {
+    start: u64,
+    ids: Vec<String>,
+}
+
+/// `RevisionInfo` contains one entry of the `_revs_info` field of a document.
+///
+/// # Summary
+///
+/// * `RevisionInfo` has public members instead of accessor methods because
+///   there are no invariants restricting the data.
+///
+/// * `RevisionInfo` implements `Deserialize`.
+///
+/// # Remarks
+///
+/// The server includes the `_revs_info` field when getting a document with the
+/// `revs_info=true` query parameter. The field lists the document's revision
+/// and its ancestors, newest first, each with its status.
+///
+/// # Example
+///
+/// ```
+/// extern crate couchdb;
+/// extern crate serde_json;
+///
+/// let body = br#"[
+///     {"rev": "2-7051cbe5c8faecd085a3fa619e6e6337", "status": "available"},
+///     {"rev": "1-967a00dff5e02add41819138abb3284d", "status": "missing"}
+/// ]"#;
+///
+/// let revs_info: Vec<couchdb::RevisionInfo> = serde_json::from_slice(body).unwrap();
+///
+/// assert_eq!(revs_info[0].status, couchdb::RevisionStatus::Available);
+/// assert_eq!(revs_info[1].status, couchdb::RevisionStatus::Missing);
+/// ```
+///
+/// # Compatibility
+///
+/// `RevisionInfo` contains a dummy private member in order to prevent
+/// applications from directly constructing a `RevisionInfo` instance. This
+/// allows new fields to be added to `RevisionInfo` in future releases without
+/// it being a breaking change.
+///
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq)]
+pub struct RevisionInfo {
+    pub rev: Revision,
+    pub status: RevisionStatus,
+
+    #[serde(default = "PhantomData::default")]
+    _private_guard: PhantomData<()>,
+}
+
+/// `RevisionStatus` is the status of a revision in the `_revs_info` field of a
+/// document.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevisionStatus {
+    /// The server stores the revision's content.
+    Available,
+
+    /// The server no longer stores the revision's content—e.g., owing to
+    /// compaction.
+    Missing,
+
+    /// The revision is a deletion.
+    Deleted,
+}
+
+impl Revisions {
+    /// Returns the sequence number of the newest revision.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Borrows the revision digests, newest first.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    /// Expands the revision history into a list of revisions, newest first.
+    ///
+    /// Returns an error if there are more digests than the `start` sequence
+    /// number allows, or if a digest is invalid.
+    ///
+    pub fn to_revisions(&self) -> Result<Vec<Revision>, Error> {
+
+        if self.ids.len() as u64 > self.start {
+            return Err(Error::bad_revision_path(
+                "The revision history has more ids than its start allows",
+            ));
+        }
+
+        self.ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                Revision::parse_tolerant(&format!("{}-{}", self.start - i as u64, id))
+            })
+            .collect()
+    }
+
+    /// Constructs a revision history from a list of revisions, newest first.
+    ///
+    /// Returns an error if the list is empty or if its sequence numbers do not
+    /// decrease one by one.
+    ///
+    pub fn from_revisions(revs: &[Revision]) -> Result<Self, Error> {
+
+        let start = match revs.first() {
+            None => return Err(Error::bad_revision_path("The path is empty")),
+            Some(x) => x.sequence_number(),
+        };
+
+        for (i, rev) in revs.iter().enumerate() {
+            if rev.sequence_number() != start - i as u64 {
+                return Err(Error::bad_revision_path(
+                    "The path's sequence numbers are not consecutive",
+                ));
+            }
+        }
+
+        Ok(Revisions {
+            start: start,
+            ids: revs.iter().map(|x| x.digest_string()).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn revisions_expand_to_revisions() {
+        let source = r#"{"start":3,"ids":["7051cbe5c8faecd085a3fa619e6e6337","967a00dff5e02add41819138abb3284d"]}"#;
+        let revisions: Revisions = serde_json::from_str(source).unwrap();
+        assert_eq!(revisions.start(), 3);
+        assert_eq!(
+            revisions.to_revisions().unwrap(),
+            vec![
+                Revision::parse("3-7051cbe5c8faecd085a3fa619e6e6337").unwrap(),
+                Revision::parse("2-967a00dff5e02add41819138abb3284d").unwrap(),
+            ]
+        );
+        assert_eq!(serde_json::to_string(&revisions).unwrap(), source);
+    }
+
+    #[test]
+    fn revisions_reject_bad_history() {
+        let revisions: Revisions = serde_json::from_str(
+            r#"{"start":1,"ids":["7051cbe5c8faecd085a3fa619e6e6337","967a00dff5e02add41819138abb3284d"]}"#,
+        ).unwrap();
+        match revisions.to_revisions() {
+            Err(Error::BadRevisionPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+
+        let revisions: Revisions = serde_json::from_str(r#"{"start":1,"ids":[""]}"#).unwrap();
+        match revisions.to_revisions() {
+            Err(Error::BadRevision) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn revisions_from_revisions() {
+        let revs = vec![
+            Revision::parse("2-7051cbe5c8faecd085a3fa619e6e6337").unwrap(),
+            Revision::parse("1-967a00dff5e02add41819138abb3284d").unwrap(),
+        ];
+        let revisions = Revisions::from_revisions(&revs).unwrap();
+        assert_eq!(revisions.start(), 2);
+        assert_eq!(
+            revisions.ids(),
+            &[
+                String::from("7051cbe5c8faecd085a3fa619e6e6337"),
+                String::from("967a00dff5e02add41819138abb3284d"),
+            ]
+        );
+        assert_eq!(revisions.to_revisions().unwrap(), revs);
+
+        match Revisions::from_revisions(&[]) {
+            Err(Error::BadRevisionPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+        match Revisions::from_revisions(&[revs[1].clone(), revs[0].clone()]) {
+            Err(Error::BadRevisionPath { .. }) => {}
+            x => panic!("Got unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn revision_info_deserializes_ok() {
+        let source = r#"[
+            {"rev": "3-7051cbe5c8faecd085a3fa619e6e6337", "status": "deleted"},
+            {"rev": "2-eec205a9d413992850a6e32678485900", "status": "available"},
+            {"rev": "1-967a00dff5e02add41819138abb3284d", "status": "missing"}
+        ]"#;
+        let got: Vec<RevisionInfo> = serde_json::from_str(source).unwrap();
+        assert_eq!(
+            got.iter().map(|x| x.status).collect::<Vec<_>>(),
+            vec![
+                RevisionStatus::Deleted,
+                RevisionStatus::Available,
+                RevisionStatus::Missing,
+            ]
+        );
+        assert_eq!(got[1].rev, Revision::parse("2-eec205a9d413992850a6e32678485900").unwrap());
+    }
+}
@@ -29,6 +29,11 @@ pub struct Root {
     pub vendor: Vendor,
     pub version: Version,
 
+    /// The optional features the server has enabled. CouchDB 2.1 and later
+    /// send this field.
+    #[serde(default)]
+    pub features: Vec<String>,
+
     #[serde(default = "PhantomData::default")]
     _private_guard: PhantomData<()>,
 }
@@ -56,6 +61,9 @@ pub struct Root {
 #[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq)]
 pub struct Vendor {
     pub name: String,
+
+    /// The vendor's version. CouchDB 2.x and later do not send this field.
+    #[serde(default)]
     pub version: Version,
 
     #[serde(default = "PhantomData::default")]
@@ -167,10 +175,30 @@ mod tests {
                 _private_guard: PhantomData,
             },
             version: Version::from("1.6.1"),
+            features: Vec::new(),
             _private_guard: PhantomData,
         };
 
         let got: Root = serde_json::from_str(source).unwrap();
         assert_eq!(got, expected);
     }
+
+    #[test]
+    fn root_deserializes_couchdb_2_ok() {
+
+        let source = r#"{
+            "couchdb": "Welcome",
+            "version": "2.1.1",
+            "features": ["scheduler"],
+            "vendor": {
+                "name": "The Apache Software Foundation"
+            },
+            "uuid": "0762dcce5f0d7f6f79157f852186f149"
+        }"#;
+
+        let got: Root = serde_json::from_str(source).unwrap();
+        assert_eq!(got.version, Version::from("2.1.1"));
+        assert_eq!(got.features, vec![String::from("scheduler")]);
+        assert_eq!(got.vendor.version, Version::default());
+    }
 }
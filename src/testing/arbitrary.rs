@@ -0,0 +1,86 @@
+//! The `arbitrary` module provides [proptest](https://crates.io/crates/proptest)
+//! strategies for generating `couchdb` values.
+//!
+//! # Summary
+//!
+//! * The `arbitrary` module is available only when the `proptest` feature is
+//!   enabled.
+//!
+//! * The `couchdb` crate implements proptest's `Arbitrary` trait for its name,
+//!   id, path, revision, and attachment types. Hence, an application may
+//!   generate these values via `proptest::prelude::any`.
+//!
+//! * The `arbitrary` module additionally provides strategies that generate
+//!   specific kinds of values, such as document ids for design documents only.
+//!
+//! # Remarks
+//!
+//! Generated values are valid by construction. For example, every generated
+//! path can be parsed back from its string form, every generated database name
+//! satisfies CouchDB's naming rules, and every generated normal document id
+//! lacks a leading underscore.
+//!
+//! # Example
+//!
+//! ```rust
+//! extern crate couchdb;
+//! #[macro_use]
+//! extern crate proptest;
+//!
+//! use proptest::prelude::*;
+//! use proptest::test_runner::TestRunner;
+//!
+//! # fn main() {
+//! let mut runner = TestRunner::default();
+//!
+//! runner
+//!     .run(&any::<couchdb::DocumentPath>(), |path| {
+//!         let s = path.to_string();
+//!         prop_assert_eq!(couchdb::DocumentPath::parse(&s).unwrap(), path);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use {DesignDocumentName, DocumentId, LocalDocumentName, NormalDocumentName};
+use proptest::prelude::*;
+use proptest::strategy::BoxedStrategy;
+
+/// Returns a strategy that generates ids of normal documents—i.e., documents
+/// that are neither design documents nor local documents.
+pub fn normal_document_id() -> BoxedStrategy<DocumentId> {
+    any::<NormalDocumentName>().prop_map(DocumentId::from).boxed()
+}
+
+/// Returns a strategy that generates ids of design documents.
+pub fn design_document_id() -> BoxedStrategy<DocumentId> {
+    any::<DesignDocumentName>().prop_map(DocumentId::from).boxed()
+}
+
+/// Returns a strategy that generates ids of local documents.
+pub fn local_document_id() -> BoxedStrategy<DocumentId> {
+    any::<LocalDocumentName>().prop_map(DocumentId::from).boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn normal_document_id_is_normal(x in normal_document_id()) {
+            prop_assert!(x.is_normal());
+        }
+
+        #[test]
+        fn design_document_id_is_design(x in design_document_id()) {
+            prop_assert!(x.is_design());
+        }
+
+        #[test]
+        fn local_document_id_is_local(x in local_document_id()) {
+            prop_assert!(x.is_local());
+        }
+    }
+}
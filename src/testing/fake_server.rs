@@ -37,6 +37,12 @@ impl Drop for AutoKillProcess {
 /// `FakeServer` instance. When the instance drops, the server shuts down and
 /// all of its data are deleted.
 ///
+/// An application that needs to test its HTTPS configuration may instead spawn
+/// the server via `FakeServer::with_tls`, which additionally enables CouchDB's
+/// SSL service using a throwaway certificate authority. The application can
+/// trust exactly that server by adding the CA certificate (obtained via the
+/// `FakeServer::ca_certificate` method) to its HTTP client's root store.
+///
 /// # Example
 ///
 /// ```rust
@@ -73,47 +79,132 @@ pub struct FakeServer {
     _process: AutoKillProcess,
     _tmp_root: tempdir::TempDir,
     url: String,
+    tls: Option<Tls>,
+}
+
+// HTTPS-related state for a FakeServer spawned with TLS enabled.
+struct Tls {
+    url: String,
+    ca_certificate: Vec<u8>,
 }
 
+// File names, relative to the server's temporary directory, of the generated
+// certificate and key files.
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key";
+const SERVER_CERT_FILE: &str = "server.pem";
+const SERVER_CSR_FILE: &str = "server.csr";
+const SERVER_EXT_FILE: &str = "server.ext";
+const SERVER_KEY_FILE: &str = "server.key";
+
 impl FakeServer {
     /// Spawns a CouchDB server process for testing.
     pub fn new() -> Result<FakeServer, Error> {
+        FakeServer::spawn(false)
+    }
+
+    /// Spawns a CouchDB server process for testing, with the server's HTTPS
+    /// service enabled.
+    ///
+    /// The server uses a certificate signed by a newly generated, throwaway
+    /// certificate authority, which is valid for `localhost` and `127.0.0.1`.
+    /// The certificate and key files persist in the server's temporary
+    /// directory and are deleted when the `FakeServer` instance drops.
+    ///
+    /// Certificate generation requires the `openssl` command-line tool, version
+    /// 1.1.1 or later, to be installed on the local machine. Earlier versions
+    /// lack the `-addext` option of `openssl req`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// extern crate couchdb;
+    ///
+    /// let server = match couchdb::testing::FakeServer::with_tls() {
+    ///     Ok(x) => x,
+    ///     Err(e) => {
+    ///         println!("Are CouchDB and OpenSSL installed locally? ({})", e);
+    ///         return;
+    ///     }
+    /// };
+    ///
+    /// assert!(server.https_url().unwrap().starts_with("https://"));
+    ///
+    /// let ca_cert = server.ca_certificate().unwrap();
+    /// assert!(ca_cert.starts_with(b"-----BEGIN CERTIFICATE-----"));
+    /// ```
+    ///
+    pub fn with_tls() -> Result<FakeServer, Error> {
+        FakeServer::spawn(true)
+    }
 
-        let tmp_root = try!(tempdir::TempDir::new("couchdb_test").map_err(|e| {
+    fn spawn(enable_tls: bool) -> Result<FakeServer, Error> {
+
+        let tmp_root = tempdir::TempDir::new("couchdb_test").map_err(|e| {
             Error::from((
                 "Failed to create temporary directory for CouchDB server",
                 e,
             ))
-        }));
+        })?;
+
+        let ca_certificate = if enable_tls {
+            Some(generate_certificates(tmp_root.path())?)
+        } else {
+            None
+        };
 
         {
             use std::io::Write;
             let path = tmp_root.path().join("couchdb.conf");
-            let mut f = try!(std::fs::File::create(&path).map_err(|e| {
+            let mut f = std::fs::File::create(&path).map_err(|e| {
                 Error::from(("Failed to open CouchDB server configuration file", e))
-            }));
-            try!(
-                f.write_all(
-                    b"[couchdb]\n\
-                database_dir = var\n\
-                uri_file = couchdb.uri\n\
-                view_index_dir = view\n\
-                \n\
-                [log]\n\
-                file = couchdb.log\n\
+            })?;
+            f.write_all(
+                b"[couchdb]\n\
+            database_dir = var\n\
+            uri_file = couchdb.uri\n\
+            view_index_dir = view\n\
+            \n\
+            [log]\n\
+            file = couchdb.log\n\
+            \n\
+            [httpd]\n\
+            port = 0\n\
+            ",
+            ).map_err(|e| {
+                Error::from(("Failed to write CouchDB server configuration file", e))
+            })?;
+
+            if enable_tls {
+
+                // The `[daemons]` entry enables HTTPS for CouchDB 1.x, whereas
+                // the `enable` option enables it for CouchDB 2.x.
+
+                write!(
+                    f,
+                    "\n\
+                [daemons]\n\
+                httpsd = {{couch_httpd, start_link, [https]}}\n\
                 \n\
-                [httpd]\n\
+                [ssl]\n\
+                enable = true\n\
                 port = 0\n\
+                cacert_file = {}\n\
+                cert_file = {}\n\
+                key_file = {}\n\
                 ",
+                    tmp_root.path().join(CA_CERT_FILE).display(),
+                    tmp_root.path().join(SERVER_CERT_FILE).display(),
+                    tmp_root.path().join(SERVER_KEY_FILE).display()
                 ).map_err(|e| {
-                        Error::from(("Failed to write CouchDB server configuration file", e))
-                    })
-            );
+                    Error::from(("Failed to write CouchDB server configuration file", e))
+                })?;
+            }
         }
 
-        let child = try!(new_test_server_command(&tmp_root).spawn().map_err(|e| {
+        let child = new_test_server_command(&tmp_root).spawn().map_err(|e| {
             Error::from(("Failed to spawn CouchDB server process", e))
-        }));
+        })?;
         let mut process = AutoKillProcess(child);
 
         let (tx, rx) = std::sync::mpsc::channel();
@@ -124,25 +215,37 @@ impl FakeServer {
             process_out = std::io::BufReader::new(stdout);
         }
 
+        // The server reports one URL per HTTP service: first HTTP, then HTTPS.
+        let num_urls = if enable_tls { 2 } else { 1 };
+
         let t = std::thread::spawn(move || {
 
             let re = regex::Regex::new(r"Apache CouchDB has started on (http.*)").unwrap();
             let mut line = String::new();
+            let mut remaining = num_urls;
 
-            loop {
+            while remaining > 0 {
                 use std::io::BufRead;
                 line.clear();
-                process_out.read_line(&mut line).unwrap();
+
+                // Stop if the server exits without reporting all its URLs, so
+                // that `tx` drops and the receiver fails instead of blocking.
+                match process_out.read_line(&mut line) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+
                 let line = line.trim_right();
                 match re.captures(line) {
                     None => (),
                     Some(caps) => {
                         tx.send(caps.get(1).unwrap().as_str().to_owned()).unwrap();
 
-                        // TODO: Instead of breaking out of the loop, continue
-                        // looking for URL updates due to `POST /_restart`.
+                        // TODO: Instead of stopping after the last URL,
+                        // continue looking for URL updates due to `POST
+                        // /_restart`.
 
-                        break;
+                        remaining -= 1;
                     }
                 }
             }
@@ -158,19 +261,51 @@ impl FakeServer {
             }
         });
 
-        // Wait for the CouchDB server to start its HTTP service.
-        let url = try!(rx.recv().map_err(|e| {
-            t.join().unwrap_err();
+        // Wait for the CouchDB server to start its HTTP service(s).
+        let mut urls = Vec::new();
+        for _ in 0..num_urls {
+            match rx.recv() {
+                Ok(url) => urls.push(url),
+                Err(e) => {
+                    t.join().unwrap();
+                    return Err(Error::from((
+                        "Failed to obtain URL from CouchDB server",
+                        std::io::Error::other(e),
+                    )));
+                }
+            }
+        }
+
+        let (https_urls, http_urls): (Vec<_>, Vec<_>) = urls.into_iter().partition(|x| x.starts_with("https:"));
+
+        let url = http_urls.into_iter().next().ok_or_else(|| {
             Error::from((
-                "Failed to obtain URL from CouchDB server",
-                std::io::Error::new(std::io::ErrorKind::Other, e),
+                "Failed to obtain HTTP URL from CouchDB server",
+                std::io::Error::other("server reported no HTTP URL"),
             ))
-        }));
+        })?;
+
+        let tls = match ca_certificate {
+            None => None,
+            Some(ca_certificate) => {
+                let url = https_urls.into_iter().next().ok_or_else(|| {
+                    Error::from((
+                        "Failed to obtain HTTPS URL from CouchDB server",
+                        std::io::Error::other("server reported no HTTPS URL"),
+                    ))
+                })?;
+                Some(Tls {
+                    url: url,
+                    ca_certificate: ca_certificate,
+                })
+            }
+        };
 
         Ok(FakeServer {
             _process: process,
             _tmp_root: tmp_root,
             url: url,
+            tls: tls,
         })
     }
 
@@ -178,6 +313,131 @@ impl FakeServer {
     pub fn url(&self) -> &str {
         &self.url
     }
+
+    /// Returns the CouchDB server's HTTPS URL, if the server was spawned with
+    /// TLS enabled.
+    pub fn https_url(&self) -> Option<&str> {
+        self.tls.as_ref().map(|x| x.url.as_str())
+    }
+
+    /// Borrows the PEM-encoded certificate of the certificate authority that
+    /// signed the CouchDB server's certificate, if the server was spawned with
+    /// TLS enabled.
+    pub fn ca_certificate(&self) -> Option<&[u8]> {
+        self.tls.as_ref().map(|x| x.ca_certificate.as_slice())
+    }
+}
+
+// Generates a throwaway certificate authority and a server certificate signed
+// by that authority, writing all files into the given directory. Returns the
+// PEM-encoded CA certificate.
+fn generate_certificates(dir: &std::path::Path) -> Result<Vec<u8>, Error> {
+
+    {
+        use std::io::Write;
+        let path = dir.join(SERVER_EXT_FILE);
+        let mut f = std::fs::File::create(&path).map_err(|e| {
+            Error::from(("Failed to open server certificate extensions file", e))
+        })?;
+        f.write_all(b"subjectAltName = DNS:localhost, IP:127.0.0.1\n")
+            .map_err(|e| {
+                Error::from(("Failed to write server certificate extensions file", e))
+            })?;
+    }
+
+    run_openssl(
+        dir,
+        "Failed to generate CA certificate",
+        &[
+            "req",
+            "-x509",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-days",
+            "1",
+            "-subj",
+            "/CN=couchdb-rs test CA",
+            "-addext",
+            "basicConstraints=critical,CA:TRUE",
+            "-addext",
+            "keyUsage=critical,keyCertSign,cRLSign",
+            "-keyout",
+            CA_KEY_FILE,
+            "-out",
+            CA_CERT_FILE,
+        ],
+    )?;
+
+    run_openssl(
+        dir,
+        "Failed to generate server certificate request",
+        &[
+            "req",
+            "-newkey",
+            "rsa:2048",
+            "-nodes",
+            "-subj",
+            "/CN=localhost",
+            "-keyout",
+            SERVER_KEY_FILE,
+            "-out",
+            SERVER_CSR_FILE,
+        ],
+    )?;
+
+    run_openssl(
+        dir,
+        "Failed to sign server certificate",
+        &[
+            "x509",
+            "-req",
+            "-days",
+            "1",
+            "-in",
+            SERVER_CSR_FILE,
+            "-CA",
+            CA_CERT_FILE,
+            "-CAkey",
+            CA_KEY_FILE,
+            "-CAcreateserial",
+            "-extfile",
+            SERVER_EXT_FILE,
+            "-out",
+            SERVER_CERT_FILE,
+        ],
+    )?;
+
+    let mut ca_certificate = Vec::new();
+    {
+        use std::io::Read;
+        let mut f = std::fs::File::open(dir.join(CA_CERT_FILE)).map_err(|e| {
+            Error::from(("Failed to open CA certificate file", e))
+        })?;
+        f.read_to_end(&mut ca_certificate).map_err(|e| {
+            Error::from(("Failed to read CA certificate file", e))
+        })?;
+    }
+
+    Ok(ca_certificate)
+}
+
+fn run_openssl(dir: &std::path::Path, what: &'static str, args: &[&str]) -> Result<(), Error> {
+
+    let output = std::process::Command::new("openssl")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| Error::from((what, e)))?;
+
+    if !output.status.success() {
+        return Err(Error::from((
+            what,
+            std::io::Error::other(String::from_utf8_lossy(&output.stderr).into_owned()),
+        )));
+    }
+
+    Ok(())
 }
 
 #[cfg(any(windows))]
@@ -215,3 +475,54 @@ fn new_test_server_command(tmp_root: &tempdir::TempDir) -> std::process::Command
     c.stdout(std::process::Stdio::piped());
     c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_certificates_signs_server_certificate() {
+
+        let tmp_root = tempdir::TempDir::new("couchdb_test").unwrap();
+        let ca_certificate = match generate_certificates(tmp_root.path()) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Is OpenSSL 1.1.1 or later installed locally? ({})", e);
+                return;
+            }
+        };
+        assert!(ca_certificate.starts_with(b"-----BEGIN CERTIFICATE-----"));
+
+        run_openssl(
+            tmp_root.path(),
+            "Failed to verify server certificate",
+            &["verify", "-CAfile", CA_CERT_FILE, SERVER_CERT_FILE],
+        ).unwrap();
+    }
+
+    #[test]
+    fn with_tls_serves_https() {
+
+        let server = match FakeServer::with_tls() {
+            Ok(x) => x,
+            Err(e) => {
+                println!("Are CouchDB and OpenSSL installed locally? ({})", e);
+                return;
+            }
+        };
+
+        let url = server.https_url().unwrap();
+        assert!(url.starts_with("https://"));
+        let authority = url["https://".len()..].split('/').next().unwrap();
+
+        // Complete a TLS handshake, verifying the server's certificate against
+        // the throwaway certificate authority.
+        let output = std::process::Command::new("openssl")
+            .args(["s_client", "-connect", authority, "-CAfile", CA_CERT_FILE, "-verify_return_error"])
+            .current_dir(server._tmp_root.path())
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
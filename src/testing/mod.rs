@@ -1,6 +1,13 @@
 //! The `testing` module provides tools for applications to test their use of
 //! CouchDB.
+//!
+//! When the `proptest` feature is enabled, the `arbitrary` submodule provides
+//! [proptest](https://crates.io/crates/proptest) strategies for the `couchdb`
+//! crate's types.
 
 mod fake_server;
 
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+
 pub use self::fake_server::FakeServer;